@@ -0,0 +1,225 @@
+#!/usr/bin/env python3
+"""Perft for shogi and the smaller variants, written apart from the engine's move generation so
+that the two can check each other's counts.
+
+usage: scripts/perft.py <shogi|minishogi|judkins> <depth> [sfen]
+
+The board is a plain list of squares and attacks are found by looking outwards from the
+attacked square, unlike either of the engine's boards. It is slow, depth 4 from a 9x9 opening
+takes a minute or so.
+"""
+
+import sys
+
+# board size, promotion ranks and the starting position of every variant
+VARIANTS = {
+    "shogi": (9, 3, "lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL b - 1"),
+    "minishogi": (5, 1, "rbsgk/4p/5/P4/KGSBR b - 1"),
+    "judkins": (6, 2, "rbnsgk/5p/6/6/P5/KGSNBR b - 1"),
+}
+
+HAND_KINDS = "PLNSGBR"
+PROMOTABLE = "PLNSBR"
+
+# steps as (rows, columns) for sente, rows count down from gote's side so forward is -1
+ORTHOGONAL = [(-1, 0), (1, 0), (0, -1), (0, 1)]
+DIAGONAL = [(-1, -1), (-1, 1), (1, -1), (1, 1)]
+GOLD = [(-1, -1), (-1, 0), (-1, 1), (0, -1), (0, 1), (1, 0)]
+STEPS = {
+    "P": [(-1, 0)],
+    "L": [],
+    "N": [(-2, -1), (-2, 1)],
+    "S": [(-1, -1), (-1, 0), (-1, 1), (1, -1), (1, 1)],
+    "G": GOLD,
+    "K": ORTHOGONAL + DIAGONAL,
+    "B": [],
+    "R": [],
+    "+P": GOLD,
+    "+L": GOLD,
+    "+N": GOLD,
+    "+S": GOLD,
+    "+B": ORTHOGONAL,
+    "+R": DIAGONAL,
+}
+SLIDES = {"L": [(-1, 0)], "B": DIAGONAL, "R": ORTHOGONAL, "+B": DIAGONAL, "+R": ORTHOGONAL}
+
+
+class Position:
+    def __init__(self, size, zone, sfen):
+        self.size = size
+        self.zone = zone
+        fields = sfen.split()
+        # every square holds None or (side, kind)
+        self.squares = []
+        for row in fields[0].split("/"):
+            promoted = False
+            for c in row:
+                if c.isdigit():
+                    self.squares += [None] * int(c)
+                elif c == "+":
+                    promoted = True
+                else:
+                    kind = ("+" if promoted else "") + c.upper()
+                    self.squares.append((0 if c.isupper() else 1, kind))
+                    promoted = False
+        assert len(self.squares) == size * size, "wrong number of squares"
+        self.stm = 0 if fields[1] == "b" else 1
+        self.hands = [dict.fromkeys(HAND_KINDS, 0) for _ in range(2)]
+        count = ""
+        for c in fields[2] if fields[2] != "-" else "":
+            if c.isdigit():
+                count += c
+                continue
+            self.hands[0 if c.isupper() else 1][c.upper()] += int(count or 1)
+            count = ""
+
+    def forward(self, side, dr):
+        return dr if side == 0 else -dr
+
+    def on_board(self, r, c):
+        return 0 <= r < self.size and 0 <= c < self.size
+
+    def at(self, r, c):
+        return self.squares[r * self.size + c]
+
+    def king(self, side):
+        return self.squares.index((side, "K"))
+
+    def attacked(self, sq, by):
+        r, c = divmod(sq, self.size)
+        for kind, steps in STEPS.items():
+            for dr, dc in steps:
+                fr, fc = r - self.forward(by, dr), c - dc
+                if self.on_board(fr, fc) and self.at(fr, fc) == (by, kind):
+                    return True
+        for kind, slides in SLIDES.items():
+            for dr, dc in slides:
+                dr = self.forward(by, dr)
+                fr, fc = r - dr, c - dc
+                while self.on_board(fr, fc):
+                    piece = self.at(fr, fc)
+                    if piece is not None:
+                        if piece == (by, kind):
+                            return True
+                        break
+                    fr, fc = fr - dr, fc - dc
+        return False
+
+    # how far up the board a row is for a side, 0 being its own back row
+    def height(self, side, r):
+        return self.size - 1 - r if side == 0 else r
+
+    def dead(self, side, kind, r):
+        height = self.height(side, r)
+        if kind in ("P", "L"):
+            return height == self.size - 1
+        if kind == "N":
+            return height >= self.size - 2
+        return False
+
+    def in_zone(self, side, r):
+        return self.height(side, r) >= self.size - self.zone
+
+    def pseudo_moves(self):
+        side = self.stm
+        moves = []
+        for sq, piece in enumerate(self.squares):
+            if piece is None or piece[0] != side:
+                continue
+            kind = piece[1]
+            r, c = divmod(sq, self.size)
+            targets = []
+            for dr, dc in STEPS[kind]:
+                tr, tc = r + self.forward(side, dr), c + dc
+                if self.on_board(tr, tc):
+                    targets.append((tr, tc))
+            for dr, dc in SLIDES.get(kind, []):
+                dr = self.forward(side, dr)
+                tr, tc = r + dr, c + dc
+                while self.on_board(tr, tc):
+                    targets.append((tr, tc))
+                    if self.at(tr, tc) is not None:
+                        break
+                    tr, tc = tr + dr, tc + dc
+            for tr, tc in targets:
+                target = self.at(tr, tc)
+                if target is not None and target[0] == side:
+                    continue
+                to = tr * self.size + tc
+                if kind in PROMOTABLE and (self.in_zone(side, r) or self.in_zone(side, tr)):
+                    moves.append(("move", sq, to, True))
+                if not self.dead(side, kind, tr):
+                    moves.append(("move", sq, to, False))
+        for kind in HAND_KINDS:
+            if self.hands[side][kind] == 0:
+                continue
+            for to, piece in enumerate(self.squares):
+                if piece is not None:
+                    continue
+                r, c = divmod(to, self.size)
+                if self.dead(side, kind, r):
+                    continue
+                if kind == "P" and any(
+                    self.at(row, c) == (side, "P") for row in range(self.size)
+                ):
+                    continue
+                moves.append(("drop", kind, to, False))
+        return moves
+
+    def make(self, move):
+        child = Position.__new__(Position)
+        child.size, child.zone = self.size, self.zone
+        child.squares = list(self.squares)
+        child.hands = [dict(hand) for hand in self.hands]
+        side = self.stm
+        child.stm = 1 - side
+        kind, frm, to, promote = move
+        if kind == "drop":
+            child.hands[side][frm] -= 1
+            child.squares[to] = (side, frm)
+        else:
+            piece = child.squares[frm]
+            captured = child.squares[to]
+            if captured is not None:
+                child.hands[side][captured[1].lstrip("+")] += 1
+            child.squares[frm] = None
+            child.squares[to] = (side, "+" + piece[1] if promote else piece[1])
+        return child
+
+    def legal_moves(self):
+        side = self.stm
+        legal = []
+        for move in self.pseudo_moves():
+            child = self.make(move)
+            if child.attacked(child.king(side), 1 - side):
+                continue
+            # a pawn drop can't give mate
+            if (
+                move[0] == "drop"
+                and move[1] == "P"
+                and child.attacked(child.king(1 - side), side)
+                and not child.legal_moves()
+            ):
+                continue
+            legal.append(move)
+        return legal
+
+    def perft(self, depth):
+        moves = self.legal_moves()
+        if depth == 1:
+            return len(moves)
+        return sum(self.make(move).perft(depth - 1) for move in moves)
+
+
+def main():
+    if len(sys.argv) not in (3, 4) or sys.argv[1] not in VARIANTS:
+        print(__doc__.strip().split("\n\n")[1])
+        sys.exit(1)
+    size, zone, start = VARIANTS[sys.argv[1]]
+    position = Position(size, zone, sys.argv[3] if len(sys.argv) == 4 else start)
+    for depth in range(1, int(sys.argv[2]) + 1):
+        print(f"depth {depth}: {position.perft(depth)}", flush=True)
+
+
+if __name__ == "__main__":
+    main()
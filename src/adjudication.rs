@@ -0,0 +1,240 @@
+//! Ending decided games early from the scores the players report, for game loops that play many
+//! games like [`datagen`](crate::datagen) and [`tournament`](crate::tournament). Every score is
+//! from the point of view of the side that reported it.
+
+use std::str::FromStr;
+
+use crate::record::Termination;
+
+/// A side resigns once it has scored `-score` or worse for `moves` actions in a row while the
+/// other side scored `score` or better for as many of its own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResignAdjudication {
+    pub score: i32,
+    pub moves: usize,
+}
+
+/// The game is drawn once both sides have scored within `score` of zero for `moves` actions in a
+/// row each, counting only actions after the first `ply`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DrawAdjudication {
+    pub score: i32,
+    pub moves: usize,
+    pub ply: usize,
+}
+
+// comma separated numbers, as the rules are given on the command line
+fn parse_list<const N: usize>(s: &str, format: &str) -> Result<[i64; N], String> {
+    let values: Vec<i64> = s
+        .split(',')
+        .map(|value| value.trim().parse().ok().filter(|value| *value >= 0))
+        .collect::<Option<_>>()
+        .ok_or_else(|| format!("expected {format}, got {s}"))?;
+    values
+        .try_into()
+        .map_err(|_| format!("expected {format}, got {s}"))
+}
+
+impl FromStr for ResignAdjudication {
+    type Err = String;
+
+    /// `score,moves`, like `1000,3`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let [score, moves] = parse_list(s, "score,moves")?;
+        if moves == 0 {
+            return Err("resign adjudication needs at least 1 move".to_owned());
+        }
+        Ok(Self {
+            score: score.min(i32::MAX.into()) as i32,
+            moves: moves as usize,
+        })
+    }
+}
+
+impl FromStr for DrawAdjudication {
+    type Err = String;
+
+    /// `score,moves,ply`, like `10,8,80`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let [score, moves, ply] = parse_list(s, "score,moves,ply")?;
+        if moves == 0 {
+            return Err("draw adjudication needs at least 1 move".to_owned());
+        }
+        Ok(Self {
+            score: score.min(i32::MAX.into()) as i32,
+            moves: moves as usize,
+            ply: ply as usize,
+        })
+    }
+}
+
+/// Both rules are off by default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AdjudicationConfig {
+    pub resign: Option<ResignAdjudication>,
+    pub draw: Option<DrawAdjudication>,
+}
+
+/// Keeps track of how long each side's scores have met each rule over a game.
+#[derive(Debug, Clone, Default)]
+pub struct Adjudicator {
+    config: AdjudicationConfig,
+    losing: [usize; 2],
+    winning: [usize; 2],
+    drawn: [usize; 2],
+}
+
+fn side_name(side: usize) -> &'static str {
+    if side == 0 {
+        "sente"
+    } else {
+        "gote"
+    }
+}
+
+impl Adjudicator {
+    pub fn new(config: AdjudicationConfig) -> Self {
+        Self {
+            config,
+            ..Default::default()
+        }
+    }
+
+    /// Records the score `side` gave with its action, None if it didn't give one. `ply` is the
+    /// number of actions in the game including this one.
+    pub fn record(&mut self, side: u8, score: Option<i32>, ply: usize) {
+        let side = side as usize;
+        let streak = |count: &mut usize, met: bool| *count = if met { *count + 1 } else { 0 };
+        let resign = self.config.resign.map_or(i32::MAX, |resign| resign.score);
+        streak(
+            &mut self.losing[side],
+            score.is_some_and(|score| score <= -resign),
+        );
+        streak(
+            &mut self.winning[side],
+            score.is_some_and(|score| score >= resign),
+        );
+        let drawn = self.config.draw.is_some_and(|draw| {
+            ply > draw.ply && score.is_some_and(|score| score.abs() <= draw.score)
+        });
+        streak(&mut self.drawn[side], drawn);
+    }
+
+    /// Whether the game should end before the side to move plays, with a reason to record. Only
+    /// the side to move can be made to resign, so a side that is lost resigns on its next turn.
+    pub fn adjudicate(&self, stm: u8) -> Option<(Termination, String)> {
+        let stm = stm as usize;
+        if let Some(resign) = self.config.resign {
+            if self.losing[stm] >= resign.moves && self.winning[1 - stm] >= resign.moves {
+                let reason = format!(
+                    "resign adjudication: {} scored -{} or worse and {} {} or better for {} \
+                     moves",
+                    side_name(stm),
+                    resign.score,
+                    side_name(1 - stm),
+                    resign.score,
+                    resign.moves
+                );
+                return Some((Termination::Resign, reason));
+            }
+        }
+        if let Some(draw) = self.config.draw {
+            if self.drawn.iter().all(|&count| count >= draw.moves) {
+                let reason = format!(
+                    "draw adjudication: both sides scored within {} for {} moves after ply {}",
+                    draw.score, draw.moves, draw.ply
+                );
+                return Some((Termination::Draw, reason));
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AdjudicationConfig, Adjudicator, DrawAdjudication, ResignAdjudication};
+    use crate::record::Termination;
+
+    #[test]
+    fn resign_needs_both_sides() {
+        let config = AdjudicationConfig {
+            resign: Some(ResignAdjudication {
+                score: 1000,
+                moves: 2,
+            }),
+            draw: None,
+        };
+        let mut adjudicator = Adjudicator::new(config);
+        adjudicator.record(0, Some(1200), 1);
+        adjudicator.record(1, Some(-1100), 2);
+        adjudicator.record(0, Some(1500), 3);
+        // gote only agreed once so far
+        assert_eq!(adjudicator.adjudicate(1), None);
+        adjudicator.record(1, Some(-1300), 4);
+        // sente is winning, so it never resigns
+        assert_eq!(adjudicator.adjudicate(0), None);
+        adjudicator.record(0, Some(1400), 5);
+        let (termination, reason) = adjudicator.adjudicate(1).unwrap();
+        assert_eq!(termination, Termination::Resign);
+        assert!(reason.starts_with("resign adjudication: gote"));
+
+        // a missing score breaks the streak
+        adjudicator.record(1, None, 6);
+        assert_eq!(adjudicator.adjudicate(1), None);
+    }
+
+    #[test]
+    fn draw_waits_for_the_ply() {
+        let config = AdjudicationConfig {
+            resign: None,
+            draw: Some(DrawAdjudication {
+                score: 10,
+                moves: 2,
+                ply: 4,
+            }),
+        };
+        let mut adjudicator = Adjudicator::new(config);
+        for ply in 1..=4 {
+            adjudicator.record((ply % 2) as u8, Some(0), ply);
+        }
+        assert_eq!(adjudicator.adjudicate(0), None);
+        for ply in 5..=7 {
+            adjudicator.record((ply % 2) as u8, Some(-5), ply);
+        }
+        assert_eq!(adjudicator.adjudicate(0), None);
+        adjudicator.record(0, Some(8), 8);
+        assert_eq!(
+            adjudicator
+                .adjudicate(1)
+                .map(|(termination, _)| termination),
+            Some(Termination::Draw)
+        );
+        adjudicator.record(1, Some(50), 9);
+        assert_eq!(adjudicator.adjudicate(0), None);
+    }
+
+    #[test]
+    fn parses_rules() {
+        assert_eq!(
+            "1000,3".parse(),
+            Ok(ResignAdjudication {
+                score: 1000,
+                moves: 3
+            })
+        );
+        assert_eq!(
+            "10,8,80".parse(),
+            Ok(DrawAdjudication {
+                score: 10,
+                moves: 8,
+                ply: 80
+            })
+        );
+        assert!("1000".parse::<ResignAdjudication>().is_err());
+        assert!("10,8,-1".parse::<DrawAdjudication>().is_err());
+        // no moves would end every game before it starts
+        assert!("1000,0".parse::<ResignAdjudication>().is_err());
+        assert!("10,0,0".parse::<DrawAdjudication>().is_err());
+    }
+}
@@ -0,0 +1,28 @@
+use std::time::Instant;
+
+use ctenophore::{board::Board, perft::perft_internal};
+
+// fixed so that the node count can be used as a signature for each commit
+const BENCH_DEPTH: u8 = 3;
+
+const BENCH_FENS: [&str; 7] = [
+    "lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL b - 1",
+    "lnsgkgsnl/1r5b1/pppppp1pp/6p2/9/2P6/PP1PPPPPP/1B5R1/LNSGKGSNL b - 3",
+    "lnsgk1snl/1r4g2/p1pppp1pp/6p2/1p7/2P6/PP1PPPPPP/7R1/LNSGKGSNL b Bb 9",
+    "ln1g3nl/1r1sg1k2/p1pppp1pp/1p4p2/9/2P1P4/PPSP1PPPP/2G4R1/LN2KGSNL b Bbs 1",
+    "l6nl/5+P1gk/2np1S3/p1p4Pp/3P2Sp1/1PPb2P1P/P5GS1/R8/LN4bKL w RGgsn5p 1",
+    "8l/1l+R2P3/p2pBG1pp/kps1p4/Nn1P2G2/P1P1P2PP/1PS6/1KSG3+r1/LN2+p3L w Sbgn3p 124",
+    "ln3g2l/1r2g1sk1/1pp1ppn2/p2ps1ppp/7P1/P1PP1PP1P/1PS1P1N2/2GK1G3/LNS4RL b Bb 1",
+];
+
+pub fn bench() {
+    let mut nodes = 0;
+    let start = Instant::now();
+    for fen in BENCH_FENS {
+        let mut board = Board::default();
+        board.load_fen(fen);
+        nodes += perft_internal(&mut board, BENCH_DEPTH);
+    }
+    let elapsed = start.elapsed().as_secs_f64();
+    println!("{} nodes {} nps", nodes, (nodes as f64 / elapsed) as u64);
+}
@@ -10,8 +10,14 @@ use crate::{
         piece::{Piece, NUM_PIECE_TYPES},
         square::{Square, NUM_SQUARES},
     },
+    zobrist::ZOBRIST,
 };
 
+/// The even game starting position.
+pub const STARTPOS: &str = "lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL b - 1";
+
+/// Everything about a position that changes when an action is made, the [`Board`] keeps one of
+/// these per ply so that undoing an action is just popping the last one.
 #[derive(Debug, Clone, Copy)]
 pub struct Position {
     sides: [Bitboard; 2],
@@ -19,6 +25,7 @@ pub struct Position {
     mailbox: [Piece; NUM_SQUARES as usize],
     hands: [Hand; 2],
     checkers: Bitboard,
+    hash: u64,
 }
 
 impl Default for Position {
@@ -29,6 +36,7 @@ impl Default for Position {
             mailbox: [Piece::default(); NUM_SQUARES as usize],
             hands: [Hand::default(); 2],
             checkers: Bitboard::EMPTY,
+            hash: 0,
         }
     }
 }
@@ -39,6 +47,7 @@ impl Position {
         self.sides[piece.side() as usize] ^= bitboard_square;
         self.pieces[piece.piece().as_usize()] ^= bitboard_square;
         self.mailbox[sq.as_usize()] = piece;
+        self.hash ^= ZOBRIST.pieces[piece.as_usize()][sq.as_usize()];
     }
 
     pub fn remove_piece(&mut self, sq: Square, piece: Piece) {
@@ -46,6 +55,23 @@ impl Position {
         self.sides[piece.side() as usize] ^= bitboard_square;
         self.pieces[piece.piece().as_usize()] ^= bitboard_square;
         self.mailbox[sq.as_usize()] = Piece::NONE;
+        self.hash ^= ZOBRIST.pieces[piece.as_usize()][sq.as_usize()];
+    }
+
+    pub fn add_to_hand(&mut self, side: u8, piece: Piece) {
+        let piece = piece.unpromote().piece();
+        let count = self.hands[side as usize].num(piece) as usize;
+        let keys = &ZOBRIST.hands[side as usize][piece.as_usize()];
+        self.hash ^= keys[count] ^ keys[count + 1];
+        self.hands[side as usize].inc(piece);
+    }
+
+    pub fn remove_from_hand(&mut self, side: u8, piece: Piece) {
+        let piece = piece.unpromote().piece();
+        let count = self.hands[side as usize].num(piece) as usize;
+        let keys = &ZOBRIST.hands[side as usize][piece.as_usize()];
+        self.hash ^= keys[count] ^ keys[count - 1];
+        self.hands[side as usize].dec(piece);
     }
 
     pub fn move_piece(&mut self, from: Square, piece: Piece, to: Square, victim: Piece) {
@@ -72,6 +98,10 @@ impl Position {
     }
 }
 
+/// A position along with the history of positions that led to it.
+///
+/// Sides are numbered 0 for sente and 1 for gote, squares count from 9i (0) to 1a (80) along the
+/// ranks.
 #[derive(Debug, Clone)]
 pub struct Board {
     states: Vec<Position>,
@@ -99,6 +129,21 @@ impl Board {
         self.states.last_mut().expect("No current state")
     }
 
+    /// Creates a board from an SFEN string, panicking if it is malformed.
+    pub fn from_fen(fen: &str) -> Self {
+        let mut board = Self::default();
+        board.load_fen(fen);
+        board
+    }
+
+    /// Creates a board from an SFEN string, or says what is wrong with it. Use this for input
+    /// from outside, like a gui, where [`Board::from_fen`] would panic.
+    pub fn try_from_fen(fen: &str) -> Result<Self, String> {
+        check_fen(fen)?;
+        Ok(Self::from_fen(fen))
+    }
+
+    /// Prints the board, hands and SFEN to stdout.
     pub fn print_state(&self) {
         let state = self.current_state();
 
@@ -144,8 +189,11 @@ impl Board {
             state.hands[1].to_string().to_ascii_lowercase()
         );
         println!("ply count: {}", self.ply);
+        println!("sfen: {}", self.to_fen());
     }
 
+    /// Sets the current position from an SFEN string, panicking if it is malformed. The move
+    /// count is optional.
     pub fn load_fen(&mut self, fen: &str) {
         let mut state = Position::default();
 
@@ -345,84 +393,105 @@ impl Board {
         // third token: hand
         token = fen_segments.next().expect("no hand");
         if token != "-" {
-            let mut count = 1;
+            let mut count = 0;
             for c in token.chars() {
                 match c {
                     'p' => {
-                        state.hands[1].set(Piece::PAWN, count);
-                        count = 1;
+                        state.hands[1].set(Piece::PAWN, count.max(1));
+                        count = 0;
                     }
                     'P' => {
-                        state.hands[0].set(Piece::PAWN, count);
-                        count = 1;
+                        state.hands[0].set(Piece::PAWN, count.max(1));
+                        count = 0;
                     }
                     'l' => {
-                        state.hands[1].set(Piece::LANCE, count);
-                        count = 1;
+                        state.hands[1].set(Piece::LANCE, count.max(1));
+                        count = 0;
                     }
                     'L' => {
-                        state.hands[0].set(Piece::LANCE, count);
-                        count = 1;
+                        state.hands[0].set(Piece::LANCE, count.max(1));
+                        count = 0;
                     }
                     'n' => {
-                        state.hands[1].set(Piece::KNIGHT, count);
-                        count = 1;
+                        state.hands[1].set(Piece::KNIGHT, count.max(1));
+                        count = 0;
                     }
                     'N' => {
-                        state.hands[0].set(Piece::KNIGHT, count);
-                        count = 1;
+                        state.hands[0].set(Piece::KNIGHT, count.max(1));
+                        count = 0;
                     }
                     's' => {
-                        state.hands[1].set(Piece::SILVER, count);
-                        count = 1;
+                        state.hands[1].set(Piece::SILVER, count.max(1));
+                        count = 0;
                     }
                     'S' => {
-                        state.hands[0].set(Piece::SILVER, count);
-                        count = 1;
+                        state.hands[0].set(Piece::SILVER, count.max(1));
+                        count = 0;
                     }
                     'g' => {
-                        state.hands[1].set(Piece::GOLD, count);
-                        count = 1;
+                        state.hands[1].set(Piece::GOLD, count.max(1));
+                        count = 0;
                     }
                     'G' => {
-                        state.hands[0].set(Piece::GOLD, count);
-                        count = 1;
+                        state.hands[0].set(Piece::GOLD, count.max(1));
+                        count = 0;
                     }
                     'b' => {
-                        state.hands[1].set(Piece::BISHOP, count);
-                        count = 1;
+                        state.hands[1].set(Piece::BISHOP, count.max(1));
+                        count = 0;
                     }
                     'B' => {
-                        state.hands[0].set(Piece::BISHOP, count);
-                        count = 1;
+                        state.hands[0].set(Piece::BISHOP, count.max(1));
+                        count = 0;
                     }
                     'r' => {
-                        state.hands[1].set(Piece::ROOK, count);
-                        count = 1;
+                        state.hands[1].set(Piece::ROOK, count.max(1));
+                        count = 0;
                     }
                     'R' => {
-                        state.hands[0].set(Piece::ROOK, count);
-                        count = 1;
+                        state.hands[0].set(Piece::ROOK, count.max(1));
+                        count = 0;
                     }
-                    // sets the count to use for next time
+                    // builds up the count to use for next time, counts can be 2 digits
                     _ => {
-                        count = c
-                            .to_digit(10)
-                            .unwrap_or_else(|| panic!("invalid character in fen: {c}"))
+                        count = count * 10
+                            + c.to_digit(10)
+                                .unwrap_or_else(|| panic!("invalid character in fen: {c}"))
                     }
                 }
             }
         }
 
         // fourth token: move count (optional)
-        let token_option = fen_segments.next();
-        if token_option.is_some() {
-            self.ply = token_option.unwrap().parse().unwrap();
+        if let Some(token) = fen_segments.next() {
+            self.ply = token.parse().unwrap();
+        }
+
+        // the hands were set directly, so their part of the hash is added here
+        for side in 0..2 {
+            for (piece, count) in state.hands[side] {
+                // counts past 18 have no key, try_from_fen rejects them before they get here
+                let key = ZOBRIST.hands[side][piece.as_usize()]
+                    .get(count as usize)
+                    .unwrap_or_else(|| panic!("can't have {count} of {piece} in hand"));
+                state.hash ^= key;
+            }
+        }
+        if self.stm == 1 {
+            state.hash ^= ZOBRIST.stm;
         }
 
         self.states.push(state);
         self.update_checkers();
     }
+    /// The SFEN string of the current position, including the move count.
+    pub fn to_fen(&self) -> String {
+        let state = self.current_state();
+        fen_from_parts(&state.mailbox, self.stm, state.hands, self.ply)
+    }
+
+    /// Generates pseudo-legal actions, some of them may leave the king in check or be
+    /// uchifuzume. [`Board::perform_action`] and [`Board::is_legal`] filter those out.
     pub fn get_actions(&self) -> Actionlist {
         let state = self.current_state();
         let mut actions = Actionlist::default();
@@ -532,17 +601,46 @@ impl Board {
 
         actions
     }
+    /// Generates only the legal actions.
+    pub fn legal_actions(&mut self) -> Actionlist {
+        let mut actions = self.get_actions();
+        actions.retain(|action| self.is_legal(*action));
+        actions
+    }
+
     pub fn piece_on_square(&self, sq: Square) -> Piece {
         self.current_state().piece_on_square(sq)
     }
 
+    /// The side to move, 0 for sente and 1 for gote.
+    pub fn stm(&self) -> u8 {
+        self.stm
+    }
+
+    pub fn ply(&self) -> i16 {
+        self.ply
+    }
+
+    pub fn hand(&self, side: u8) -> Hand {
+        self.current_state().hands[side as usize]
+    }
+
+    /// The enemy pieces attacking a square, from the point of view of the side to move.
     pub fn get_attackers(&self, sq: Square) -> Bitboard {
+        self.attackers_with_occupancy(sq, self.current_state().occupied())
+    }
+
+    // same as get_attackers, but the sliders see the given occupancy instead of the real one
+    fn attackers_with_occupancy(&self, sq: Square, occ: Bitboard) -> Bitboard {
         let opps = 1 - self.stm;
-        let pawn_atk_bb = Bitboard::from_square(Square(
-            (sq.as_u16() as i32 + if self.stm == 0 { 9 } else { -9 }) as u8,
-        ));
+        // the square a pawn attacking sq would be on, if it is on the board at all
+        let pawn_sq = sq.as_u16() as i32 + if self.stm == 0 { 9 } else { -9 };
+        let pawn_atk_bb = if (0..81).contains(&pawn_sq) {
+            Bitboard::from_square(Square(pawn_sq as u8))
+        } else {
+            Bitboard::EMPTY
+        };
         let state = self.current_state();
-        let occ = state.occupied();
         let gold_movers = state.sided_piece(Piece::GOLD.raw(), opps)
             | state.sided_piece(Piece::PROMO_PAWN.raw(), opps)
             | state.sided_piece(Piece::PROMO_LANCE.raw(), opps)
@@ -565,14 +663,64 @@ impl Board {
             | (get_gold_attacks(sq, self.stm) & gold_movers)
     }
 
+    /// Checks a pseudo-legal action for legality without making it.
+    // pawn drops that give check still need to be made to find out if they are uchifuzume
+    pub fn is_legal(&mut self, action: Action) -> bool {
+        match self.is_legal_unmade(action) {
+            Some(legal) => legal,
+            None => {
+                let legal = self.perform_action(action);
+                if legal {
+                    self.undo_action();
+                }
+                legal
+            }
+        }
+    }
+
+    /// [`Board::is_legal`] for a board that can't be changed, None for the pawn drops that give
+    /// check and have to be made to be checked.
+    pub fn is_legal_unmade(&self, action: Action) -> Option<bool> {
+        let state = self.current_state();
+        let to = action.to();
+        let king_sq = self.king_sq();
+        let captured = Bitboard::from_square(to);
+
+        let mut occ = state.occupied() | captured;
+        if action.is_drop() {
+            if action.piece().piece() == Piece::PAWN {
+                let checked_sq = if self.stm == 0 {
+                    to.0 as i16 + 9
+                } else {
+                    to.0 as i16 - 9
+                };
+                if checked_sq == king_sq_of(state, 1 - self.stm).0 as i16 {
+                    return None;
+                }
+            }
+        } else {
+            let from = action.from();
+            occ ^= Bitboard::from_square(from);
+            if from == king_sq {
+                return Some((self.attackers_with_occupancy(to, occ) & !captured).is_empty());
+            }
+        }
+
+        // anything standing on the destination square gets captured, so it can't attack
+        Some((self.attackers_with_occupancy(king_sq, occ) & !captured).is_empty())
+    }
+
     pub fn in_check(&self) -> bool {
         !self.current_state().checkers.is_empty()
     }
 
     pub fn king_sq(&self) -> Square {
-        let state = self.current_state();
-        let our_king = state.sided_piece(Piece::KING.raw(), self.stm);
-        Square(our_king.lsb())
+        king_sq_of(self.current_state(), self.stm)
+    }
+
+    /// The zobrist hash of the current position, including hands and side to move.
+    pub fn hash(&self) -> u64 {
+        self.current_state().hash
     }
 
     pub fn update_checkers(&mut self) {
@@ -582,6 +730,8 @@ impl Board {
         state.checkers = king_atkers;
     }
 
+    /// Makes a pseudo-legal action. Returns false and leaves the board unchanged if the action
+    /// turns out to be illegal.
     pub fn perform_action(&mut self, action: Action) -> bool {
         self.states.push(*self.current_state());
         // just like in anura, not using self.current_state_mut() because of borrowing shenanigans
@@ -590,7 +740,7 @@ impl Board {
             let to = action.to();
             let piece = action.piece();
             state.add_piece(to, piece);
-            state.hands[self.stm as usize].dec(piece.unpromote());
+            state.remove_from_hand(self.stm, piece);
         } else {
             let from = action.from();
             let to = action.to();
@@ -599,7 +749,7 @@ impl Board {
             state.remove_piece(from, piece);
             if victim != Piece::NONE {
                 state.remove_piece(to, victim);
-                state.hands[self.stm as usize].inc(victim.unpromote())
+                state.add_to_hand(self.stm, victim);
             }
             if action.is_promo() {
                 state.add_piece(to, piece.promote());
@@ -607,22 +757,230 @@ impl Board {
                 state.add_piece(to, piece);
             }
         }
+        state.hash ^= ZOBRIST.stm;
 
         self.ply += 1;
         // legality check
         if !self.get_attackers(self.king_sq()).is_empty() {
             self.undo_action();
             self.stm = 1 - self.stm;
-            false
+            return false;
+        }
+        self.stm = 1 - self.stm;
+
+        // uchifuzume, checkmating with a pawn drop is illegal
+        if action.is_drop()
+            && action.piece().piece() == Piece::PAWN
+            && self.is_pawn_drop_mate(action.to())
+        {
+            self.undo_action();
+            return false;
+        }
+        true
+    }
+
+    // called with the side that is defending against the dropped pawn to move
+    fn is_pawn_drop_mate(&mut self, pawn_sq: Square) -> bool {
+        let king_sq = self.king_sq();
+        let checked_sq = if self.stm == 0 {
+            pawn_sq.0 as i16 - 9
         } else {
-            self.stm = 1 - self.stm;
-            true
+            pawn_sq.0 as i16 + 9
+        };
+        if checked_sq != king_sq.0 as i16 {
+            return false;
         }
+        !self.has_legal_action()
     }
 
+    /// Whether the side to move has any legal action, false means checkmate (or stalemate).
+    pub fn has_legal_action(&mut self) -> bool {
+        for action in &self.get_actions() {
+            if self.perform_action(*action) {
+                self.undo_action();
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Takes back the last action made by [`Board::perform_action`].
     pub fn undo_action(&mut self) {
         self.states.pop();
         self.ply -= 1;
         self.stm = 1 - self.stm;
     }
 }
+
+fn king_sq_of(state: &Position, side: u8) -> Square {
+    Square(state.sided_piece(Piece::KING.raw(), side).lsb())
+}
+
+// everything load_fen would panic on, plus anything that would leave the board unusable
+// every piece type but the king, with how many of it a game has
+const PIECE_LIMITS: [(char, u32); 7] = [
+    ('p', 18),
+    ('l', 4),
+    ('n', 4),
+    ('s', 4),
+    ('g', 4),
+    ('b', 2),
+    ('r', 2),
+];
+
+fn check_fen(fen: &str) -> Result<(), String> {
+    let segments: Vec<&str> = fen.split_ascii_whitespace().collect();
+    if !(3..=4).contains(&segments.len()) {
+        return Err(format!(
+            "expected 3 or 4 fields in sfen, found {}",
+            segments.len()
+        ));
+    }
+
+    let ranks: Vec<&str> = segments[0].split('/').collect();
+    if ranks.len() != 9 {
+        return Err(format!("expected 9 ranks in sfen, found {}", ranks.len()));
+    }
+    let mut kings = [0; 2];
+    // promoted pieces count as the piece they promoted from
+    let mut totals = [0; PIECE_LIMITS.len()];
+    let limit_index = |piece: char| {
+        PIECE_LIMITS
+            .iter()
+            .position(|&(limited, _)| limited == piece)
+    };
+    for rank in ranks {
+        let mut squares = 0;
+        let mut is_promoted = false;
+        for c in rank.chars() {
+            match c {
+                '+' if !is_promoted => is_promoted = true,
+                '1'..='9' if !is_promoted => squares += c as u32 - '0' as u32,
+                _ => {
+                    let piece = c.to_ascii_lowercase();
+                    if !"plnsbrgk".contains(piece) {
+                        return Err(format!("invalid character in sfen: {c}"));
+                    }
+                    if is_promoted && (piece == 'g' || piece == 'k') {
+                        return Err(format!("{c} can't be promoted"));
+                    }
+                    if piece == 'k' {
+                        kings[c.is_ascii_lowercase() as usize] += 1;
+                    }
+                    if let Some(index) = limit_index(piece) {
+                        totals[index] += 1;
+                    }
+                    is_promoted = false;
+                    squares += 1;
+                }
+            }
+        }
+        if is_promoted || squares != 9 {
+            return Err(format!("rank {rank} isn't 9 squares"));
+        }
+    }
+    if kings != [1, 1] {
+        return Err("each side needs exactly one king".to_owned());
+    }
+
+    if segments[1] != "b" && segments[1] != "w" {
+        return Err(format!("side to move must be b or w, not {}", segments[1]));
+    }
+
+    if segments[2] != "-" {
+        let mut count = 0u32;
+        for c in segments[2].chars() {
+            if let Some(digit) = c.to_digit(10) {
+                count = count.saturating_mul(10).saturating_add(digit);
+                continue;
+            }
+            let index = limit_index(c.to_ascii_lowercase())
+                .ok_or_else(|| format!("invalid character in hand: {c}"))?;
+            let (_, max) = PIECE_LIMITS[index];
+            if count > max {
+                return Err(format!("can't have {count} of {c} in hand"));
+            }
+            totals[index] += count.max(1);
+            count = 0;
+        }
+        if count != 0 {
+            return Err("hand ends with a count".to_owned());
+        }
+    }
+    for (&(piece, max), total) in PIECE_LIMITS.iter().zip(totals) {
+        if total > max {
+            return Err(format!(
+                "{total} of {piece} on the board and in hand, there are only {max}"
+            ));
+        }
+    }
+
+    if let Some(ply) = segments.get(3) {
+        ply.parse::<i16>()
+            .map_err(|_| format!("invalid move count: {ply}"))?;
+    }
+    Ok(())
+}
+
+// the sfen for a position given square by square, shared with the binary formats that have to
+// rebuild one
+pub(crate) fn fen_from_parts(mailbox: &[Piece; 81], stm: u8, hands: [Hand; 2], ply: i16) -> String {
+    let mut fen = String::new();
+
+    // position, from the top rank down
+    for rank in (0..9).rev() {
+        let mut empty = 0;
+        for file in 0..9 {
+            let piece = mailbox[Square::from_rf(rank, file).as_usize()];
+            if piece == Piece::NONE {
+                empty += 1;
+                continue;
+            }
+            if empty > 0 {
+                fen += &empty.to_string();
+                empty = 0;
+            }
+            fen += &piece.to_string();
+        }
+        if empty > 0 {
+            fen += &empty.to_string();
+        }
+        if rank != 0 {
+            fen.push('/');
+        }
+    }
+
+    // stm
+    fen += if stm == 0 { " b " } else { " w " };
+
+    // hands, in the usual order of rook, bishop, gold, silver, knight, lance, pawn
+    let order = [
+        Piece::ROOK,
+        Piece::BISHOP,
+        Piece::GOLD,
+        Piece::SILVER,
+        Piece::KNIGHT,
+        Piece::LANCE,
+        Piece::PAWN,
+    ];
+    let mut hand_fen = String::new();
+    for side in 0..2 {
+        for piece in order {
+            let count = hands[side as usize].num(piece);
+            if count > 1 {
+                hand_fen += &count.to_string();
+            }
+            if count > 0 {
+                hand_fen += &piece.as_stm(side).to_string();
+            }
+        }
+    }
+    if hand_fen.is_empty() {
+        hand_fen.push('-');
+    }
+    fen += &hand_fen;
+
+    // move count
+    fen += &format!(" {ply}");
+    fen
+}
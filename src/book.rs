@@ -0,0 +1,270 @@
+//! Opening books in the YaneuraOu standard `.db` text format. A book maps positions, as SFENs
+//! without the move count, to the actions worth playing there.
+//!
+//! ```text
+//! #YANEURAOU-DB2016 1.00
+//! sfen lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL b - 1
+//! 7g7f 3c3d 50 32 10
+//! 2g2f none 40 30 5
+//! ```
+//!
+//! Each action line is the action, the expected reply (or `none`), the evaluation, the depth it
+//! was searched to and how often it was played.
+//!
+//! ShogiGUI's binary SBK books aren't read or written. They can be converted to `.db` with
+//! ShogiGUI or YaneuraOu's book tools before loading them here.
+
+use std::collections::HashMap;
+
+use crate::{board::Board, record::RecordError, types::action::Action};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BookEntry {
+    /// In USI notation, only checked against the position when the book is probed.
+    pub action: String,
+    pub ponder: Option<String>,
+    pub value: i32,
+    pub depth: u32,
+    pub count: u64,
+}
+
+/// How to pick between the entries of a position.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BookSelection {
+    /// The highest value, ties go to the most played.
+    Best,
+    /// Randomly, in proportion to how often each was played.
+    #[default]
+    Weighted,
+    /// Any of them with the same chance.
+    Random,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Book {
+    positions: HashMap<String, Vec<BookEntry>>,
+}
+
+impl Book {
+    /// Reads a book in the `.db` format. SFENs are normalised, so books that write hands in a
+    /// different order still match.
+    pub fn parse_db(text: &str) -> Result<Self, RecordError> {
+        let mut book = Self::default();
+        let mut current: Option<String> = None;
+        for (i, line) in text.lines().enumerate() {
+            let number = i + 1;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with("//") {
+                continue;
+            }
+
+            if let Some(fen) = line.strip_prefix("sfen ") {
+                let board = Board::try_from_fen(fen)
+                    .map_err(|e| RecordError::new(number, format!("invalid sfen: {e}")))?;
+                let key = book_key(&board);
+                book.positions.entry(key.clone()).or_default();
+                current = Some(key);
+                continue;
+            }
+
+            let key = current
+                .as_ref()
+                .ok_or_else(|| RecordError::new(number, "action before any sfen"))?;
+            let entry = parse_entry(line)
+                .ok_or_else(|| RecordError::new(number, format!("invalid book entry: {line}")))?;
+            book.positions
+                .get_mut(key)
+                .expect("every key is inserted with its sfen line")
+                .push(entry);
+        }
+        Ok(book)
+    }
+
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+    /// The entries for the position, in the order the book lists them.
+    pub fn entries(&self, board: &Board) -> &[BookEntry] {
+        self.positions
+            .get(&book_key(board))
+            .map_or(&[], Vec::as_slice)
+    }
+
+    /// Picks an action for the position and the reply to expect, if the book has one. Entries
+    /// searched shallower than `min_depth` are skipped, as are any that aren't legal here.
+    /// `random` is any random number, used by the random selections.
+    pub fn probe(
+        &self,
+        board: &Board,
+        selection: BookSelection,
+        min_depth: u32,
+        random: u64,
+    ) -> Option<(Action, Option<Action>)> {
+        let candidates: Vec<(&BookEntry, Action)> = self
+            .entries(board)
+            .iter()
+            .filter(|entry| entry.depth >= min_depth)
+            .filter_map(|entry| Some((entry, Action::from_usi(&entry.action, board).ok()?)))
+            .collect();
+
+        let (entry, action) = match selection {
+            BookSelection::Best => candidates
+                .iter()
+                .max_by_key(|(entry, _)| (entry.value, entry.count))
+                .copied()?,
+            BookSelection::Random => candidates
+                .get((random % candidates.len().max(1) as u64) as usize)
+                .copied()?,
+            BookSelection::Weighted => {
+                // entries that were never played still get picked once in a while
+                let weight = |entry: &BookEntry| entry.count.max(1);
+                let total: u64 = candidates.iter().map(|(entry, _)| weight(entry)).sum();
+                let mut pick = random % total.max(1);
+                candidates
+                    .iter()
+                    .find(|(entry, _)| {
+                        let hit = pick < weight(entry);
+                        pick = pick.saturating_sub(weight(entry));
+                        hit
+                    })
+                    .copied()?
+            }
+        };
+
+        let ponder = entry.ponder.as_ref().and_then(|ponder| {
+            let mut board = board.clone();
+            board.perform_action(action);
+            Action::from_usi(ponder, &board).ok()
+        });
+        Some((action, ponder))
+    }
+}
+
+// books ignore the move count so that transpositions share entries
+fn book_key(board: &Board) -> String {
+    let fen = board.to_fen();
+    let (key, _ply) = fen
+        .rsplit_once(' ')
+        .expect("sfens always have a move count");
+    key.to_owned()
+}
+
+// the value, depth and count are optional, older books leave them out
+fn parse_entry(line: &str) -> Option<BookEntry> {
+    let mut tokens = line.split_ascii_whitespace();
+    let action = tokens.next()?.to_owned();
+    let ponder = match tokens.next() {
+        None | Some("none") => None,
+        Some(ponder) => Some(ponder.to_owned()),
+    };
+    let value = tokens.next().map_or(Some(0), |token| token.parse().ok())?;
+    let depth = tokens.next().map_or(Some(0), |token| token.parse().ok())?;
+    let count = tokens.next().map_or(Some(0), |token| token.parse().ok())?;
+    Some(BookEntry {
+        action,
+        ponder,
+        value,
+        depth,
+        count,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Book, BookSelection};
+    use crate::board::{Board, STARTPOS};
+
+    const BOOK: &str = "#YANEURAOU-DB2016 1.00
+sfen lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL b - 1
+7g7f 3c3d 30 32 3
+2g2f 8c8d 50 10 1
+5i5h none 0 40 0
+1a1b none 0 40 100
+sfen lnsgkgsnl/1r5b1/ppppppppp/9/9/2P6/PP1PPPPPP/1B5R1/LNSGKGSNL w - 2
+// a comment
+3c3d 2g2f 0 20 7
+";
+
+    fn probe(
+        book: &Book,
+        fen: &str,
+        selection: BookSelection,
+        min_depth: u32,
+        random: u64,
+    ) -> Option<(String, Option<String>)> {
+        book.probe(&Board::from_fen(fen), selection, min_depth, random)
+            .map(|(action, ponder)| (action.to_usi(), ponder.map(|ponder| ponder.to_usi())))
+    }
+
+    #[test]
+    fn parses_db() {
+        let book = Book::parse_db(BOOK).unwrap();
+        assert_eq!(book.len(), 2);
+        assert_eq!(book.entries(&Board::from_fen(STARTPOS)).len(), 4);
+        // the move count doesn't matter
+        let second = "lnsgkgsnl/1r5b1/ppppppppp/9/9/2P6/PP1PPPPPP/1B5R1/LNSGKGSNL w - 40";
+        assert_eq!(
+            probe(&book, second, BookSelection::Best, 0, 0),
+            Some(("3c3d".to_owned(), Some("2g2f".to_owned())))
+        );
+        assert_eq!(
+            probe(
+                &book,
+                "4k4/9/9/9/9/9/9/9/4K4 b - 1",
+                BookSelection::Best,
+                0,
+                0
+            ),
+            None
+        );
+
+        assert_eq!(Book::parse_db("7g7f").unwrap_err().line, 1);
+        assert_eq!(Book::parse_db("sfen 9/9 b - 1").unwrap_err().line, 1);
+        assert_eq!(
+            Book::parse_db(
+                "sfen lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL b - 1\n7g7f none x"
+            )
+            .unwrap_err()
+            .line,
+            2
+        );
+    }
+
+    #[test]
+    fn selection() {
+        let book = Book::parse_db(BOOK).unwrap();
+        // 1a1b isn't legal, so it is never picked however often it was played
+        assert_eq!(
+            probe(&book, STARTPOS, BookSelection::Best, 0, 0),
+            Some(("2g2f".to_owned(), Some("8c8d".to_owned())))
+        );
+        assert_eq!(
+            probe(&book, STARTPOS, BookSelection::Best, 30, 0),
+            Some(("7g7f".to_owned(), Some("3c3d".to_owned())))
+        );
+        assert_eq!(probe(&book, STARTPOS, BookSelection::Best, 50, 0), None);
+
+        // weights 3, 1 and 1
+        let weighted: Vec<String> = (0..5)
+            .map(|random| {
+                probe(&book, STARTPOS, BookSelection::Weighted, 0, random)
+                    .unwrap()
+                    .0
+            })
+            .collect();
+        assert_eq!(weighted, ["7g7f", "7g7f", "7g7f", "2g2f", "5i5h"]);
+        let random: Vec<String> = (0..3)
+            .map(|random| {
+                probe(&book, STARTPOS, BookSelection::Random, 0, random)
+                    .unwrap()
+                    .0
+            })
+            .collect();
+        assert_eq!(random, ["7g7f", "2g2f", "5i5h"]);
+    }
+}
@@ -0,0 +1,49 @@
+use std::{fs, sync::atomic::AtomicBool};
+
+use ctenophore::{
+    csa_client::CsaClient,
+    record::csa::write_csa,
+    search::{search, SearchLimits},
+};
+
+// usage: csa <host:port> <user> <password> [games]
+// plays games one after another and saves each of them as <game id>.csa
+pub fn run(args: &[String]) {
+    let [addr, user, password, rest @ ..] = args else {
+        println!("usage: csa <host:port> <user> <password> [games]");
+        return;
+    };
+    let games: usize = rest
+        .first()
+        .map_or(1, |games| games.parse().expect("Invalid Game Count"));
+    // floodgate listens on the standard csa port
+    let addr = if addr.contains(':') {
+        addr.clone()
+    } else {
+        format!("{addr}:4081")
+    };
+
+    let mut client = CsaClient::connect(&addr, user, password).expect("failed to log in");
+    println!("logged in to {addr} as {user}");
+    for _ in 0..games {
+        let report = client
+            .play_game(|board, time| {
+                let limits = SearchLimits {
+                    time: Some(time),
+                    ..Default::default()
+                };
+                let result = search(board, limits, &AtomicBool::new(false), |_| {});
+                result.best.map(|_| result)
+            })
+            .expect("connection to the server failed");
+        println!(
+            "game {} over after {} actions: {:?}",
+            report.id,
+            report.game.actions.len(),
+            report.outcome
+        );
+        let path = format!("{}.csa", report.id.replace(['/', '\\'], "_"));
+        fs::write(&path, write_csa(&report.game)).expect("failed to write the record");
+    }
+    client.logout().expect("failed to log out");
+}
@@ -0,0 +1,541 @@
+//! Converting positions and games between formats. Input is read a record at a time, so files of
+//! any size go through in constant memory, and a record that doesn't parse is reported and
+//! skipped without stopping the rest.
+//!
+//! Games become positions by taking every position of the main line, positions become games
+//! with no actions. Several CSA or KIF games in one file are separated by a line with only `/`,
+//! the same as CSA's own separator.
+
+use std::{
+    collections::HashSet,
+    fmt,
+    io::{self, BufRead, Read, Write},
+    str::FromStr,
+};
+
+use crate::{
+    board::{Board, STARTPOS},
+    packed_sfen::{from_move16, to_move16, PackedSfen, PackedSfenValue},
+    record::{
+        csa::{parse_csa, write_csa},
+        kif::{parse_kif, write_kif},
+        Game, GameResult, RecordError,
+    },
+    types::action::Action,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// A position per line, optionally after `sfen `, or `startpos`.
+    Sfen,
+    Csa,
+    Kif,
+    /// 32 byte [`PackedSfen`] positions.
+    PackedSfen,
+    /// 40 byte [`PackedSfenValue`] training records.
+    PackedSfenValue,
+}
+
+impl Format {
+    pub fn is_binary(self) -> bool {
+        matches!(self, Self::PackedSfen | Self::PackedSfenValue)
+    }
+
+    pub fn is_game(self) -> bool {
+        matches!(self, Self::Csa | Self::Kif)
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sfen" => Ok(Self::Sfen),
+            "csa" => Ok(Self::Csa),
+            "kif" => Ok(Self::Kif),
+            "psfen" => Ok(Self::PackedSfen),
+            "psv" => Ok(Self::PackedSfenValue),
+            _ => Err(format!(
+                "unknown format {s}, expected sfen, csa, kif, psfen or psv"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Sfen => "sfen",
+            Self::Csa => "csa",
+            Self::Kif => "kif",
+            Self::PackedSfen => "psfen",
+            Self::PackedSfenValue => "psv",
+        })
+    }
+}
+
+/// A position along with what the training formats know about it.
+#[derive(Debug, Clone)]
+pub struct Position {
+    pub board: Board,
+    /// The action played from the position.
+    pub action: Option<Action>,
+    /// From the side to move's point of view.
+    pub score: i16,
+    /// 1 if the side to move went on to win, 0 for a draw or an unknown result and -1 for a loss.
+    pub result: i8,
+}
+
+impl Position {
+    fn new(board: Board) -> Self {
+        Self {
+            board,
+            action: None,
+            score: 0,
+            result: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Record {
+    Position(Position),
+    Game(Game),
+}
+
+/// Reads records one at a time. Every item is the line (or for binary formats, the record number)
+/// the record started on along with the record, or why it couldn't be read. Errors from the
+/// input itself end the iteration.
+pub struct Reader<R> {
+    input: R,
+    format: Format,
+    line: usize,
+    done: bool,
+}
+
+impl<R: BufRead> Reader<R> {
+    pub fn new(input: R, format: Format) -> Self {
+        Self {
+            input,
+            format,
+            line: 0,
+            done: false,
+        }
+    }
+
+    // files from windows and japanese GUIs aren't always valid utf-8, those lines come through
+    // mangled and fail to parse rather than stopping everything
+    fn read_line(&mut self) -> io::Result<Option<String>> {
+        let mut bytes = Vec::new();
+        if self.input.read_until(b'\n', &mut bytes)? == 0 {
+            return Ok(None);
+        }
+        self.line += 1;
+        Ok(Some(String::from_utf8_lossy(&bytes).trim_end().to_owned()))
+    }
+
+    fn read_sfen(&mut self) -> io::Result<Option<(usize, Result<Record, RecordError>)>> {
+        loop {
+            let Some(line) = self.read_line()? else {
+                return Ok(None);
+            };
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fen = line.strip_prefix("sfen ").unwrap_or(line);
+            let fen = if fen == "startpos" { STARTPOS } else { fen };
+            let record = Board::try_from_fen(fen)
+                .map(|board| Record::Position(Position::new(board)))
+                .map_err(|e| RecordError::new(self.line, format!("invalid sfen: {e}")));
+            return Ok(Some((self.line, record)));
+        }
+    }
+
+    fn read_game(&mut self) -> io::Result<Option<(usize, Result<Record, RecordError>)>> {
+        let mut text = String::new();
+        let mut start = None;
+        while let Some(line) = self.read_line()? {
+            if line.trim() == "/" {
+                if start.is_some() {
+                    break;
+                }
+                continue;
+            }
+            if start.is_none() && line.trim().is_empty() {
+                continue;
+            }
+            start.get_or_insert(self.line);
+            text += &line;
+            text.push('\n');
+        }
+        let Some(start) = start else {
+            return Ok(None);
+        };
+
+        let game = if self.format == Format::Csa {
+            parse_csa(&text)
+        } else {
+            parse_kif(&text)
+        };
+        // errors count lines from the start of the game, not the file
+        let record = game
+            .map(Record::Game)
+            .map_err(|e| RecordError::new(start + e.line.max(1) - 1, e.message));
+        Ok(Some((start, record)))
+    }
+
+    fn read_packed(&mut self) -> io::Result<Option<(usize, Result<Record, RecordError>)>> {
+        let mut bytes = [0; PackedSfenValue::SIZE];
+        let size = if self.format == Format::PackedSfen {
+            32
+        } else {
+            PackedSfenValue::SIZE
+        };
+        let bytes = &mut bytes[..size];
+        let read = read_full(&mut self.input, bytes)?;
+        if read == 0 {
+            return Ok(None);
+        }
+        self.line += 1;
+        if read < size {
+            let message = format!("truncated record, {read} of {size} bytes");
+            return Ok(Some((self.line, Err(RecordError::new(self.line, message)))));
+        }
+
+        let invalid = || RecordError::new(self.line, "invalid packed position");
+        let record = if self.format == Format::PackedSfen {
+            let mut sfen = [0; 32];
+            sfen.copy_from_slice(bytes);
+            PackedSfen(sfen)
+                .to_board(1)
+                .map(|board| Record::Position(Position::new(board)))
+                .ok_or_else(invalid)
+        } else {
+            let value = PackedSfenValue::from_bytes((&*bytes).try_into().expect("sized above"));
+            value
+                .sfen
+                .to_board(value.game_ply.max(1))
+                .map(|board| {
+                    Record::Position(Position {
+                        // yaneuraou's MOVE_NONE and anything illegal come out as None
+                        action: from_move16(value.action, &board),
+                        board,
+                        score: value.score,
+                        result: value.game_result.signum(),
+                    })
+                })
+                .ok_or_else(invalid)
+        };
+        Ok(Some((self.line, record)))
+    }
+}
+
+// like read_exact, but a short read at the end of the input isn't an error
+fn read_full(input: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut read = 0;
+    while read < buf.len() {
+        match input.read(&mut buf[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(read)
+}
+
+impl<R: BufRead> Iterator for Reader<R> {
+    type Item = io::Result<(usize, Result<Record, RecordError>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let record = match self.format {
+            Format::Sfen => self.read_sfen(),
+            Format::Csa | Format::Kif => self.read_game(),
+            Format::PackedSfen | Format::PackedSfenValue => self.read_packed(),
+        };
+        if !matches!(record, Ok(Some(_))) {
+            self.done = true;
+        }
+        record.transpose()
+    }
+}
+
+/// Every position of the main line of a game, with the action played from it and the result.
+pub fn game_positions(game: &Game) -> Vec<Position> {
+    let mut board = Board::from_fen(&game.start_fen);
+    let result = |stm: u8| match game.result() {
+        Some(GameResult::SenteWin) => 1 - 2 * stm as i8,
+        Some(GameResult::GoteWin) => 2 * stm as i8 - 1,
+        Some(GameResult::Draw) | None => 0,
+    };
+    let mut positions = Vec::with_capacity(game.actions.len() + 1);
+    for &action in &game.actions {
+        positions.push(Position {
+            action: Some(action),
+            result: result(board.stm()),
+            ..Position::new(board.clone())
+        });
+        board.perform_action(action);
+    }
+    let stm = board.stm();
+    positions.push(Position {
+        result: result(stm),
+        ..Position::new(board)
+    });
+    positions
+}
+
+/// Writes records in a format, converting between games and positions as needed.
+pub struct Writer<W> {
+    output: W,
+    format: Format,
+    /// Hashes of the positions written so far, when deduplicating.
+    seen: Option<HashSet<u64>>,
+    games: usize,
+}
+
+/// What happened to the positions or games given to a [`Writer`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WriteCount {
+    pub written: usize,
+    pub duplicates: usize,
+    /// Positions the format can't hold, like handicaps in PackedSfen.
+    pub unsupported: usize,
+}
+
+impl<W: Write> Writer<W> {
+    /// Deduplication only applies to the position formats.
+    pub fn new(output: W, format: Format, dedup: bool) -> Self {
+        Self {
+            output,
+            format,
+            seen: dedup.then(HashSet::new),
+            games: 0,
+        }
+    }
+
+    pub fn write(&mut self, record: Record) -> io::Result<WriteCount> {
+        match (record, self.format.is_game()) {
+            (Record::Game(game), true) => self.write_game(&game),
+            (Record::Position(position), true) => {
+                self.write_game(&Game::new(&position.board.to_fen()))
+            }
+            (Record::Game(game), false) => {
+                let mut count = WriteCount::default();
+                for position in game_positions(&game) {
+                    let written = self.write_position(&position)?;
+                    count.written += written.written;
+                    count.duplicates += written.duplicates;
+                    count.unsupported += written.unsupported;
+                }
+                Ok(count)
+            }
+            (Record::Position(position), false) => self.write_position(&position),
+        }
+    }
+
+    fn write_game(&mut self, game: &Game) -> io::Result<WriteCount> {
+        if self.games > 0 {
+            self.output.write_all(b"/\n")?;
+        }
+        let text = if self.format == Format::Csa {
+            write_csa(game)
+        } else {
+            write_kif(game)
+        };
+        self.output.write_all(text.as_bytes())?;
+        self.games += 1;
+        Ok(WriteCount {
+            written: 1,
+            ..Default::default()
+        })
+    }
+
+    fn write_position(&mut self, position: &Position) -> io::Result<WriteCount> {
+        if let Some(seen) = &mut self.seen {
+            if !seen.insert(position.board.hash()) {
+                return Ok(WriteCount {
+                    duplicates: 1,
+                    ..Default::default()
+                });
+            }
+        }
+
+        let board = &position.board;
+        match self.format {
+            Format::Sfen => writeln!(self.output, "{}", board.to_fen())?,
+            Format::PackedSfen | Format::PackedSfenValue => {
+                let Some(sfen) = PackedSfen::from_board(board) else {
+                    return Ok(WriteCount {
+                        unsupported: 1,
+                        ..Default::default()
+                    });
+                };
+                if self.format == Format::PackedSfen {
+                    self.output.write_all(&sfen.0)?;
+                } else {
+                    let value = PackedSfenValue {
+                        sfen,
+                        score: position.score,
+                        action: position.action.map_or(0, to_move16),
+                        game_ply: board.ply().max(1) as u16,
+                        game_result: position.result,
+                    };
+                    self.output.write_all(&value.to_bytes())?;
+                }
+            }
+            Format::Csa | Format::Kif => unreachable!("games are written by write_game"),
+        }
+        Ok(WriteCount {
+            written: 1,
+            ..Default::default()
+        })
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Format, Reader, Record, Writer};
+    use crate::board::STARTPOS;
+
+    const CSA: &str = "V2.2
+PI
++
++7776FU
+-3334FU
++8822UM
+-3122GI
+%TORYO
+/
+PI
++
++7776FU
+-9999FU
+";
+
+    // reads everything, turning errors into their line numbers
+    fn read(input: &[u8], format: Format) -> (Vec<Record>, Vec<usize>) {
+        let mut records = Vec::new();
+        let mut errors = Vec::new();
+        for item in Reader::new(input, format) {
+            match item.unwrap() {
+                (_, Ok(record)) => records.push(record),
+                (_, Err(e)) => errors.push(e.line),
+            }
+        }
+        (records, errors)
+    }
+
+    fn convert(input: &[u8], from: Format, to: Format, dedup: bool) -> (Vec<u8>, usize) {
+        let mut output = Vec::new();
+        let mut writer = Writer::new(&mut output, to, dedup);
+        let mut duplicates = 0;
+        for record in read(input, from).0 {
+            duplicates += writer.write(record).unwrap().duplicates;
+        }
+        (output, duplicates)
+    }
+
+    #[test]
+    fn errors_have_line_numbers() {
+        let (records, errors) = read(CSA.as_bytes(), Format::Csa);
+        assert_eq!(records.len(), 1);
+        // the second game starts on line 10 and its illegal action is on line 13
+        assert_eq!(errors, [13]);
+
+        let sfens = format!("startpos\n\n# comment\nsfen {STARTPOS}\n9/9 b - 1\n");
+        let (records, errors) = read(sfens.as_bytes(), Format::Sfen);
+        assert_eq!(records.len(), 2);
+        assert_eq!(errors, [5]);
+
+        let (records, errors) = read(&[0; 70], Format::PackedSfen);
+        assert_eq!(records.len(), 0);
+        assert_eq!(errors, [1, 2, 3]);
+    }
+
+    #[test]
+    fn impossible_positions_are_errors() {
+        // no kings, then nineteen pawns in hand
+        let csa = format!(
+            "PI59OU51OU\n+\n/\nP+59OU\nP-51OU\nP+{}\n+\n+5958OU\n",
+            "00FU".repeat(19)
+        );
+        let (records, errors) = read(csa.as_bytes(), Format::Csa);
+        assert!(records.is_empty());
+        assert_eq!(errors, [2, 8]);
+
+        let empty_rank = "|・・・・・・・・・|";
+        let mut diagram = [empty_rank; 9].join("\n");
+        let kif = format!("{diagram}\n/\n");
+        diagram = diagram.replacen(empty_rank, "|・・・・v玉・・・・|", 1);
+        let kif = kif
+            + &diagram.replacen(empty_rank, "|・・・・ 玉・・・・|", 1)
+            + "\n先手の持駒：歩十九\n手数----指手---------消費時間--\n";
+        let (records, errors) = read(kif.as_bytes(), Format::Kif);
+        assert!(records.is_empty());
+        assert_eq!(errors, [9, 21]);
+    }
+
+    #[test]
+    fn converts_between_formats() {
+        let (sfens, _) = convert(CSA.as_bytes(), Format::Csa, Format::Sfen, false);
+        let sfens = String::from_utf8(sfens).unwrap();
+        assert_eq!(sfens.lines().count(), 5);
+        assert_eq!(sfens.lines().next(), Some(STARTPOS));
+        assert_eq!(
+            sfens.lines().last(),
+            Some("lnsgkg1nl/1r5s1/pppppp1pp/6p2/9/2P6/PP1PPPPPP/7R1/LNSGKGSNL b Bb 5")
+        );
+
+        // the game survives a trip through kif
+        let (kif, _) = convert(CSA.as_bytes(), Format::Csa, Format::Kif, false);
+        let (csa, _) = convert(&kif, Format::Kif, Format::Csa, false);
+        let (games, errors) = read(&csa, Format::Csa);
+        assert!(errors.is_empty());
+        let Record::Game(game) = &games[0] else {
+            panic!("expected a game");
+        };
+        assert_eq!(game.actions.len(), 4);
+
+        // positions keep the action played and the result through psv
+        let (psv, _) = convert(CSA.as_bytes(), Format::Csa, Format::PackedSfenValue, false);
+        assert_eq!(psv.len(), 5 * 40);
+        let (positions, errors) = read(&psv, Format::PackedSfenValue);
+        assert!(errors.is_empty());
+        let results: Vec<(Option<String>, i8)> = positions
+            .iter()
+            .map(|record| match record {
+                Record::Position(position) => (
+                    position.action.map(|action| action.to_usi()),
+                    position.result,
+                ),
+                Record::Game(_) => panic!("expected a position"),
+            })
+            .collect();
+        // sente resigned
+        assert_eq!(results[0], (Some("7g7f".to_owned()), -1));
+        assert_eq!(results[1], (Some("3c3d".to_owned()), 1));
+        assert_eq!(results[4], (None, -1));
+        let (sfens_again, _) = convert(&psv, Format::PackedSfenValue, Format::Sfen, false);
+        assert_eq!(String::from_utf8(sfens_again).unwrap(), sfens);
+    }
+
+    #[test]
+    fn dedup() {
+        let input = format!("startpos\n{STARTPOS}\nstartpos\n");
+        let (output, duplicates) = convert(input.as_bytes(), Format::Sfen, Format::Sfen, true);
+        assert_eq!(String::from_utf8(output).unwrap(), format!("{STARTPOS}\n"));
+        assert_eq!(duplicates, 2);
+        let (output, _) = convert(input.as_bytes(), Format::Sfen, Format::Sfen, false);
+        assert_eq!(output.len(), 3 * (STARTPOS.len() + 1));
+    }
+}
@@ -0,0 +1,86 @@
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+};
+
+use ctenophore::convert::{Format, Reader, WriteCount, Writer};
+
+// usage: convert <from> <to> <input> <output> [dedup]
+// formats are sfen, csa, kif, psfen and psv, and - reads stdin or writes stdout. everything but
+// the converted records goes to stderr so that the output can be piped
+pub fn run(args: &[String]) {
+    let usage = "usage: convert <sfen|csa|kif|psfen|psv> <sfen|csa|kif|psfen|psv> <input> \
+                 <output> [dedup]";
+    let [from, to, input_path, output_path, rest @ ..] = args else {
+        eprintln!("{usage}");
+        return;
+    };
+    let (from, to) = match (from.parse::<Format>(), to.parse::<Format>()) {
+        (Ok(from), Ok(to)) => (from, to),
+        (Err(e), _) | (_, Err(e)) => {
+            eprintln!("{e}");
+            return;
+        }
+    };
+    let dedup = match rest {
+        [] => false,
+        [flag] if flag == "dedup" => true,
+        _ => {
+            eprintln!("{usage}");
+            return;
+        }
+    };
+    if dedup && to.is_game() {
+        eprintln!("dedup only applies to positions, not {to} games");
+        return;
+    }
+
+    let input: Box<dyn BufRead> = if input_path == "-" {
+        Box::new(io::stdin().lock())
+    } else {
+        Box::new(BufReader::new(
+            File::open(input_path).expect("failed to open the input file"),
+        ))
+    };
+    let output: Box<dyn Write> = if output_path == "-" {
+        Box::new(io::stdout().lock())
+    } else {
+        Box::new(File::create(output_path).expect("failed to create the output file"))
+    };
+    let mut writer = Writer::new(BufWriter::new(output), to, dedup);
+
+    // binary formats count records instead of lines
+    let unit = if from.is_binary() { "record" } else { "line" };
+    let mut records = 0;
+    let mut errors = 0;
+    let mut total = WriteCount::default();
+    for item in Reader::new(input, from) {
+        let (start, record) = item.expect("failed to read the input");
+        records += 1;
+        let record = match record {
+            Ok(record) => record,
+            Err(e) => {
+                eprintln!("{input_path}: {unit} {}: {}", e.line, e.message);
+                errors += 1;
+                continue;
+            }
+        };
+        let count = writer.write(record).expect("failed to write the output");
+        if count.unsupported > 0 {
+            eprintln!(
+                "{input_path}: {unit} {start}: {} positions can't be written as {to}",
+                count.unsupported
+            );
+        }
+        total.written += count.written;
+        total.duplicates += count.duplicates;
+        total.unsupported += count.unsupported;
+    }
+    writer.flush().expect("failed to write the output");
+
+    eprintln!(
+        "read {records} records with {errors} errors, wrote {} ({} duplicates and {} unsupported \
+         skipped)",
+        total.written, total.duplicates, total.unsupported
+    );
+}
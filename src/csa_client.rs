@@ -0,0 +1,491 @@
+//! A client for the CSA server protocol used by floodgate and the computer shogi championships.
+//! It logs in, accepts games, sends the actions chosen by a player function along with its score
+//! and pv, and keeps the record of every game.
+
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    net::{TcpStream, ToSocketAddrs},
+    time::Duration,
+};
+
+use crate::{
+    board::Board,
+    record::{
+        csa::{csa_action, parse_csa_action, PositionReader},
+        Game, Termination,
+    },
+    search::SearchResult,
+    types::action::Action,
+};
+
+/// The time control from the game summary, in milliseconds whatever `Time_Unit` it was given in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TimeControl {
+    pub total: u64,
+    pub byoyomi: u64,
+    pub increment: u64,
+    /// The milliseconds in one `Time_Unit`, which the times sent with actions count in.
+    pub unit: u64,
+}
+
+/// Remaining time for both sides in milliseconds, kept up to date from the times the server sends
+/// back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Clock {
+    pub control: TimeControl,
+    pub remaining: [u64; 2],
+}
+
+impl Clock {
+    pub fn new(control: TimeControl) -> Self {
+        Self {
+            control,
+            remaining: [control.total; 2],
+        }
+    }
+
+    fn spend(&mut self, side: u8, millis: u64) {
+        // once the main time is gone every action gets the byoyomi on its own
+        let remaining = &mut self.remaining[side as usize];
+        *remaining = remaining.saturating_sub(millis) + self.control.increment;
+    }
+
+    /// How long to think for, a slice of the remaining time plus the byoyomi and increment, with a
+    /// second kept back for the network.
+    pub fn think_time(&self, side: u8) -> Duration {
+        let remaining = self.remaining[side as usize];
+        let budget = remaining / 40 + self.control.byoyomi + self.control.increment;
+        Duration::from_millis(budget.saturating_sub(1000).max(100))
+    }
+}
+
+/// How a game ended for us.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Win,
+    Lose,
+    Draw,
+    /// The server stopped the game without a result.
+    Censored,
+}
+
+#[derive(Debug, Clone)]
+pub struct GameReport {
+    pub id: String,
+    /// 0 if we played sente, 1 for gote.
+    pub side: u8,
+    pub game: Game,
+    pub outcome: Outcome,
+}
+
+/// A logged in connection to a CSA server.
+pub struct CsaClient {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+fn protocol_error(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+// the milliseconds in a Time_Unit, like 1sec, 1min or 10msec
+fn parse_time_unit(unit: &str) -> Option<u64> {
+    let (count, millis) = if let Some(count) = unit.strip_suffix("msec") {
+        (count, 1)
+    } else if let Some(count) = unit.strip_suffix("sec") {
+        (count, 1000)
+    } else if let Some(count) = unit.strip_suffix("min") {
+        (count, 60_000)
+    } else {
+        return None;
+    };
+    let count: u64 = if count.is_empty() {
+        1
+    } else {
+        count.parse().ok()?
+    };
+    (count > 0).then_some(count * millis)
+}
+
+// an action line from the server, its time in milliseconds goes to the clock and in whole seconds
+// to the record
+fn read_action(
+    board: &mut Board,
+    clock: &mut Clock,
+    game: &mut Game,
+    line: &str,
+) -> Result<(), String> {
+    let (text, time) = line.split_once(",T").unwrap_or((line, "0"));
+    let action = parse_csa_action(board, text)
+        .filter(|action| board.legal_actions().contains(action))
+        .ok_or_else(|| format!("illegal action: {line}"))?;
+    let millis = time.parse::<u64>().unwrap_or(0) * clock.control.unit;
+    clock.spend(board.stm(), millis);
+    board.perform_action(action);
+    game.actions.push(action);
+    game.times.push(Some((millis / 1000) as u32));
+    Ok(())
+}
+
+impl CsaClient {
+    /// Connects and logs in, failing if the server rejects the login.
+    pub fn connect(addr: impl ToSocketAddrs, user: &str, password: &str) -> io::Result<Self> {
+        let writer = TcpStream::connect(addr)?;
+        let mut client = Self {
+            reader: BufReader::new(writer.try_clone()?),
+            writer,
+        };
+        client.send(&format!("LOGIN {user} {password}"))?;
+        let reply = client.receive()?;
+        if reply != format!("LOGIN:{user} OK") {
+            return Err(protocol_error(format!("login failed: {reply}")));
+        }
+        Ok(client)
+    }
+
+    fn send(&mut self, line: &str) -> io::Result<()> {
+        self.writer.write_all(line.as_bytes())?;
+        self.writer.write_all(b"\n")?;
+        self.writer.flush()
+    }
+
+    fn receive(&mut self) -> io::Result<String> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "server closed the connection",
+            ));
+        }
+        Ok(line.trim_end().to_owned())
+    }
+
+    /// Waits for a game, agrees to it and plays it to the end. `player` gets the board and the
+    /// time it may use and returns None to resign.
+    pub fn play_game(
+        &mut self,
+        mut player: impl FnMut(&mut Board, Duration) -> Option<SearchResult>,
+    ) -> io::Result<GameReport> {
+        let (id, side, mut clock, mut game) = self.read_summary()?;
+        self.send(&format!("AGREE {id}"))?;
+        let reply = self.receive()?;
+        if reply.starts_with("REJECT") {
+            return Err(protocol_error(format!("game rejected: {reply}")));
+        }
+        if !reply.starts_with("START") {
+            return Err(protocol_error(format!("expected START, got {reply}")));
+        }
+
+        let mut board = game.board();
+        let mut reason = None;
+        // set until the server echoes our action back
+        let mut sent = false;
+        loop {
+            if board.stm() == side && !sent && reason.is_none() {
+                sent = true;
+                match player(&mut board, clock.think_time(side)) {
+                    Some(SearchResult {
+                        best: Some(action),
+                        score,
+                        pv,
+                        ..
+                    }) => {
+                        let mut line = csa_action(&board, action);
+                        line += &pv_comment(&mut board, score, &pv);
+                        self.send(&line)?;
+                    }
+                    _ => self.send("%TORYO")?,
+                }
+            }
+
+            let line = self.receive()?;
+            if line.is_empty() {
+                continue;
+            }
+            if line.starts_with(['+', '-']) {
+                read_action(&mut board, &mut clock, &mut game, &line)
+                    .map_err(|e| protocol_error(format!("{e} from server")))?;
+                sent = false;
+                continue;
+            }
+
+            let stm = board.stm();
+            let outcome = match line.as_str() {
+                "#WIN" => Outcome::Win,
+                "#LOSE" => Outcome::Lose,
+                "#DRAW" => Outcome::Draw,
+                "#CENSORED" => Outcome::Censored,
+                _ => {
+                    // the reason comes first, the outcome follows on the next line
+                    reason = line.strip_prefix('#').map(str::to_owned);
+                    continue;
+                }
+            };
+            game.termination = reason
+                .as_deref()
+                .and_then(|reason| termination(reason, outcome, side, stm));
+            return Ok(GameReport {
+                id,
+                side,
+                game,
+                outcome,
+            });
+        }
+    }
+
+    fn read_summary(&mut self) -> io::Result<(String, u8, Clock, Game)> {
+        // anything before the summary, like keep alive lines, is skipped
+        while self.receive()? != "BEGIN Game_Summary" {}
+
+        let mut id = String::new();
+        let mut side = 0;
+        // the times are only converted once the unit is known
+        let mut unit = 1000;
+        let mut times = [0; 3];
+        let mut game = Game::default();
+        let mut position = PositionReader::default();
+        let mut actions = Vec::new();
+        let mut in_position = false;
+        for number in 1.. {
+            let line = self.receive()?;
+            if line == "END Game_Summary" {
+                break;
+            }
+            if line == "BEGIN Position" {
+                in_position = true;
+                continue;
+            }
+            if line == "END Position" {
+                in_position = false;
+                continue;
+            }
+            if in_position {
+                if line.starts_with('P') {
+                    position
+                        .read_line(&line, number)
+                        .map_err(|e| protocol_error(e.to_string()))?;
+                } else if line == "+" || line == "-" {
+                    position.stm = u8::from(line == "-");
+                } else if line.starts_with(['+', '-']) {
+                    actions.push(line);
+                }
+                continue;
+            }
+
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+            match key {
+                "Game_ID" => id = value.to_owned(),
+                "Your_Turn" => side = u8::from(value == "-"),
+                "Name+" => game.headers.push(("N+".to_owned(), value.to_owned())),
+                "Name-" => game.headers.push(("N-".to_owned(), value.to_owned())),
+                "Time_Unit" => {
+                    unit = parse_time_unit(value)
+                        .ok_or_else(|| protocol_error(format!("unknown time unit {value}")))?
+                }
+                "Total_Time" => times[0] = value.parse().unwrap_or(0),
+                "Byoyomi" => times[1] = value.parse().unwrap_or(0),
+                "Increment" => times[2] = value.parse().unwrap_or(0),
+                _ => {}
+            }
+        }
+        game.headers.push(("$EVENT".to_owned(), id.clone()));
+        game.start_fen = position
+            .checked_fen(0)
+            .map_err(|e| protocol_error(e.message))?;
+
+        // a resumed game comes with the actions played so far
+        let [total, byoyomi, increment] = times.map(|time: u64| time * unit);
+        let mut clock = Clock::new(TimeControl {
+            total,
+            byoyomi,
+            increment,
+            unit,
+        });
+        let mut board = Board::from_fen(&game.start_fen);
+        for line in actions {
+            read_action(&mut board, &mut clock, &mut game, &line)
+                .map_err(|e| protocol_error(format!("{e} in summary")))?;
+        }
+        Ok((id, side, clock, game))
+    }
+
+    pub fn logout(mut self) -> io::Result<()> {
+        self.send("LOGOUT")?;
+        // the server may just hang up instead of confirming
+        let _ = self.receive();
+        Ok(())
+    }
+}
+
+// floodgate style comment, the score is from sente's point of view and the pv is in csa notation
+fn pv_comment(board: &mut Board, score: i32, pv: &[Action]) -> String {
+    let score = if board.stm() == 0 { score } else { -score };
+    let mut comment = format!(",'* {score}");
+    let mut made = 0;
+    for action in pv {
+        comment.push(' ');
+        comment += &csa_action(board, *action);
+        board.perform_action(*action);
+        made += 1;
+    }
+    for _ in 0..made {
+        board.undo_action();
+    }
+    comment
+}
+
+// the server gives a reason and then who won, that together says how the game ended
+fn termination(reason: &str, outcome: Outcome, side: u8, stm: u8) -> Option<Termination> {
+    // whether the side to move is the one that lost
+    let stm_lost = match outcome {
+        Outcome::Win => side != stm,
+        Outcome::Lose => side == stm,
+        Outcome::Draw | Outcome::Censored => false,
+    };
+    Some(match reason {
+        "RESIGN" => Termination::Resign,
+        "TIME_UP" => Termination::Timeout,
+        "SENNICHITE" => Termination::Sennichite,
+        "OUTE_SENNICHITE" | "ILLEGAL_MOVE" if stm_lost => Termination::IllegalMove,
+        "OUTE_SENNICHITE" | "ILLEGAL_MOVE" => Termination::OpponentIllegalMove,
+        "JISHOGI" if outcome == Outcome::Draw => Termination::Jishogi,
+        "JISHOGI" if stm_lost => Termination::Resign,
+        "JISHOGI" => Termination::EnteringKing,
+        "MAX_MOVES" => Termination::MaxMoves,
+        "CHUDAN" => Termination::Interrupted,
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader, Write},
+        net::TcpListener,
+        thread,
+    };
+
+    use super::{parse_time_unit, read_action, Clock, CsaClient, Outcome, TimeControl};
+    use crate::{
+        board::{Board, STARTPOS},
+        record::{Game, GameResult, Termination},
+        search::SearchResult,
+    };
+
+    const SUMMARY: &str = "\
+BEGIN Game_Summary
+Protocol_Version:1.2
+Game_ID:test-game
+Name+:tester
+Name-:engine
+Your_Turn:-
+To_Move:+
+BEGIN Time
+Time_Unit:1sec
+Total_Time:600
+Byoyomi:10
+END Time
+BEGIN Position
+PI
++
++7776FU,T5
+END Position
+END Game_Summary
+";
+
+    // plays the sente side of a short game, checking what the client sends
+    fn mock_server(listener: TcpListener) -> Vec<String> {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut writer = stream;
+        let mut received = Vec::new();
+        let mut receive = |received: &mut Vec<String>| {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            received.push(line.trim_end().to_owned());
+            line.trim_end().to_owned()
+        };
+
+        assert_eq!(receive(&mut received), "LOGIN engine secret");
+        writeln!(writer, "LOGIN:engine OK").unwrap();
+        write!(writer, "{SUMMARY}").unwrap();
+        assert_eq!(receive(&mut received), "AGREE test-game");
+        writeln!(writer, "START:test-game").unwrap();
+
+        let reply = receive(&mut received);
+        let action = reply.split(',').next().unwrap();
+        writeln!(writer, "{action},T1").unwrap();
+        writeln!(writer, "+2726FU,T3").unwrap();
+        writeln!(writer, "#RESIGN").unwrap();
+        writeln!(writer, "#LOSE").unwrap();
+
+        assert_eq!(receive(&mut received), "%TORYO");
+        receive(&mut received);
+        writeln!(writer, "LOGOUT:completed").unwrap();
+        received
+    }
+
+    #[test]
+    fn plays_a_game() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || mock_server(listener));
+
+        let mut client = CsaClient::connect(addr, "engine", "secret").unwrap();
+        let mut turns = 0;
+        let report = client
+            .play_game(|board, _| {
+                turns += 1;
+                if turns > 1 {
+                    return None;
+                }
+                let action = board
+                    .legal_actions()
+                    .into_iter()
+                    .find(|action| action.to_usi() == "3c3d")?;
+                Some(SearchResult {
+                    best: Some(action),
+                    score: -20,
+                    pv: vec![action],
+                    ..Default::default()
+                })
+            })
+            .unwrap();
+        client.logout().unwrap();
+        let received = server.join().unwrap();
+
+        assert_eq!(received[2], "-3334FU,'* 20 -3334FU");
+        assert_eq!(report.side, 1);
+        assert_eq!(report.outcome, Outcome::Lose);
+        assert_eq!(report.game.actions.len(), 3);
+        assert_eq!(report.game.times, [Some(5), Some(1), Some(3)]);
+        assert_eq!(report.game.termination, Some(Termination::Resign));
+        assert_eq!(report.game.result(), Some(GameResult::SenteWin));
+    }
+
+    #[test]
+    fn time_units() {
+        assert_eq!(parse_time_unit("1sec"), Some(1000));
+        assert_eq!(parse_time_unit("1min"), Some(60_000));
+        assert_eq!(parse_time_unit("10msec"), Some(10));
+        assert_eq!(parse_time_unit("1hour"), None);
+        assert_eq!(parse_time_unit("0sec"), None);
+
+        // a minute of main time in milliseconds, with 2 seconds used by sente
+        let control = TimeControl {
+            total: 60_000,
+            byoyomi: 0,
+            increment: 0,
+            unit: 1,
+        };
+        let mut clock = Clock::new(control);
+        let mut board = Board::from_fen(STARTPOS);
+        let mut game = Game::default();
+        read_action(&mut board, &mut clock, &mut game, "+7776FU,T2000").unwrap();
+        assert_eq!(clock.remaining, [58_000, 60_000]);
+        assert_eq!(game.times, [Some(2)]);
+        assert!(read_action(&mut board, &mut clock, &mut game, "+2726FU,T1").is_err());
+    }
+}
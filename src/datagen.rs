@@ -0,0 +1,242 @@
+//! Self-play games for training evaluations. Every game starts with a few random actions, then
+//! both sides play the search's choice at a fixed depth or node count. Quiet positions are kept
+//! with the action played, its score and, once the game is over, the result, and written as
+//! [`PackedEntry`] records that [`PackedFile`](crate::packed_position::PackedFile) maps for the
+//! trainers.
+//!
+//! Generation is deterministic: the same config and seed give the same output byte for byte, no
+//! matter how many threads play the games.
+
+use std::{
+    collections::BTreeMap,
+    io::{self, Write},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc,
+    },
+    thread,
+};
+
+use crate::{
+    adjudication::{AdjudicationConfig, Adjudicator},
+    board::{Board, STARTPOS},
+    packed_position::{write_entries, PackedEntry},
+    record::Termination,
+    search::{search, SearchLimits, MATE_BOUND},
+    types::{action::Action, piece::Piece},
+};
+
+#[derive(Debug, Clone, Copy)]
+pub struct DatagenConfig {
+    pub games: usize,
+    pub threads: usize,
+    pub seed: u64,
+    /// Random actions played at the start of every game.
+    pub random_plies: usize,
+    /// The time limit is ignored, it would make the output depend on the machine.
+    pub limits: SearchLimits,
+    /// Games that go on this long are scored as draws.
+    pub max_plies: usize,
+    /// Ends decided games early from the search's scores.
+    pub adjudication: AdjudicationConfig,
+}
+
+impl Default for DatagenConfig {
+    fn default() -> Self {
+        Self {
+            games: 100,
+            threads: 1,
+            seed: 0,
+            random_plies: 8,
+            limits: SearchLimits {
+                depth: Some(4),
+                ..Default::default()
+            },
+            max_plies: 400,
+            adjudication: AdjudicationConfig::default(),
+        }
+    }
+}
+
+// splitmix64, small and good enough to pick random actions
+#[derive(Debug, Clone)]
+pub(crate) struct Rng(pub(crate) u64);
+
+impl Rng {
+    pub(crate) fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+}
+
+/// Plays one game, `index` picks which game of the seed it is. Positions in check, ones where the
+/// best action captures and ones with mate scores are left out, since their scores say little
+/// about the position itself.
+pub fn play_game(config: &DatagenConfig, index: usize) -> Vec<PackedEntry> {
+    let mut rng = Rng(config.seed ^ Rng(index as u64).next());
+    let mut board = Board::from_fen(STARTPOS);
+    for _ in 0..config.random_plies {
+        let legal = board.legal_actions();
+        if legal.is_empty() {
+            return Vec::new();
+        }
+        board.perform_action(legal[(rng.next() % legal.len() as u64) as usize]);
+    }
+
+    let limits = SearchLimits {
+        time: None,
+        ..config.limits
+    };
+    let stop = AtomicBool::new(false);
+    let mut positions = Vec::new();
+    let mut hashes = vec![board.hash()];
+    let mut adjudicator = Adjudicator::new(config.adjudication);
+    let winner = loop {
+        if hashes.len() > config.max_plies {
+            break None;
+        }
+        // an adjudicated game is either a resignation or a draw
+        match adjudicator.adjudicate(board.stm()) {
+            Some((Termination::Resign, _)) => break Some(1 - board.stm()),
+            Some(_) => break None,
+            None => {}
+        }
+        let result = search(&mut board, limits, &stop, |_| {});
+        // no legal actions is a loss, stalemate included
+        let Some(best) = result.best else {
+            break Some(1 - board.stm());
+        };
+
+        let in_check = !board.get_attackers(board.king_sq()).is_empty();
+        if !in_check && !is_capture(&board, best) && result.score.abs() < MATE_BOUND {
+            // the result is filled in once the game is over
+            positions.extend(PackedEntry::new(&board, best, result.score as i16, 0));
+        }
+
+        adjudicator.record(
+            board.stm(),
+            Some(result.score),
+            config.random_plies + hashes.len(),
+        );
+        board.perform_action(best);
+        let hash = board.hash();
+        hashes.push(hash);
+        // sennichite, perpetual check is scored as a draw too
+        if hashes.iter().filter(|&&seen| seen == hash).count() >= 4 {
+            break None;
+        }
+    };
+
+    for entry in &mut positions {
+        entry.result = match winner {
+            None => 0,
+            Some(winner) if winner == entry.position.stm => 1,
+            Some(_) => -1,
+        };
+    }
+    positions
+}
+
+fn is_capture(board: &Board, action: Action) -> bool {
+    !action.is_drop() && board.piece_on_square(action.to()) != Piece::NONE
+}
+
+/// Plays `config.games` games across `config.threads` threads and writes their entries in game
+/// order. `progress` is called after every game with the games and entries written so far.
+/// Returns the number of entries.
+pub fn generate(
+    config: &DatagenConfig,
+    output: &mut impl Write,
+    mut progress: impl FnMut(usize, usize),
+) -> io::Result<usize> {
+    let next_game = AtomicUsize::new(0);
+    let (sender, receiver) = mpsc::channel();
+    thread::scope(|scope| {
+        for _ in 0..config.threads.max(1) {
+            let sender = sender.clone();
+            let next_game = &next_game;
+            scope.spawn(move || loop {
+                let index = next_game.fetch_add(1, Ordering::Relaxed);
+                if index >= config.games {
+                    break;
+                }
+                if sender.send((index, play_game(config, index))).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(sender);
+
+        // games finish out of order, they are held back until all earlier ones are written
+        let mut pending = BTreeMap::new();
+        let mut written_games = 0;
+        let mut written_entries = 0;
+        for (index, entries) in receiver {
+            pending.insert(index, entries);
+            while let Some(entries) = pending.remove(&written_games) {
+                write_entries(output, &entries)?;
+                written_games += 1;
+                written_entries += entries.len();
+                progress(written_games, written_entries);
+            }
+        }
+        Ok(written_entries)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+
+    use super::{generate, DatagenConfig};
+    use crate::{packed_position::PackedFile, search::SearchLimits};
+
+    #[test]
+    fn deterministic_across_threads() {
+        let config = DatagenConfig {
+            games: 4,
+            threads: 1,
+            seed: 7,
+            random_plies: 6,
+            limits: SearchLimits {
+                depth: Some(1),
+                ..Default::default()
+            },
+            max_plies: 60,
+            adjudication: Default::default(),
+        };
+        let mut single = Vec::new();
+        let count = generate(&config, &mut single, |_, _| {}).unwrap();
+        assert!(count > 0);
+
+        let mut multi = Vec::new();
+        let threads = DatagenConfig {
+            threads: 3,
+            ..config
+        };
+        assert_eq!(generate(&threads, &mut multi, |_, _| {}).unwrap(), count);
+        assert_eq!(single, multi);
+
+        let mut other = Vec::new();
+        let seed = DatagenConfig { seed: 8, ..config };
+        generate(&seed, &mut other, |_, _| {}).unwrap();
+        assert_ne!(single, other);
+
+        // the output is the packed format the trainers read
+        let path = env::temp_dir().join(format!("ctenophore-datagen-{}.bin", process::id()));
+        fs::write(&path, &single).unwrap();
+        let entries = PackedFile::open(&path).unwrap();
+        assert_eq!(entries.len(), count);
+        for entry in &entries {
+            let mut board = entry.board().unwrap();
+            assert!(board.get_attackers(board.king_sq()).is_empty());
+            assert!(board.legal_actions().contains(&entry.action()));
+            assert!((-1..=1).contains(&entry.result));
+        }
+        drop(entries);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,54 @@
+//! Static evaluation: material on the board and in hand plus piece-square tables. The constants
+//! can be regenerated from self-play data with `ctenophore tune`, see [`tune`](crate::tune).
+
+use crate::{
+    board::Board,
+    types::{piece::Piece, square::Square},
+};
+
+// indexed by piece type, kings are worth nothing since they never leave the board
+pub const PIECE_VALUES: [i32; 14] = [
+    90, 315, 405, 495, 855, 990, 540, 0, 540, 540, 540, 540, 945, 1395,
+];
+
+// pieces in hand are a little more flexible than the same piece on the board
+pub const HAND_VALUES: [i32; 7] = [105, 350, 450, 550, 950, 1100, 600];
+
+// indexed by piece type and then the square from the owner's point of view, see relative_square
+pub const PST: [[i32; 81]; 14] = [[0; 81]; 14];
+
+/// The square as its owner sees it, gote's squares are turned around so that both sides share
+/// the same tables.
+pub fn relative_square(sq: Square, side: u8) -> usize {
+    if side == 0 {
+        sq.as_usize()
+    } else {
+        80 - sq.as_usize()
+    }
+}
+
+/// The evaluation from the point of view of the side to move.
+pub fn evaluate(board: &Board) -> i32 {
+    let mut score = 0;
+    for sq in 0..81 {
+        let piece = board.piece_on_square(Square(sq));
+        if piece == Piece::NONE {
+            continue;
+        }
+        let piece_type = piece.piece().as_usize();
+        let value =
+            PIECE_VALUES[piece_type] + PST[piece_type][relative_square(Square(sq), piece.side())];
+        score += if piece.side() == 0 { value } else { -value };
+    }
+    for side in 0..2 {
+        for (piece, count) in board.hand(side) {
+            let value = HAND_VALUES[piece.as_usize()] * count as i32;
+            score += if side == 0 { value } else { -value };
+        }
+    }
+    if board.stm() == 0 {
+        score
+    } else {
+        -score
+    }
+}
@@ -0,0 +1,177 @@
+//! Handicap (komaochi) starting positions. The handicap giver plays gote with pieces taken off
+//! the board and moves first, so every one of these starts with gote to move.
+
+use std::{fmt, str::FromStr};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Handicap {
+    Lance,
+    Bishop,
+    Rook,
+    RookLance,
+    TwoPiece,
+    FourPiece,
+    SixPiece,
+    EightPiece,
+    TenPiece,
+}
+
+// the english name, the japanese one used by kif, and the position
+const HANDICAPS: [(Handicap, &str, &str, &str); 9] = [
+    (
+        Handicap::Lance,
+        "lance",
+        "香落ち",
+        "lnsgkgsn1/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL w - 1",
+    ),
+    (
+        Handicap::Bishop,
+        "bishop",
+        "角落ち",
+        "lnsgkgsnl/1r7/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL w - 1",
+    ),
+    (
+        Handicap::Rook,
+        "rook",
+        "飛車落ち",
+        "lnsgkgsnl/7b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL w - 1",
+    ),
+    (
+        Handicap::RookLance,
+        "rook-lance",
+        "飛香落ち",
+        "lnsgkgsn1/7b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL w - 1",
+    ),
+    (
+        Handicap::TwoPiece,
+        "2-piece",
+        "二枚落ち",
+        "lnsgkgsnl/9/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL w - 1",
+    ),
+    (
+        Handicap::FourPiece,
+        "4-piece",
+        "四枚落ち",
+        "1nsgkgsn1/9/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL w - 1",
+    ),
+    (
+        Handicap::SixPiece,
+        "6-piece",
+        "六枚落ち",
+        "2sgkgs2/9/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL w - 1",
+    ),
+    (
+        Handicap::EightPiece,
+        "8-piece",
+        "八枚落ち",
+        "3gkg3/9/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL w - 1",
+    ),
+    (
+        Handicap::TenPiece,
+        "10-piece",
+        "十枚落ち",
+        "4k4/9/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL w - 1",
+    ),
+];
+
+impl Handicap {
+    pub const ALL: [Self; 9] = [
+        Self::Lance,
+        Self::Bishop,
+        Self::Rook,
+        Self::RookLance,
+        Self::TwoPiece,
+        Self::FourPiece,
+        Self::SixPiece,
+        Self::EightPiece,
+        Self::TenPiece,
+    ];
+
+    fn entry(self) -> &'static (Self, &'static str, &'static str, &'static str) {
+        HANDICAPS
+            .iter()
+            .find(|(handicap, ..)| *handicap == self)
+            .expect("every handicap is in the table")
+    }
+
+    /// The name used by USI commands and options, like `rook-lance`.
+    pub fn name(self) -> &'static str {
+        self.entry().1
+    }
+
+    /// The `手合割` name, like `飛香落ち`.
+    pub fn japanese_name(self) -> &'static str {
+        self.entry().2
+    }
+
+    pub fn fen(self) -> &'static str {
+        self.entry().3
+    }
+
+    pub fn from_japanese_name(name: &str) -> Option<Self> {
+        HANDICAPS
+            .iter()
+            .find(|(_, _, japanese, _)| *japanese == name)
+            .map(|(handicap, ..)| *handicap)
+    }
+
+    /// The handicap a position is the start of, the move number doesn't matter.
+    pub fn from_fen(fen: &str) -> Option<Self> {
+        let position = fen.split_ascii_whitespace().take(3);
+        HANDICAPS
+            .iter()
+            .find(|(.., handicap_fen)| {
+                handicap_fen
+                    .split_ascii_whitespace()
+                    .take(3)
+                    .eq(position.clone())
+            })
+            .map(|(handicap, ..)| *handicap)
+    }
+}
+
+impl FromStr for Handicap {
+    type Err = String;
+
+    /// Takes the english or japanese name.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        HANDICAPS
+            .iter()
+            .find(|(_, name, japanese, _)| name.eq_ignore_ascii_case(s) || *japanese == s)
+            .map(|(handicap, ..)| *handicap)
+            .ok_or_else(|| {
+                let names: Vec<&str> = HANDICAPS.iter().map(|(_, name, ..)| *name).collect();
+                format!("unknown handicap {s}, expected one of {}", names.join(", "))
+            })
+    }
+}
+
+impl fmt::Display for Handicap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Handicap;
+    use crate::board::{Board, STARTPOS};
+
+    #[test]
+    fn names_and_positions() {
+        for handicap in Handicap::ALL {
+            assert_eq!(handicap.name().parse(), Ok(handicap));
+            assert_eq!(handicap.japanese_name().parse(), Ok(handicap));
+            assert_eq!(
+                Handicap::from_japanese_name(handicap.japanese_name()),
+                Some(handicap)
+            );
+            let board = Board::try_from_fen(handicap.fen()).unwrap();
+            assert_eq!(board.stm(), 1);
+            assert_eq!(Handicap::from_fen(&board.to_fen()), Some(handicap));
+        }
+        assert_eq!("ROOK-LANCE".parse(), Ok(Handicap::RookLance));
+        assert!("queen".parse::<Handicap>().is_err());
+        assert_eq!(Handicap::from_fen(STARTPOS), None);
+    }
+}
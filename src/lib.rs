@@ -0,0 +1,63 @@
+//! Board representation and move generation for shogi, used by the ctenophore engine and
+//! usable on its own by tools that need positions and legal actions.
+//!
+//! ```
+//! use ctenophore::board::{Board, STARTPOS};
+//!
+//! let mut board = Board::from_fen(STARTPOS);
+//! let actions = board.legal_actions();
+//! assert_eq!(actions.len(), 30);
+//!
+//! let action = actions.iter().find(|action| action.to_usi() == "7g7f").unwrap();
+//! assert!(board.perform_action(*action));
+//! assert_eq!(
+//!     board.to_fen(),
+//!     "lnsgkgsnl/1r5b1/ppppppppp/9/9/2P6/PP1PPPPPP/1B5R1/LNSGKGSNL w - 2"
+//! );
+//! board.undo_action();
+//! ```
+//!
+//! - [`board`]: positions, SFEN conversion, legal action generation and making/undoing actions
+//! - [`types`]: [`Action`](types::action::Action), [`Bitboard`](types::bitboard::Bitboard),
+//!   [`Square`](types::square::Square), [`Piece`](types::piece::Piece) and
+//!   [`Hand`](types::hand::Hand)
+//! - [`movegen`]: attack lookups for every piece type
+//! - [`perft`]: move generation correctness tools
+//! - [`handicap`]: handicap starting positions
+//! - [`variant`]: minishogi, judkins shogi and other board sizes
+//! - [`notation`]: western and japanese notation for actions
+//! - [`record`]: game records and the KIF, KI2, CSA and JKF formats
+//! - [`eval`] and [`search`]: a material evaluation and a small alpha-beta search
+//! - [`book`]: opening books in the YaneuraOu `.db` format
+//! - [`convert`]: streaming conversion between SFEN, CSA, KIF and the packed formats
+//! - [`datagen`]: self-play training data
+//! - [`packed_sfen`]: YaneuraOu's binary PackedSfen and PackedSfenValue formats
+//! - [`packed_position`]: the native fixed size training record, read through memory maps
+//! - [`nnue`] and [`trainer`]: a small NNUE evaluation and a CPU trainer for it
+//! - [`tune`]: texel tuning of the evaluation constants
+//! - [`tournament`]: matches between USI engines with Elo and SPRT
+//! - [`adjudication`]: resign and draw adjudication from reported scores
+//! - [`csa_client`]: playing on CSA protocol servers like floodgate
+
+pub mod adjudication;
+pub mod board;
+pub mod book;
+pub mod convert;
+pub mod csa_client;
+pub mod datagen;
+pub mod eval;
+pub mod handicap;
+pub mod movegen;
+pub mod nnue;
+pub mod notation;
+pub mod packed_position;
+pub mod packed_sfen;
+pub mod perft;
+pub mod record;
+pub mod search;
+pub mod tournament;
+pub mod trainer;
+pub mod tune;
+pub mod types;
+pub mod variant;
+pub mod zobrist;
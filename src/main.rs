@@ -1,19 +1,50 @@
 use std::env;
 
+use bench::bench;
 use usi::UsiManager;
 
-pub mod board;
-pub mod movegen;
-pub mod perft;
-pub mod types;
-pub mod usi;
+mod bench;
+mod client;
+mod converter;
+mod selfplay;
+mod texel;
+mod train;
+mod usi;
+mod versus;
 
 fn main() {
     env::set_var("RUST_BACKTRACE", "full");
-    let mut manager = UsiManager::default();
-    loop {
-        if !manager.get_command() {
-            break;
+    let args: Vec<String> = env::args().collect();
+    match args.get(1).map(String::as_str) {
+        Some("bench") => {
+            bench();
+            return;
         }
+        Some("csa") => {
+            client::run(&args[2..]);
+            return;
+        }
+        Some("convert") => {
+            converter::run(&args[2..]);
+            return;
+        }
+        Some("datagen") => {
+            selfplay::run(&args[2..]);
+            return;
+        }
+        Some("tune") => {
+            texel::run(&args[2..]);
+            return;
+        }
+        Some("match") => {
+            versus::run(&args[2..]);
+            return;
+        }
+        Some("train") => {
+            train::run(&args[2..]);
+            return;
+        }
+        _ => {}
     }
+    UsiManager::from_stdin().run();
 }
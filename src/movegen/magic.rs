@@ -41,17 +41,19 @@ pub fn get_bishop_attacks_internal(sq: Square, occ: Bitboard) -> Bitboard {
         >> (81 - BISHOP_SHIFT);
     BISHOP_ATTACKS[sq.as_usize()][idx as usize]
 }
+// the back rank entries are built by hand rather than searched for, lance blockers are all on one
+// file so each blocker bit can be shifted straight into its own index bit without collisions
 const LANCE_MAGICS: [[u128; 81]; 2] = [
     [
-        10194502017933093372238,
-        443963134339884184502320,
-        689837006778911441289472,
-        152028944172202605019158,
-        454573026427370909892608,
-        607137707067574884237320,
-        399216544945746487603202,
-        19498535077010928304129,
-        94596061055462287360000,
+        37038168493095256064,
+        18519084246547628032,
+        9259542123273814016,
+        4629771061636907008,
+        2314885530818453504,
+        1157442765409226752,
+        578721382704613376,
+        289360691352306688,
+        144680345676153344,
         606910004016578341898562,
         302707324521735665365025,
         229196798312055407642241,
@@ -198,15 +200,15 @@ const LANCE_MAGICS: [[u128; 81]; 2] = [
         2224836439662519141992528,
         8878324253379244326976,
         691540769717509873308261,
-        1596164485324788444247296,
-        1383654574095940169633024,
-        1058991117820135613937795,
-        369007293699387818552,
-        85592933597805663521537,
-        168824709884242795497472,
-        2213612262340230783536,
-        40140130344035600699852,
-        75705463886480347766849,
+        37038168493095256064,
+        18519084246547628032,
+        9259542123273814016,
+        4629771061636907008,
+        2314885530818453504,
+        1157442765409226752,
+        578721382704613376,
+        289360691352306688,
+        144680345676153344,
     ],
 ];
 const ROOK_MAGICS: [u128; 81] = [
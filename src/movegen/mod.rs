@@ -1,3 +1,7 @@
+//! Attacks for each piece type from a square. The pieces that move differently for each side take
+//! the side as `stm`, the sliders take the occupancy that blocks them. Promoted pieces other than
+//! the horse and dragon attack like golds.
+
 mod lookups;
 
 #[cfg(not(feature = "pext"))]
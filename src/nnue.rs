@@ -0,0 +1,257 @@
+//! A small NNUE evaluation, trained by [`trainer`](crate::trainer). The input features are every
+//! piece on the board relative to the king of the side looking at it, plus how many of each
+//! piece type both sides hold in hand. Both sides' features go through the same feature
+//! transformer, then a clipped ReLU, then a single output neuron, side to move first.
+//!
+//! Network files are a header (`CTNN`, a version and the hidden size) followed by the quantised
+//! weights as little endian integers. Accumulators are refreshed from scratch on every
+//! evaluation, which is plenty for the sizes this is meant for.
+
+use std::{
+    fs,
+    sync::{Arc, RwLock},
+};
+
+use crate::{
+    board::Board,
+    eval::relative_square,
+    types::{hand::Hand, piece::Piece, square::Square},
+};
+
+/// Owner (own or the opponent's), piece type without kings, square.
+pub const BOARD_FEATURES: usize = 2 * 13 * 81;
+/// One "at least n in hand" feature per owner, piece type and count.
+pub const HAND_FEATURES: usize = 2 * 38;
+/// The board features for every king square, then the hand features.
+pub const NUM_FEATURES: usize = 81 * BOARD_FEATURES + HAND_FEATURES;
+
+/// Feature transformer weights are scaled by `QA` and output weights by `QB`.
+pub const QA: i32 = 255;
+pub const QB: i32 = 64;
+/// An output of 1 is this many centipawns.
+pub const SCALE: i32 = 400;
+
+const MAGIC: &[u8; 4] = b"CTNN";
+const VERSION: u32 = 1;
+
+// where each piece type's hand features start, and how many there are
+const HAND_OFFSETS: [usize; 7] = [0, 18, 22, 26, 30, 32, 34];
+const HAND_MAX: [u8; 7] = [18, 4, 4, 4, 2, 2, 4];
+
+/// Appends the active features of a position as `perspective` sees it.
+pub fn features(mailbox: &[Piece; 81], hands: [Hand; 2], perspective: u8, out: &mut Vec<u32>) {
+    let king = Piece::KING.as_stm(perspective);
+    let Some(king_sq) = mailbox.iter().position(|piece| *piece == king) else {
+        return;
+    };
+    let king_offset = relative_square(Square(king_sq as u8), perspective) * BOARD_FEATURES;
+
+    for (sq, piece) in mailbox.iter().enumerate() {
+        if *piece == Piece::NONE || piece.piece() == Piece::KING {
+            continue;
+        }
+        let owner = usize::from(piece.side() != perspective);
+        // kings sit between the unpromoted and promoted pieces
+        let piece_type = piece.piece().as_usize();
+        let piece_type = if piece_type > 7 {
+            piece_type - 1
+        } else {
+            piece_type
+        };
+        let sq = relative_square(Square(sq as u8), perspective);
+        out.push((king_offset + (owner * 13 + piece_type) * 81 + sq) as u32);
+    }
+
+    for (owner, side) in [perspective, 1 - perspective].into_iter().enumerate() {
+        for (i, piece) in HAND_OFFSETS.iter().zip(HAND_MAX).enumerate() {
+            let (offset, max) = piece;
+            let count = hands[side as usize].num(Piece(i as u8)).min(max);
+            for n in 0..count as usize {
+                out.push((81 * BOARD_FEATURES + owner * 38 + offset + n) as u32);
+            }
+        }
+    }
+}
+
+fn board_mailbox(board: &Board) -> [Piece; 81] {
+    let mut mailbox = [Piece::NONE; 81];
+    for (sq, piece) in mailbox.iter_mut().enumerate() {
+        *piece = board.piece_on_square(Square(sq as u8));
+    }
+    mailbox
+}
+
+/// A quantised network, as the engine uses it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Network {
+    pub hidden: usize,
+    /// `hidden` weights per feature.
+    pub ft_weights: Vec<i16>,
+    pub ft_bias: Vec<i16>,
+    /// The side to move's half of the hidden layer, then the other side's.
+    pub out_weights: Vec<i16>,
+    /// Scaled by `QA * QB`.
+    pub out_bias: i32,
+}
+
+impl Network {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        let header = bytes
+            .get(..12)
+            .ok_or("the file is too short for a network")?;
+        if &header[..4] != MAGIC {
+            return Err("not a ctenophore network".to_owned());
+        }
+        let version = u32::from_le_bytes(header[4..8].try_into().expect("4 bytes"));
+        if version != VERSION {
+            return Err(format!("network version {version}, expected {VERSION}"));
+        }
+        let hidden = u32::from_le_bytes(header[8..12].try_into().expect("4 bytes")) as usize;
+
+        let shorts = (NUM_FEATURES + 1 + 2) * hidden;
+        let body = &bytes[12..];
+        if body.len() != shorts * 2 + 4 {
+            return Err(format!(
+                "a network with {hidden} hidden neurons is {} bytes, not {}",
+                12 + shorts * 2 + 4,
+                bytes.len()
+            ));
+        }
+        let mut values = body[..shorts * 2]
+            .chunks_exact(2)
+            .map(|pair| i16::from_le_bytes([pair[0], pair[1]]));
+        let mut take = |n: usize| values.by_ref().take(n).collect::<Vec<_>>();
+        let ft_weights = take(NUM_FEATURES * hidden);
+        let ft_bias = take(hidden);
+        let out_weights = take(2 * hidden);
+        let out_bias = i32::from_le_bytes(body[shorts * 2..].try_into().expect("4 bytes"));
+        Ok(Self {
+            hidden,
+            ft_weights,
+            ft_bias,
+            out_weights,
+            out_bias,
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        bytes.extend_from_slice(&(self.hidden as u32).to_le_bytes());
+        for value in self
+            .ft_weights
+            .iter()
+            .chain(&self.ft_bias)
+            .chain(&self.out_weights)
+        {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes.extend_from_slice(&self.out_bias.to_le_bytes());
+        bytes
+    }
+
+    pub fn load(path: &str) -> Result<Self, String> {
+        let bytes = fs::read(path).map_err(|e| format!("failed to read {path}: {e}"))?;
+        Self::from_bytes(&bytes).map_err(|e| format!("invalid network {path}: {e}"))
+    }
+
+    /// The evaluation from the point of view of the side to move.
+    pub fn evaluate(&self, board: &Board) -> i32 {
+        let hands = [board.hand(0), board.hand(1)];
+        self.evaluate_position(&board_mailbox(board), hands, board.stm())
+    }
+
+    pub fn evaluate_position(&self, mailbox: &[Piece; 81], hands: [Hand; 2], stm: u8) -> i32 {
+        let mut active = Vec::with_capacity(80);
+        let mut output = 0;
+        for (half, perspective) in [stm, 1 - stm].into_iter().enumerate() {
+            active.clear();
+            features(mailbox, hands, perspective, &mut active);
+            let mut accumulator: Vec<i32> = self.ft_bias.iter().map(|&b| i32::from(b)).collect();
+            for &feature in &active {
+                let weights = &self.ft_weights[feature as usize * self.hidden..][..self.hidden];
+                for (value, &weight) in accumulator.iter_mut().zip(weights) {
+                    *value += i32::from(weight);
+                }
+            }
+            let out_weights = &self.out_weights[half * self.hidden..][..self.hidden];
+            for (&value, &weight) in accumulator.iter().zip(out_weights) {
+                output += value.clamp(0, QA) * i32::from(weight);
+            }
+        }
+        let output = (output + self.out_bias) as i64 * i64::from(SCALE) / i64::from(QA * QB);
+        // well clear of mate scores
+        output.clamp(-20000, 20000) as i32
+    }
+}
+
+static NETWORK: RwLock<Option<Arc<Network>>> = RwLock::new(None);
+
+/// Sets the network searches evaluate with, None goes back to the hand-crafted evaluation.
+/// Searches already running keep the one they started with.
+pub fn set_network(network: Option<Network>) {
+    *NETWORK.write().expect("the network lock is never poisoned") = network.map(Arc::new);
+}
+
+pub fn network() -> Option<Arc<Network>> {
+    NETWORK
+        .read()
+        .expect("the network lock is never poisoned")
+        .clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{board_mailbox, features, Network, BOARD_FEATURES, NUM_FEATURES, QA, QB, SCALE};
+    use crate::board::{Board, STARTPOS};
+
+    #[test]
+    fn feature_counts() {
+        let board = Board::from_fen("4k4/9/9/9/9/9/9/9/4K4 b RG3Pp 1");
+        let mailbox = board_mailbox(&board);
+        let hands = [board.hand(0), board.hand(1)];
+        let mut sente = Vec::new();
+        features(&mailbox, hands, 0, &mut sente);
+        // rook, gold and three pawns for sente, a pawn for gote
+        assert_eq!(sente.len(), 6);
+        assert!(sente.iter().all(|&f| f as usize >= 81 * BOARD_FEATURES));
+
+        // the starting position looks the same from both sides
+        let board = Board::from_fen(STARTPOS);
+        let mailbox = board_mailbox(&board);
+        let hands = [board.hand(0), board.hand(1)];
+        let (mut sente, mut gote) = (Vec::new(), Vec::new());
+        features(&mailbox, hands, 0, &mut sente);
+        features(&mailbox, hands, 1, &mut gote);
+        sente.sort_unstable();
+        gote.sort_unstable();
+        assert_eq!(sente.len(), 38);
+        assert_eq!(sente, gote);
+    }
+
+    #[test]
+    fn evaluates_and_round_trips() {
+        // one hidden neuron that lights up when the side to move has a pawn in hand
+        let pawn_in_hand = 81 * BOARD_FEATURES;
+        let mut ft_weights = vec![0; NUM_FEATURES];
+        ft_weights[pawn_in_hand] = 10;
+        let network = Network {
+            hidden: 1,
+            ft_weights,
+            ft_bias: vec![0],
+            out_weights: vec![QB as i16, 0],
+            out_bias: 0,
+        };
+        let board = Board::from_fen("4k4/9/9/9/9/9/9/9/4K4 b 2P 1");
+        // the second pawn is a feature of its own
+        assert_eq!(network.evaluate(&board), 10 * SCALE / QA);
+        // the side to move is the one that counts
+        let board = Board::from_fen("4k4/9/9/9/9/9/9/9/4K4 w 2P 1");
+        assert_eq!(network.evaluate(&board), 0);
+
+        let bytes = network.to_bytes();
+        assert_eq!(Network::from_bytes(&bytes), Ok(network));
+        assert!(Network::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(Network::from_bytes(b"NNUE").is_err());
+    }
+}
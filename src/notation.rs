@@ -0,0 +1,365 @@
+//! Actions in the notations people read: western (`P-7f`, `Bx2b+`, `S*5e`) and japanese
+//! (`▲７六歩`, `△同角成`). Both need the board before the action to know which piece moved and
+//! whether another piece could have made the same move.
+
+use crate::{
+    board::Board,
+    record::kif::{piece_name, FILE_DIGITS, RANK_KANJI},
+    types::{action::Action, piece::Piece, square::Square},
+};
+
+// indexed by piece type
+const WESTERN_NAMES: [&str; 14] = [
+    "P", "L", "N", "S", "B", "R", "G", "K", "+P", "+L", "+N", "+S", "+B", "+R",
+];
+
+/// The japanese disambiguators, which of the pieces that could reach the destination moved.
+/// Directions are from the point of view of the side moving.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct Relative {
+    /// 右, the rightmost piece.
+    pub(crate) right: bool,
+    /// 左, the leftmost piece.
+    pub(crate) left: bool,
+    /// 直, moving straight forward.
+    pub(crate) straight: bool,
+    /// 上, moving forward.
+    pub(crate) up: bool,
+    /// 引, moving backward.
+    pub(crate) back: bool,
+    /// 寄, moving sideways.
+    pub(crate) sideways: bool,
+}
+
+impl Relative {
+    fn to_japanese(self) -> String {
+        let mut output = String::new();
+        for (set, c) in [
+            (self.left, '左'),
+            (self.right, '右'),
+            (self.straight, '直'),
+            (self.up, '上'),
+            (self.back, '引'),
+            (self.sideways, '寄'),
+        ] {
+            if set {
+                output.push(c);
+            }
+        }
+        output
+    }
+}
+
+// the ones worth trying when writing, in the order they are preferred
+const RELATIVES: [Relative; 12] = {
+    let none = Relative {
+        right: false,
+        left: false,
+        straight: false,
+        up: false,
+        back: false,
+        sideways: false,
+    };
+    let up = Relative { up: true, ..none };
+    let back = Relative { back: true, ..none };
+    let sideways = Relative {
+        sideways: true,
+        ..none
+    };
+    let left = Relative { left: true, ..none };
+    let right = Relative {
+        right: true,
+        ..none
+    };
+    [
+        up,
+        back,
+        sideways,
+        Relative {
+            straight: true,
+            ..none
+        },
+        left,
+        right,
+        Relative { up: true, ..left },
+        Relative { back: true, ..left },
+        Relative {
+            sideways: true,
+            ..left
+        },
+        Relative { up: true, ..right },
+        Relative {
+            back: true,
+            ..right
+        },
+        Relative {
+            sideways: true,
+            ..right
+        },
+    ]
+};
+
+/// The squares of the pieces of a type that can legally move to a square.
+pub(crate) fn movers(board: &mut Board, piece: Piece, to: Square) -> Vec<Square> {
+    let mut froms: Vec<Square> = board
+        .legal_actions()
+        .iter()
+        .filter(|action| {
+            !action.is_drop()
+                && action.to() == to
+                && board.piece_on_square(action.from()).piece() == piece
+        })
+        .map(Action::from)
+        .collect();
+    // moves that can promote show up twice
+    froms.dedup();
+    froms
+}
+
+/// Narrows down the pieces that could move to `to` with the disambiguators.
+pub(crate) fn select(stm: u8, froms: &[Square], to: Square, relative: Relative) -> Vec<Square> {
+    let forward = |from: &Square| {
+        let diff = to.rank() as i8 - from.rank() as i8;
+        if stm == 0 {
+            diff
+        } else {
+            -diff
+        }
+    };
+    let rightness = |from: &Square| {
+        if stm == 0 {
+            from.file()
+        } else {
+            8 - from.file()
+        }
+    };
+
+    let mut froms: Vec<Square> = froms
+        .iter()
+        .copied()
+        .filter(|from| {
+            (!relative.up || forward(from) > 0)
+                && (!relative.back || forward(from) < 0)
+                && (!relative.sideways || forward(from) == 0)
+                && (!relative.straight || (forward(from) > 0 && from.file() == to.file()))
+        })
+        .collect();
+    if relative.right {
+        let best = froms.iter().map(rightness).max();
+        froms.retain(|from| Some(rightness(from)) == best);
+    }
+    if relative.left {
+        let best = froms.iter().map(rightness).min();
+        froms.retain(|from| Some(rightness(from)) == best);
+    }
+    froms
+}
+
+fn western_square(sq: Square) -> String {
+    format!("{}{}", 9 - sq.file(), (b'i' - sq.rank()) as char)
+}
+
+impl Action {
+    /// The action in western (Hodges) notation, like `P-7f`, `Bx2b+`, `S*5e`, `G6i-5h` when
+    /// another gold could also move to 5h, or `N-2c=` when a promotion is declined. The board has
+    /// to be in the position before the action.
+    pub fn to_western(&self, board: &mut Board) -> String {
+        let to = self.to();
+        if self.is_drop() {
+            let name = WESTERN_NAMES[self.piece().piece().as_usize()];
+            return format!("{name}*{}", western_square(to));
+        }
+
+        let from = self.from();
+        let piece = board.piece_on_square(from).piece();
+        let mut output = WESTERN_NAMES[piece.as_usize()].to_owned();
+        if movers(board, piece, to).len() > 1 {
+            output += &western_square(from);
+        }
+        output.push(if board.piece_on_square(to) == Piece::NONE {
+            '-'
+        } else {
+            'x'
+        });
+        output += &western_square(to);
+        if self.is_promo() {
+            output.push('+');
+        } else if board
+            .legal_actions()
+            .contains(&Action::new_move(from, to, true))
+        {
+            output.push('=');
+        }
+        output
+    }
+
+    /// The action in japanese notation, like `▲７六歩`, `△同角成` or `▲５八金右`, with `打` only
+    /// when a piece on the board could have moved there too. `prev` is the action before this one,
+    /// for `同`. The board has to be in the position before the action.
+    pub fn to_japanese(&self, board: &mut Board, prev: Option<Action>) -> String {
+        let to = self.to();
+        let mut output = if board.stm() == 0 { "▲" } else { "△" }.to_owned();
+        if prev.is_some_and(|prev| prev.to() == to) {
+            output.push('同');
+        } else {
+            output.push(FILE_DIGITS[(8 - to.file()) as usize]);
+            output.push(RANK_KANJI[(8 - to.rank()) as usize]);
+        }
+
+        if self.is_drop() {
+            let piece = self.piece().piece();
+            output += piece_name(piece);
+            if !movers(board, piece, to).is_empty() {
+                output.push('打');
+            }
+            return output;
+        }
+
+        let from = self.from();
+        let piece = board.piece_on_square(from).piece();
+        output += piece_name(piece);
+        let froms = movers(board, piece, to);
+        if froms.len() > 1 {
+            // dragons and horses only ever use 左 and 右 for pieces side by side, never 直
+            let relative = RELATIVES
+                .iter()
+                .filter(|relative| {
+                    !relative.straight
+                        || (piece != Piece::PROMO_BISHOP && piece != Piece::PROMO_ROOK)
+                })
+                .find(|relative| select(board.stm(), &froms, to, **relative) == [from]);
+            // a few positions with three or more pieces can't be told apart at all
+            if let Some(relative) = relative {
+                output += &relative.to_japanese();
+            }
+        }
+        if self.is_promo() {
+            output.push('成');
+        } else if board
+            .legal_actions()
+            .contains(&Action::new_move(from, to, true))
+        {
+            output += "不成";
+        }
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        board::{Board, STARTPOS},
+        record::kif::parse_ki2,
+    };
+
+    fn notation(fen: &str, usi: &[&str]) -> (Vec<String>, Vec<String>) {
+        let mut board = Board::from_fen(fen);
+        let mut western = Vec::new();
+        let mut japanese = Vec::new();
+        let mut prev = None;
+        for usi in usi {
+            let action = board
+                .legal_actions()
+                .into_iter()
+                .find(|action| action.to_usi() == *usi)
+                .unwrap();
+            western.push(action.to_western(&mut board));
+            japanese.push(action.to_japanese(&mut board, prev));
+            board.perform_action(action);
+            prev = Some(action);
+        }
+        (western, japanese)
+    }
+
+    #[test]
+    fn opening() {
+        let (western, japanese) = notation(
+            STARTPOS,
+            &[
+                "7g7f", "3c3d", "8h2b+", "3a2b", "B*4e", "4a3b", "4i5h", "6a5b",
+            ],
+        );
+        assert_eq!(
+            western,
+            ["P-7f", "P-3d", "Bx2b+", "Sx2b", "B*4e", "G-3b", "G4i-5h", "G-5b"]
+        );
+        assert_eq!(
+            japanese,
+            [
+                "▲７六歩",
+                "△３四歩",
+                "▲２二角成",
+                "△同銀",
+                "▲４五角",
+                "△３二金",
+                "▲５八金右",
+                "△５二金"
+            ]
+        );
+    }
+
+    #[test]
+    fn disambiguation() {
+        // golds on 6i, 5i and 4i can all reach 5h
+        let fen = "4k4/9/9/9/9/9/9/9/K2GGG3 b - 1";
+        for (usi, expected_western, expected_japanese) in [
+            ("6i5h", "G6i-5h", "▲５八金左"),
+            ("5i5h", "G5i-5h", "▲５八金直"),
+            ("4i5h", "G4i-5h", "▲５八金右"),
+        ] {
+            let (western, japanese) = notation(fen, &[usi]);
+            assert_eq!(
+                (western[0].as_str(), japanese[0].as_str()),
+                (expected_western, expected_japanese)
+            );
+        }
+
+        // gote's right is sente's left
+        let (_, japanese) = notation("3gkg3/9/9/9/9/9/9/9/4K4 w - 1", &["6a5b"]);
+        assert_eq!(japanese, ["△５二金右"]);
+
+        // 打 only when a piece on the board could go there too
+        let (western, japanese) = notation("4k4/9/9/9/9/9/9/9/3GK4 b G 1", &["G*6h"]);
+        assert_eq!(western, ["G*6h"]);
+        assert_eq!(japanese, ["▲６八金打"]);
+
+        let (western, japanese) = notation("4k4/9/9/9/9/9/2P6/9/4K4 b - 1", &["7g7f"]);
+        assert_eq!(
+            (western[0].as_str(), japanese[0].as_str()),
+            ("P-7f", "▲７六歩")
+        );
+    }
+
+    #[test]
+    fn promotions() {
+        let (western, japanese) = notation("4k4/9/9/9/6N2/9/9/9/4K4 b - 1", &["3e2c"]);
+        assert_eq!(western, ["N-2c="]);
+        assert_eq!(japanese, ["▲２三桂不成"]);
+    }
+
+    #[test]
+    fn japanese_reads_back_as_ki2() {
+        // a pseudo random game, with captures and drops so that there is plenty to disambiguate
+        let mut board = Board::from_fen(STARTPOS);
+        let mut seed = 12345u64;
+        let mut prev = None;
+        let mut actions = Vec::new();
+        let mut ki2 = String::new();
+        for _ in 0..150 {
+            let legal = board.legal_actions();
+            if legal.is_empty() {
+                break;
+            }
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            let action = legal[(seed >> 33) as usize % legal.len()];
+            ki2 += &action.to_japanese(&mut board, prev);
+            ki2.push('\n');
+            board.perform_action(action);
+            actions.push(action);
+            prev = Some(action);
+        }
+        assert_eq!(parse_ki2(&ki2).unwrap().actions, actions);
+    }
+}
@@ -0,0 +1,292 @@
+//! The native training data format. Every record is a fixed 64 byte [`PackedEntry`]: the
+//! position, the action played there, the score and the game result. Files are nothing but
+//! entries back to back, so [`PackedFile`] maps them into memory and hands trainers a slice
+//! without reading or copying anything.
+//!
+//! Unlike [`packed_sfen`](crate::packed_sfen) this isn't meant for other programs, fields are
+//! in native byte order (little endian on anything this runs on) and the layout can change
+//! along with the engine.
+
+use std::{
+    fs::File,
+    io::{self, Write},
+    mem, ops,
+    path::Path,
+    slice,
+};
+
+use memmap2::Mmap;
+
+use crate::{
+    board::{fen_from_parts, Board},
+    types::{action::Action, hand::Hand, piece::Piece, square::Square},
+};
+
+/// A position in 56 bytes. The move count isn't stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(C)]
+pub struct PackedPosition {
+    /// Occupied squares, the low 64 bits first.
+    pub occupancy: [u64; 2],
+    /// Bit `i` is set when the `i`th piece belongs to gote.
+    pub gote: u64,
+    /// The raw [`Hand`] of each side.
+    pub hands: [u32; 2],
+    /// A nibble per occupied square, lowest square first and the low nibble first, holding the
+    /// piece type.
+    pub pieces: [u8; 20],
+    pub stm: u8,
+    _padding: [u8; 3],
+}
+
+/// A training record, 64 bytes. The score and result are from the side to move's point of view.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(C)]
+pub struct PackedEntry {
+    pub position: PackedPosition,
+    /// The raw [`Action`] played.
+    pub action: u16,
+    pub score: i16,
+    /// 1 for a win, 0 for a draw and -1 for a loss.
+    pub result: i8,
+    _padding: [u8; 3],
+}
+
+// the padding is spelled out so that entries have no uninitialised bytes to write
+const _: () = assert!(mem::size_of::<PackedPosition>() == 56);
+const _: () = assert!(mem::size_of::<PackedEntry>() == 64);
+
+impl PackedPosition {
+    /// Packs a position, None if it has more than the 40 pieces a game can have.
+    pub fn from_board(board: &Board) -> Option<Self> {
+        let mut packed = Self {
+            occupancy: [0; 2],
+            gote: 0,
+            hands: [board.hand(0).0, board.hand(1).0],
+            pieces: [0; 20],
+            stm: board.stm(),
+            _padding: [0; 3],
+        };
+        let mut count = 0;
+        for i in 0..81 {
+            let piece = board.piece_on_square(Square(i));
+            if piece == Piece::NONE {
+                continue;
+            }
+            if count == 40 {
+                return None;
+            }
+            packed.occupancy[i as usize / 64] |= 1 << (i % 64);
+            packed.gote |= u64::from(piece.side()) << count;
+            packed.pieces[count / 2] |= piece.piece().raw() << (count % 2 * 4);
+            count += 1;
+        }
+        Some(packed)
+    }
+
+    /// The piece on every square, None if the pieces don't make sense. Cheaper than a whole
+    /// [`Board`] for code that only needs to look at the position.
+    pub fn mailbox(&self) -> Option<[Piece; 81]> {
+        let mut mailbox = [Piece::NONE; 81];
+        let mut count = 0;
+        for (half, &bits) in self.occupancy.iter().enumerate() {
+            let mut bits = bits;
+            while bits != 0 {
+                let sq = half * 64 + bits.trailing_zeros() as usize;
+                bits &= bits - 1;
+                if sq >= 81 || count == 40 {
+                    return None;
+                }
+                let piece = self.pieces[count / 2] >> (count % 2 * 4) & 0xf;
+                if piece >= Piece::NONE.raw() {
+                    return None;
+                }
+                let side = (self.gote >> count & 1) as u8;
+                mailbox[sq] = Piece::new_unchecked(piece, side);
+                count += 1;
+            }
+        }
+        Some(mailbox)
+    }
+
+    /// Unpacks the position with the given move count, None if it doesn't hold a valid one.
+    pub fn to_board(&self, ply: i16) -> Option<Board> {
+        let mailbox = self.mailbox()?;
+        if self.stm > 1 {
+            return None;
+        }
+
+        let hands = [Hand(self.hands[0]), Hand(self.hands[1])];
+        let fen = fen_from_parts(&mailbox, self.stm, hands, ply);
+        Board::try_from_fen(&fen).ok()
+    }
+}
+
+impl PackedEntry {
+    /// None if the position can't be packed.
+    pub fn new(board: &Board, action: Action, score: i16, result: i8) -> Option<Self> {
+        Some(Self {
+            position: PackedPosition::from_board(board)?,
+            action: action.0,
+            score,
+            result,
+            _padding: [0; 3],
+        })
+    }
+
+    /// The position, with a move count of 1.
+    pub fn board(&self) -> Option<Board> {
+        self.position.to_board(1)
+    }
+
+    /// The action played, it is up to the writer that it is legal in the position.
+    pub fn action(&self) -> Action {
+        Action(self.action)
+    }
+
+    pub fn as_bytes(entries: &[Self]) -> &[u8] {
+        // SAFETY: entries are plain integers with no padding the compiler added
+        unsafe { slice::from_raw_parts(entries.as_ptr().cast(), mem::size_of_val(entries)) }
+    }
+
+    /// Views bytes as entries without copying them, None if the length isn't a whole number of
+    /// entries or the bytes aren't aligned for them.
+    pub fn from_bytes(bytes: &[u8]) -> Option<&[Self]> {
+        if !bytes.len().is_multiple_of(mem::size_of::<Self>())
+            || bytes.as_ptr().align_offset(mem::align_of::<Self>()) != 0
+        {
+            return None;
+        }
+        // SAFETY: length and alignment were checked above and any bit pattern is a valid entry
+        Some(unsafe {
+            slice::from_raw_parts(bytes.as_ptr().cast(), bytes.len() / mem::size_of::<Self>())
+        })
+    }
+}
+
+pub fn write_entries(output: &mut impl Write, entries: &[PackedEntry]) -> io::Result<()> {
+    output.write_all(PackedEntry::as_bytes(entries))
+}
+
+/// A file of entries mapped into memory. It derefs to `[PackedEntry]`, so it can be indexed,
+/// sliced and iterated like any slice.
+pub struct PackedFile {
+    // None for an empty file, which can't be mapped
+    mmap: Option<Mmap>,
+}
+
+impl PackedFile {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = File::open(path)?;
+        if file.metadata()?.len() == 0 {
+            return Ok(Self { mmap: None });
+        }
+        // SAFETY: the file mustn't be changed while it is mapped, which is on whoever writes it
+        let mmap = unsafe { Mmap::map(&file)? };
+        // mappings start on a page, so only the length can be wrong
+        if PackedEntry::from_bytes(&mmap).is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{} bytes isn't a whole number of {} byte entries",
+                    mmap.len(),
+                    mem::size_of::<PackedEntry>()
+                ),
+            ));
+        }
+        Ok(Self { mmap: Some(mmap) })
+    }
+}
+
+impl ops::Deref for PackedFile {
+    type Target = [PackedEntry];
+
+    fn deref(&self) -> &[PackedEntry] {
+        self.mmap.as_ref().map_or(&[], |mmap| {
+            PackedEntry::from_bytes(mmap).expect("checked when the file was opened")
+        })
+    }
+}
+
+impl<'a> IntoIterator for &'a PackedFile {
+    type Item = &'a PackedEntry;
+    type IntoIter = slice::Iter<'a, PackedEntry>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+
+    use super::{write_entries, PackedEntry, PackedFile, PackedPosition};
+    use crate::board::{Board, STARTPOS};
+
+    #[test]
+    fn round_trips() {
+        for fen in [
+            STARTPOS,
+            "lnsgkgsnl/1r5b1/ppppppppp/9/9/2P6/PP1PPPPPP/1B5R1/LNSGKGSNL w - 2",
+            "ln1g1g1nl/1ks2r3/1ppppsbpp/p4pp2/7PP/P1PPP1P2/1PBS1P3/1KGR2S2/LN3G1NL w - 40",
+            "4k4/9/9/9/9/9/9/9/4K4 b 2R2B4G4S4N4L18P 1",
+            "+R3k4/9/4+P4/9/9/9/9/9/4K4 w - 1",
+        ] {
+            let board = Board::from_fen(fen);
+            let packed = PackedPosition::from_board(&board).unwrap();
+            let ply = board.ply();
+            assert_eq!(packed.to_board(ply).unwrap().to_fen(), fen);
+        }
+
+        let mut bad = PackedPosition::from_board(&Board::from_fen(STARTPOS)).unwrap();
+        bad.pieces[0] |= 0xf;
+        assert!(bad.to_board(1).is_none());
+    }
+
+    #[test]
+    fn reads_mapped_files() {
+        let mut board = Board::from_fen(STARTPOS);
+        let mut entries = Vec::new();
+        for (i, usi) in ["7g7f", "3c3d", "8h2b+", "3a2b"].iter().enumerate() {
+            let action = board
+                .legal_actions()
+                .into_iter()
+                .find(|action| action.to_usi() == *usi)
+                .unwrap();
+            entries.push(PackedEntry::new(&board, action, i as i16 * 10 - 15, 1).unwrap());
+            board.perform_action(action);
+        }
+
+        let path = env::temp_dir().join(format!("ctenophore-packed-{}.bin", process::id()));
+        let mut output = Vec::new();
+        write_entries(&mut output, &entries).unwrap();
+        assert_eq!(output.len(), 4 * 64);
+        fs::write(&path, &output).unwrap();
+
+        let file = PackedFile::open(&path).unwrap();
+        assert_eq!(&file[..], &entries[..]);
+        let read: Vec<(String, String, i16)> = file
+            .iter()
+            .map(|entry| {
+                (
+                    entry.board().unwrap().to_fen(),
+                    entry.action().to_usi(),
+                    entry.score,
+                )
+            })
+            .collect();
+        assert_eq!(read[2].1, "8h2b+");
+        assert_eq!(read[3].2, 15);
+        assert_eq!(
+            read[1].0,
+            "lnsgkgsnl/1r5b1/ppppppppp/9/9/2P6/PP1PPPPPP/1B5R1/LNSGKGSNL w - 1"
+        );
+
+        fs::write(&path, &output[..100]).unwrap();
+        assert!(PackedFile::open(&path).is_err());
+        fs::write(&path, []).unwrap();
+        assert!(PackedFile::open(&path).unwrap().is_empty());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,363 @@
+//! The binary position formats used by YaneuraOu and the learners built around it: the 32 byte
+//! Huffman coded [`PackedSfen`] and the 40 byte [`PackedSfenValue`] training record.
+//!
+//! Bits are written least significant first. A packed position is the side to move (1 bit), the
+//! sente and gote king squares (7 bits each), every other square in YaneuraOu order (1a, 1b, ..
+//! 1i, 2a, ..) and finally the pieces in hand. Squares hold a Huffman code for the unpromoted
+//! piece type followed by a promotion bit (not for golds) and a side bit. Pieces in hand drop
+//! the lowest code bit, which keeps the total at exactly 256 bits when all 40 pieces are there.
+
+use crate::{
+    board::{fen_from_parts, Board},
+    types::{action::Action, hand::Hand, piece::Piece, square::Square},
+};
+
+// (code, bits) for a piece on the board, indexed by piece type, which happens to be the same
+// order as yaneuraou's
+const HUFFMAN: [(u8, u8); 7] = [
+    (0x01, 2), // pawn
+    (0x03, 4), // lance
+    (0x0b, 4), // knight
+    (0x07, 4), // silver
+    (0x1f, 6), // bishop
+    (0x3f, 6), // rook
+    (0x0f, 5), // gold
+];
+
+// also the order hands are written in
+const PIECE_TYPES: [Piece; 7] = [
+    Piece::PAWN,
+    Piece::LANCE,
+    Piece::KNIGHT,
+    Piece::SILVER,
+    Piece::BISHOP,
+    Piece::ROOK,
+    Piece::GOLD,
+];
+
+// how many of each piece type there are
+const HAND_MAX: [u8; 7] = [18, 4, 4, 4, 2, 2, 4];
+
+// yaneuraou numbers squares file first, starting from 1a
+fn to_yo_square(sq: Square) -> u8 {
+    (8 - sq.file()) * 9 + (8 - sq.rank())
+}
+
+fn from_yo_square(sq: u8) -> Square {
+    Square::from_rf(8 - sq % 9, 8 - sq / 9)
+}
+
+struct BitWriter {
+    data: [u8; 32],
+    cursor: usize,
+}
+
+impl BitWriter {
+    fn write(&mut self, value: u32, bits: u8) -> Option<()> {
+        for i in 0..bits {
+            if self.cursor >= 256 {
+                return None;
+            }
+            if value >> i & 1 != 0 {
+                self.data[self.cursor / 8] |= 1 << (self.cursor % 8);
+            }
+            self.cursor += 1;
+        }
+        Some(())
+    }
+}
+
+struct BitReader<'a> {
+    data: &'a [u8; 32],
+    cursor: usize,
+}
+
+impl BitReader<'_> {
+    fn read(&mut self, bits: u8) -> Option<u32> {
+        let mut value = 0;
+        for i in 0..bits {
+            if self.cursor >= 256 {
+                return None;
+            }
+            value |= u32::from(self.data[self.cursor / 8] >> (self.cursor % 8) & 1) << i;
+            self.cursor += 1;
+        }
+        Some(value)
+    }
+
+    // reads codes a bit at a time until one matches, None for an empty square
+    fn read_piece(&mut self, in_hand: bool) -> Option<Option<Piece>> {
+        let mut code = 0;
+        for bits in 1..=6 {
+            code |= self.read(1)? << (bits - 1);
+            if !in_hand && bits == 1 && code == 0 {
+                return Some(None);
+            }
+            let found = HUFFMAN.iter().position(|&(huffman, len)| {
+                if in_hand {
+                    u32::from(huffman >> 1) == code && len - 1 == bits
+                } else {
+                    u32::from(huffman) == code && len == bits
+                }
+            });
+            if let Some(index) = found {
+                let piece = PIECE_TYPES[index];
+                let is_promoted = piece != Piece::GOLD && self.read(1)? == 1;
+                let side = self.read(1)? as u8;
+                let piece = if is_promoted { piece.promote() } else { piece };
+                return Some(Some(piece.as_stm(side)));
+            }
+        }
+        None
+    }
+}
+
+/// A position in 32 bytes. The move count isn't stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PackedSfen(pub [u8; 32]);
+
+impl PackedSfen {
+    /// Packs a position, None unless all 40 pieces are on the board or in hand, since the
+    /// format has no way to mark the end of the hands.
+    pub fn from_board(board: &Board) -> Option<Self> {
+        let mut writer = BitWriter {
+            data: [0; 32],
+            cursor: 0,
+        };
+        writer.write(u32::from(board.stm()), 1)?;
+
+        let mut kings = [None; 2];
+        for i in 0..81 {
+            let piece = board.piece_on_square(Square(i));
+            if piece != Piece::NONE && piece.piece() == Piece::KING {
+                kings[piece.side() as usize] = Some(Square(i));
+            }
+        }
+        for king in kings {
+            writer.write(u32::from(to_yo_square(king?)), 7)?;
+        }
+
+        for yo_sq in 0..81 {
+            let piece = board.piece_on_square(from_yo_square(yo_sq));
+            if piece == Piece::NONE {
+                writer.write(0, 1)?;
+                continue;
+            }
+            let piece_type = piece.piece().unpromote();
+            if piece_type == Piece::KING {
+                continue;
+            }
+            let (code, bits) = HUFFMAN[piece_type.as_usize()];
+            writer.write(u32::from(code), bits)?;
+            if piece_type != Piece::GOLD {
+                writer.write(u32::from(piece.piece() != piece_type), 1)?;
+            }
+            writer.write(u32::from(piece.side()), 1)?;
+        }
+
+        for side in 0..2 {
+            for piece in PIECE_TYPES {
+                let (code, bits) = HUFFMAN[piece.as_usize()];
+                for _ in 0..board.hand(side).num(piece) {
+                    writer.write(u32::from(code >> 1), bits - 1)?;
+                    if piece != Piece::GOLD {
+                        writer.write(0, 1)?;
+                    }
+                    writer.write(u32::from(side), 1)?;
+                }
+            }
+        }
+        (writer.cursor == 256).then_some(Self(writer.data))
+    }
+
+    /// Unpacks into a board with the given move count, None if the bits don't make a valid
+    /// position.
+    pub fn to_board(&self, ply: u16) -> Option<Board> {
+        let mut reader = BitReader {
+            data: &self.0,
+            cursor: 0,
+        };
+        let stm = reader.read(1)? as u8;
+        let mut mailbox = [Piece::NONE; 81];
+        for side in 0..2 {
+            let sq = reader.read(7)? as u8;
+            if sq >= 81 || mailbox[from_yo_square(sq).as_usize()] != Piece::NONE {
+                return None;
+            }
+            mailbox[from_yo_square(sq).as_usize()] = Piece::KING.as_stm(side);
+        }
+        for yo_sq in 0..81 {
+            let sq = from_yo_square(yo_sq).as_usize();
+            if mailbox[sq] == Piece::NONE {
+                mailbox[sq] = reader.read_piece(false)?.unwrap_or(Piece::NONE);
+            }
+        }
+        let mut hands = [Hand::EMPTY; 2];
+        while reader.cursor < 256 {
+            let piece = reader.read_piece(true)??;
+            let hand = &mut hands[piece.side() as usize];
+            // more than there are would spill into the next piece's bits
+            if hand.num(piece) >= HAND_MAX[piece.piece().as_usize()] {
+                return None;
+            }
+            hand.inc(piece);
+        }
+
+        let fen = fen_from_parts(&mailbox, stm, hands, ply as i16);
+        Board::try_from_fen(&fen).ok()
+    }
+}
+
+/// YaneuraOu's 16 bit encoding of an action: the destination in the low 7 bits, then the origin,
+/// or the piece type (pawn = 1 up to gold = 7) for drops, with bit 14 set for drops and bit 15
+/// for promotions.
+pub fn to_move16(action: Action) -> u16 {
+    let to = u16::from(to_yo_square(action.to()));
+    if action.is_drop() {
+        to | (u16::from(action.piece().piece().raw()) + 1) << 7 | 1 << 14
+    } else {
+        to | u16::from(to_yo_square(action.from())) << 7 | u16::from(action.is_promo()) << 15
+    }
+}
+
+/// The action for a 16 bit move, if it is legal on the board.
+pub fn from_move16(move16: u16, board: &Board) -> Option<Action> {
+    let to = (move16 & 0x7f) as u8;
+    let from = (move16 >> 7 & 0x7f) as u8;
+    if to >= 81 {
+        return None;
+    }
+    let to = from_yo_square(to);
+    let action = if move16 & 1 << 14 != 0 {
+        let piece = PIECE_TYPES.get(usize::from(from).checked_sub(1)?)?;
+        Action::new_drop(piece.as_stm(board.stm()), to)
+    } else if from < 81 {
+        Action::new_move(from_yo_square(from), to, move16 & 1 << 15 != 0)
+    } else {
+        return None;
+    };
+    // from_usi already knows how to check legality
+    Action::from_usi(&action.to_usi(), board).ok()
+}
+
+/// A training record: a packed position, its score, the action played from it, the move count
+/// and how the game ended, 40 bytes in all.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PackedSfenValue {
+    pub sfen: PackedSfen,
+    /// From the side to move's point of view.
+    pub score: i16,
+    /// See [`to_move16`].
+    pub action: u16,
+    pub game_ply: u16,
+    /// 1 if the side to move went on to win, 0 for a draw and -1 for a loss.
+    pub game_result: i8,
+}
+
+impl PackedSfenValue {
+    pub const SIZE: usize = 40;
+
+    /// Little endian, with a byte of padding at the end.
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let mut bytes = [0; Self::SIZE];
+        bytes[..32].copy_from_slice(&self.sfen.0);
+        bytes[32..34].copy_from_slice(&self.score.to_le_bytes());
+        bytes[34..36].copy_from_slice(&self.action.to_le_bytes());
+        bytes[36..38].copy_from_slice(&self.game_ply.to_le_bytes());
+        bytes[38] = self.game_result as u8;
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8; Self::SIZE]) -> Self {
+        let mut sfen = [0; 32];
+        sfen.copy_from_slice(&bytes[..32]);
+        Self {
+            sfen: PackedSfen(sfen),
+            score: i16::from_le_bytes([bytes[32], bytes[33]]),
+            action: u16::from_le_bytes([bytes[34], bytes[35]]),
+            game_ply: u16::from_le_bytes([bytes[36], bytes[37]]),
+            game_result: bytes[38] as i8,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{from_move16, to_move16, PackedSfen, PackedSfenValue};
+    use crate::{
+        board::{Board, STARTPOS},
+        types::action::Action,
+    };
+
+    #[test]
+    fn startpos() {
+        let packed = PackedSfen::from_board(&Board::from_fen(STARTPOS)).unwrap();
+        // sente to move (0), sente king on 5i = 44 = 0101100, gote king on 5a = 36 = 0100100,
+        // then the gote lance on 1a starts with 1
+        assert_eq!(packed.0[0], 0b0101_1000);
+        assert_eq!(packed.0[1], 0b1010_0100);
+        assert_eq!(packed.to_board(1).unwrap().to_fen(), STARTPOS);
+    }
+
+    #[test]
+    fn round_trips() {
+        for fen in [
+            "lnsgkgsnl/1r5b1/pppppp1pp/6p2/9/2P6/PP1PPPPPP/1B5R1/LNSGKGSNL w - 2",
+            "lnsgk1snl/1r4g2/p1pppp1pp/6p2/1p7/2P6/PP1PPPPPP/7R1/LNSGKGSNL b Bb 9",
+            "l6nl/5+P1gk/2np1S3/p1p4Pp/3P2Sp1/1PPb2P1P/P5GS1/R8/LN4bKL w RGgsn5p 1",
+            "8l/1l+R2P3/p2pBG1pp/kps1p4/Nn1P2G2/P1P1P2PP/1PS6/1KSG3+r1/LN2+p3L w Sbgn3p 124",
+            "k8/9/9/9/9/9/9/9/8K b 2R2B4G4S4N4L18P 1",
+            "4k4/9/9/9/9/9/9/9/4K4 w 2r2b4g4s4n4l18p 50",
+        ] {
+            let board = Board::from_fen(fen);
+            let packed = PackedSfen::from_board(&board).unwrap();
+            assert_eq!(packed.to_board(board.ply() as u16).unwrap().to_fen(), fen);
+        }
+
+        // handicaps and anything else without all 40 pieces don't fit
+        let handicap = "lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSN1 w - 1";
+        assert_eq!(PackedSfen::from_board(&Board::from_fen(handicap)), None);
+        // kings on the same square
+        assert_eq!(
+            PackedSfen([0; 32]).to_board(1).map(|board| board.to_fen()),
+            None
+        );
+    }
+
+    #[test]
+    fn move16() {
+        // squares count from 1a = 0 down the file, so 7f = 6 * 9 + 5
+        for (fen, usi, move16) in [
+            (STARTPOS, "7g7f", 59 | 60 << 7),
+            (
+                "lnsgkgsnl/1r5b1/pppppp1pp/6p2/9/2P6/PP1PPPPPP/1B5R1/LNSGKGSNL b - 3",
+                "8h2b+",
+                10 | 70 << 7 | 1 << 15,
+            ),
+            (
+                "lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNS1KGSNL b G 1",
+                "G*5e",
+                40 | 7 << 7 | 1 << 14,
+            ),
+        ] {
+            let board = Board::from_fen(fen);
+            let action = Action::from_usi(usi, &board).unwrap();
+            assert_eq!(to_move16(action), move16, "{usi}");
+            assert_eq!(from_move16(move16, &board), Some(action));
+        }
+        let board = Board::from_fen(STARTPOS);
+        // no piece on 5e
+        assert_eq!(from_move16(40 | 40 << 7, &board), None);
+
+        let value = PackedSfenValue {
+            sfen: PackedSfen::from_board(&board).unwrap(),
+            score: -123,
+            action: 59 | 60 << 7,
+            game_ply: 77,
+            game_result: -1,
+        };
+        let bytes = value.to_bytes();
+        assert_eq!(&bytes[32..], &[0x85, 0xff, 0x3b, 0x1e, 77, 0, 0xff, 0]);
+        assert_eq!(PackedSfenValue::from_bytes(&bytes), value);
+    }
+}
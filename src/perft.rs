@@ -1,26 +1,146 @@
-use std::time::Instant;
+use std::{
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+    thread,
+    time::Instant,
+};
 
-use crate::board::Board;
+use crate::{board::Board, types::action::Action, variant::VariantBoard};
 
+// prints each legal action in usi notation with its node count, sorted so that it can be compared
+// against other engines line by line
 pub fn split_perft(board: &mut Board, depth: u8) {
-    let actions = board.get_actions();
-    let mut count = 0;
     let start = Instant::now();
-    for action in &actions {
-        if board.perform_action(*action) {
-            let result = perft_internal(board, depth - 1);
-            println!("{} : {}", action, result);
-            board.undo_action();
-            count += result;
-        }
+    let results = divide(board, depth);
+    let mut count = 0;
+    for (action, nodes) in &results {
+        println!("{action}: {nodes}");
+        count += nodes;
     }
+    println!();
     println!(
         "{} nodes {} nps",
         count,
-        count as f32 / start.elapsed().as_secs_f32()
+        (count as f64 / start.elapsed().as_secs_f64()) as u64
     );
 }
 
+pub fn divide(board: &mut Board, depth: u8) -> Vec<(String, u64)> {
+    let mut results = Vec::new();
+    for action in &board.get_actions() {
+        if board.perform_action(*action) {
+            results.push((action.to_usi(), perft_bulk(board, depth.max(1) - 1, None)));
+            board.undo_action();
+        }
+    }
+    results.sort();
+    results
+}
+
+// reads a divide listing from another engine, lines look like "7g7f: 30" or "7g7f 30", anything
+// that doesn't look like an action followed by a count is skipped
+pub fn parse_divide<'a>(lines: impl IntoIterator<Item = &'a str>) -> Vec<(String, u64)> {
+    let mut results = Vec::new();
+    for line in lines {
+        let mut tokens = line
+            .split(|c: char| c == ':' || c.is_ascii_whitespace())
+            .filter(|token| !token.is_empty());
+        let (Some(action), Some(count), None) = (tokens.next(), tokens.next(), tokens.next())
+        else {
+            continue;
+        };
+        let Ok(count) = count.parse() else {
+            continue;
+        };
+        if !is_usi_action(action) {
+            continue;
+        }
+        // some engines print drops in lowercase for gote
+        let action = if action.as_bytes()[1] == b'*' {
+            action.to_ascii_uppercase()[..2].to_owned() + &action[2..]
+        } else {
+            action.to_owned()
+        };
+        results.push((action, count));
+    }
+    results.sort();
+    results
+}
+
+// whether a token has the shape of a usi action, like 7g7f, 8h2b+ or P*5e, so that summary
+// lines like "Nodes: 62" aren't taken for one
+fn is_usi_action(token: &str) -> bool {
+    let square = |s: &[u8]| matches!(s, [b'1'..=b'9', b'a'..=b'i']);
+    match token.as_bytes() {
+        [piece, b'*', to @ ..] => b"PLNSGBRplnsgbr".contains(piece) && square(to),
+        [from @ .., b'+'] if from.len() == 4 => square(&from[..2]) && square(&from[2..]),
+        action => action.len() == 4 && square(&action[..2]) && square(&action[2..]),
+    }
+}
+
+// compares our divide against a reference one, then keeps making the first action with a
+// different count and asking for the reference divide of the new position, until the action
+// lists themselves differ
+pub fn perft_diff(
+    board: &mut Board,
+    depth: u8,
+    mut reference: Vec<(String, u64)>,
+    mut next_reference: impl FnMut(&Board, u8) -> Vec<(String, u64)>,
+) {
+    let mut depth = depth.max(1);
+    let mut path: Vec<String> = Vec::new();
+    loop {
+        let ours = divide(board, depth);
+        let missing: Vec<&str> = reference
+            .iter()
+            .filter(|(action, _)| !ours.iter().any(|(ours, _)| ours == action))
+            .map(|(action, _)| action.as_str())
+            .collect();
+        let extra: Vec<&str> = ours
+            .iter()
+            .filter(|(action, _)| !reference.iter().any(|(theirs, _)| theirs == action))
+            .map(|(action, _)| action.as_str())
+            .collect();
+
+        if !missing.is_empty() || !extra.is_empty() {
+            println!("generators diverge at sfen {}", board.to_fen());
+            println!("moves from the start: {}", path.join(" "));
+            println!("missing: {}", missing.join(" "));
+            println!("extra: {}", extra.join(" "));
+            break;
+        }
+
+        let Some(((action, ours), (_, theirs))) = ours
+            .iter()
+            .zip(&reference)
+            .find(|((_, ours), (_, theirs))| ours != theirs)
+        else {
+            println!("no difference found at depth {depth}");
+            break;
+        };
+        println!("{action}: {ours} nodes, reference has {theirs}");
+        if depth == 1 {
+            println!("generators diverge at sfen {}", board.to_fen());
+            break;
+        }
+
+        let Some(&found) = board
+            .get_actions()
+            .iter()
+            .find(|found| found.to_usi() == *action)
+        else {
+            break;
+        };
+        board.perform_action(found);
+        path.push(action.clone());
+        depth -= 1;
+        reference = next_reference(board, depth);
+    }
+
+    for _ in &path {
+        board.undo_action();
+    }
+}
+
 pub fn perft(board: &mut Board, depth: u8) {
     let start = Instant::now();
     let result = perft_internal(board, depth);
@@ -31,7 +151,27 @@ pub fn perft(board: &mut Board, depth: u8) {
     );
 }
 
-fn perft_internal(board: &mut Board, depth: u8) -> u64 {
+// perft on the generic board of another variant, with the same output as perft and split perft
+pub fn variant_perft(board: &mut VariantBoard, depth: u8, divide: bool) {
+    let start = Instant::now();
+    let count = if divide {
+        let results = board.divide(depth);
+        for (action, nodes) in &results {
+            println!("{action}: {nodes}");
+        }
+        println!();
+        results.iter().map(|(_, nodes)| nodes).sum()
+    } else {
+        board.perft(depth)
+    };
+    println!(
+        "{} nodes {} nps",
+        count,
+        (count as f64 / start.elapsed().as_secs_f64()) as u64
+    );
+}
+
+pub fn perft_internal(board: &mut Board, depth: u8) -> u64 {
     if depth == 0 {
         return 1;
     }
@@ -45,3 +185,268 @@ fn perft_internal(board: &mut Board, depth: u8) -> u64 {
     }
     count
 }
+
+// perft with bulk counting at the leaves, an optional hash table and the root split across threads
+pub fn fast_perft(board: &Board, depth: u8, threads: usize, hash_mb: usize) {
+    let table = (hash_mb > 0).then(|| PerftTable::new(hash_mb));
+    let start = Instant::now();
+    let result = perft_parallel(board, depth, threads, table.as_ref());
+    println!(
+        "{} nodes {} nps",
+        result,
+        result as f32 / start.elapsed().as_secs_f32()
+    );
+}
+
+pub fn perft_parallel(board: &Board, depth: u8, threads: usize, table: Option<&PerftTable>) -> u64 {
+    if threads <= 1 || depth <= 1 {
+        return perft_bulk(&mut board.clone(), depth, table);
+    }
+
+    let mut root = board.clone();
+    let mut actions: Vec<Action> = Vec::new();
+    for action in &root.get_actions() {
+        if root.is_legal(*action) {
+            actions.push(*action);
+        }
+    }
+
+    // threads grab the next root action whenever they finish one
+    let next = AtomicUsize::new(0);
+    thread::scope(|scope| {
+        let handles: Vec<_> = (0..threads)
+            .map(|_| {
+                let mut board = board.clone();
+                let actions = &actions;
+                let next = &next;
+                scope.spawn(move || {
+                    let mut count = 0;
+                    loop {
+                        let idx = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(&action) = actions.get(idx) else {
+                            break;
+                        };
+                        board.perform_action(action);
+                        count += perft_bulk(&mut board, depth - 1, table);
+                        board.undo_action();
+                    }
+                    count
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("perft thread panicked"))
+            .sum()
+    })
+}
+
+pub fn perft_bulk(board: &mut Board, depth: u8, table: Option<&PerftTable>) -> u64 {
+    if depth == 0 {
+        return 1;
+    }
+    let actions = board.get_actions();
+    let mut count = 0;
+    if depth == 1 {
+        for action in &actions {
+            count += board.is_legal(*action) as u64;
+        }
+        return count;
+    }
+
+    if let Some(nodes) = table.and_then(|table| table.probe(board.hash(), depth)) {
+        return nodes;
+    }
+    for action in &actions {
+        if board.perform_action(*action) {
+            count += perft_bulk(board, depth - 1, table);
+            board.undo_action();
+        }
+    }
+    if let Some(table) = table {
+        table.store(board.hash(), depth, count);
+    }
+    count
+}
+
+const DEPTH_BITS: u32 = 8;
+
+// lockless, each entry is stored as (key ^ data, data) so that torn writes from other threads fail
+// the key check instead of returning garbage
+pub struct PerftTable {
+    entries: Vec<[AtomicU64; 2]>,
+}
+
+impl PerftTable {
+    pub fn new(size_mb: usize) -> Self {
+        let len = (size_mb * 1024 * 1024 / std::mem::size_of::<[AtomicU64; 2]>()).max(1);
+        Self {
+            entries: (0..len)
+                .map(|_| [AtomicU64::new(0), AtomicU64::new(0)])
+                .collect(),
+        }
+    }
+
+    fn index(&self, hash: u64) -> usize {
+        ((hash as u128 * self.entries.len() as u128) >> 64) as usize
+    }
+
+    pub fn probe(&self, hash: u64, depth: u8) -> Option<u64> {
+        let entry = &self.entries[self.index(hash)];
+        let key = entry[0].load(Ordering::Relaxed);
+        let data = entry[1].load(Ordering::Relaxed);
+        (key ^ data == hash && (data & ((1 << DEPTH_BITS) - 1)) as u8 == depth)
+            .then_some(data >> DEPTH_BITS)
+    }
+
+    pub fn store(&self, hash: u64, depth: u8, nodes: u64) {
+        let entry = &self.entries[self.index(hash)];
+        let data = (nodes << DEPTH_BITS) | depth as u64;
+        entry[0].store(hash ^ data, Ordering::Relaxed);
+        entry[1].store(data, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_divide, perft_internal, perft_parallel, PerftTable};
+    use crate::{board::Board, handicap::Handicap};
+
+    fn check(fen: &str, expected: &[u64]) {
+        // the pext tables can only be used if the cpu actually has bmi2
+        #[cfg(feature = "pext")]
+        if !std::is_x86_feature_detected!("bmi2") {
+            return;
+        }
+        for (i, &nodes) in expected.iter().enumerate() {
+            let depth = i as u8 + 1;
+            let mut board = Board::default();
+            board.load_fen(fen);
+            assert_eq!(
+                perft_internal(&mut board, depth),
+                nodes,
+                "{fen} at depth {depth}"
+            );
+            let table = PerftTable::new(16);
+            assert_eq!(
+                perft_parallel(&board, depth, 4, Some(&table)),
+                nodes,
+                "{fen} at depth {depth} with bulk counting"
+            );
+        }
+    }
+
+    #[test]
+    fn startpos() {
+        check(
+            "lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL b - 1",
+            &[30, 900, 25470, 719731, 19861490],
+        );
+    }
+
+    // a gote king on its back rank used to look for a pawn attacker off the board
+    #[test]
+    fn king_on_the_last_rank() {
+        check("9/9/9/9/4K4/9/9/9/4k4 w - 1", &[5, 40]);
+    }
+
+    // gote gives the handicap and moves first in all of these. there are no published counts, so
+    // every depth was checked against scripts/perft.py, which generates moves independently
+    #[test]
+    fn handicaps() {
+        let expected: [(Handicap, [u64; 4]); 9] = [
+            (Handicap::Lance, [30, 900, 25530, 721433]),
+            (Handicap::Bishop, [33, 990, 29910, 846566]),
+            (Handicap::Rook, [25, 750, 18570, 524461]),
+            (Handicap::RookLance, [25, 750, 18570, 524465]),
+            (Handicap::TwoPiece, [26, 780, 19740, 558731]),
+            (Handicap::FourPiece, [24, 720, 16800, 475521]),
+            (Handicap::SixPiece, [24, 720, 16740, 473823]),
+            (Handicap::EightPiece, [20, 600, 12000, 339669]),
+            (Handicap::TenPiece, [14, 420, 5880, 166449]),
+        ];
+        for (handicap, nodes) in expected {
+            check(handicap.fen(), &nodes);
+        }
+    }
+
+    #[test]
+    fn matsuri() {
+        check(
+            "l6nl/5+P1gk/2np1S3/p1p4Pp/3P2Sp1/1PPb2P1P/P5GS1/R8/LN4bKL w RGgsn5p 1",
+            &[207, 28684, 4809015],
+        );
+    }
+
+    // the position with the most legal moves, also checks 2 digit hand counts
+    #[test]
+    fn max_moves() {
+        check(
+            "R8/2K1S1SSk/4B4/9/9/9/9/9/1L1L1L3 b RBGSNLP3g3n17p 1",
+            &[593, 105677, 53393368],
+        );
+    }
+
+    // pawn, lance and knight drops on dead end ranks, and nifu on the files with pawns
+    #[test]
+    fn drops() {
+        check("4k4/p8/9/9/9/9/9/8P/4K4 b NLPnlp 1", &[198, 36843, 4752607]);
+    }
+
+    // forced and optional promotions for both sides
+    #[test]
+    fn promotions() {
+        check(
+            "4k4/1P5SL/6N2/2B6/9/9/6s2/9/4K4 b Pr 1",
+            &[91, 7436, 318424],
+        );
+        check(
+            "4k4/1P5SL/6N2/2B6/9/9/6s2/9/4K4 w Pr 1",
+            &[86, 6270, 230119],
+        );
+    }
+
+    // a rook and a bishop pinning pieces to the king
+    #[test]
+    fn pins() {
+        check(
+            "4k4/9/4r4/9/b3S4/9/2G6/9/4K4 b P 1",
+            &[74, 1587, 25103, 761540],
+        );
+    }
+
+    // bishop check with the rook lined up behind two pieces
+    #[test]
+    fn evasions() {
+        check(
+            "4k4/9/4r4/9/4S4/2b6/4G4/3PK4/5L3 b P 1",
+            &[6, 182, 13100, 459467],
+        );
+    }
+
+    #[test]
+    fn uchifuzume() {
+        // P*1b would be mate
+        check("7nk/9/7G1/9/9/9/9/9/K8 b P 1", &[78, 158, 2948, 32309]);
+        // P*1b is only check, the king can run to 2a
+        check("8k/9/7G1/9/9/9/9/9/K8 b P 1", &[79, 81, 1636, 16396]);
+        // P*1b would be mate, the gold can't take the pawn because it is pinned
+        check(
+            "8k/6Sg1/9/5B1N1/9/9/9/9/K8 b P 1",
+            &[97, 155, 10169, 162972],
+        );
+    }
+
+    #[test]
+    fn divide_listing() {
+        let listing = "7g7f: 30\n2h2g 30\np*5e : 2\n\nNodes: 62\nTotal: 62\nNodes searched: 62";
+        assert_eq!(
+            parse_divide(listing.lines()),
+            vec![
+                ("2h2g".to_owned(), 30),
+                ("7g7f".to_owned(), 30),
+                ("P*5e".to_owned(), 2)
+            ]
+        );
+    }
+}
@@ -0,0 +1,627 @@
+//! CSA, the record format of the computer shogi association, used by floodgate and the
+//! tournament servers. Actions look like `+7776FU` and name the piece after the action, so a
+//! promotion shows up as a change of piece.
+
+use crate::{
+    board::{Board, STARTPOS},
+    handicap::Handicap,
+    types::{action::Action, piece::Piece, square::Square},
+};
+
+use super::{perform_checked, Game, RecordError, Termination};
+
+// indexed by piece type
+pub(crate) const PIECE_CODES: [&str; 14] = [
+    "FU", "KY", "KE", "GI", "KA", "HI", "KI", "OU", "TO", "NY", "NK", "NG", "UM", "RY",
+];
+
+// how many of each unpromoted piece type there are, for AL
+const PIECE_COUNTS: [u8; 8] = [18, 4, 4, 4, 2, 2, 4, 2];
+
+// the order hands are written in, same as sfen
+const HAND_ORDER: [Piece; 7] = [
+    Piece::ROOK,
+    Piece::BISHOP,
+    Piece::GOLD,
+    Piece::SILVER,
+    Piece::KNIGHT,
+    Piece::LANCE,
+    Piece::PAWN,
+];
+
+/// Reads a CSA record, checking that every action is legal. Only the first game of a file with
+/// several games separated by `/` is read.
+pub fn parse_csa(text: &str) -> Result<Game, RecordError> {
+    let mut game = Game::default();
+    let mut position = PositionReader::default();
+    let mut board: Option<Board> = None;
+
+    for (i, line) in text.trim_start_matches('\u{feff}').lines().enumerate() {
+        let number = i + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('V') {
+            continue;
+        }
+        if line == "/" {
+            break;
+        }
+        if let Some(comment) = line.strip_prefix('\'') {
+            game.comments.push((game.actions.len(), comment.to_owned()));
+            continue;
+        }
+
+        // several statements can share a line
+        for statement in line.split(',') {
+            let statement = statement.trim();
+            if let Some(name) = statement.strip_prefix("N+") {
+                game.headers.push(("N+".to_owned(), name.to_owned()));
+            } else if let Some(name) = statement.strip_prefix("N-") {
+                game.headers.push(("N-".to_owned(), name.to_owned()));
+            } else if statement.starts_with('$') {
+                let (key, value) = statement.split_once(':').unwrap_or((statement, ""));
+                game.headers.push((key.to_owned(), value.to_owned()));
+            } else if statement.starts_with('P') {
+                if board.is_some() {
+                    return Err(RecordError::new(number, "position after the first action"));
+                }
+                position.read_line(statement, number)?;
+            } else if let Some(time) = statement.strip_prefix('T') {
+                // some servers send fractions of seconds, only whole seconds are kept
+                let seconds = time
+                    .split('.')
+                    .next()
+                    .and_then(|s| s.parse().ok())
+                    .ok_or_else(|| RecordError::new(number, "invalid time"))?;
+                if let Some(last) = game.times.last_mut() {
+                    *last = Some(seconds);
+                }
+            } else if let Some(code) = statement.strip_prefix('%') {
+                let stm = board.as_ref().map_or(position.stm, Board::stm);
+                game.termination =
+                    Some(termination_from_csa(code, stm).ok_or_else(|| {
+                        RecordError::new(number, format!("unknown code %{code}"))
+                    })?);
+            } else if statement == "+" || statement == "-" {
+                position.stm = u8::from(statement == "-");
+            } else if statement.starts_with(['+', '-']) {
+                if board.is_none() {
+                    game.start_fen = position.checked_fen(number)?;
+                    board = Some(Board::from_fen(&game.start_fen));
+                }
+                let board = board.as_mut().expect("the board was just set up");
+                let action = parse_csa_action(board, statement).ok_or_else(|| {
+                    RecordError::new(number, format!("invalid action {statement}"))
+                })?;
+                perform_checked(board, action, number)?;
+                game.actions.push(action);
+                game.times.push(None);
+            } else {
+                return Err(RecordError::new(
+                    number,
+                    format!("unknown line {statement}"),
+                ));
+            }
+        }
+    }
+
+    if board.is_none() {
+        game.start_fen = position.checked_fen(text.lines().count())?;
+    }
+    Ok(game)
+}
+
+/// Writes a game as CSA version 2.2.
+pub fn write_csa(game: &Game) -> String {
+    let mut output = "V2.2\n".to_owned();
+    for (key, value) in &game.headers {
+        // kif names are the only other headers worth keeping
+        match key.as_str() {
+            "N+" | "先手" | "下手" => output += &format!("N+{value}\n"),
+            "N-" | "後手" | "上手" => output += &format!("N-{value}\n"),
+            _ if key.starts_with('$') => output += &format!("{key}:{value}\n"),
+            _ => {}
+        }
+    }
+    output += &fen_to_csa(&game.start_fen);
+
+    let mut board = Board::from_fen(&game.start_fen);
+    for (_, comment) in game.comments.iter().filter(|(at, _)| *at == 0) {
+        output += &format!("'{comment}\n");
+    }
+    for (ply, action) in game.actions.iter().enumerate() {
+        output += &csa_action(&board, *action);
+        output.push('\n');
+        if let Some(time) = game.times.get(ply).copied().flatten() {
+            output += &format!("T{time}\n");
+        }
+        for (_, comment) in game.comments.iter().filter(|(at, _)| *at == ply + 1) {
+            output += &format!("'{comment}\n");
+        }
+        board.perform_action(*action);
+    }
+
+    if let Some(termination) = game.termination {
+        output += &format!("%{}\n", termination_to_csa(termination, board.stm()));
+    }
+    output
+}
+
+/// Maps a CSA terminal code (without the `%`) to a termination, given the side to move when it
+/// was sent.
+pub fn termination_from_csa(code: &str, stm: u8) -> Option<Termination> {
+    let illegal = |side: u8| {
+        if side == stm {
+            Termination::IllegalMove
+        } else {
+            Termination::OpponentIllegalMove
+        }
+    };
+    Some(match code {
+        "TORYO" => Termination::Resign,
+        "TSUMI" => Termination::Checkmate,
+        "SENNICHITE" => Termination::Sennichite,
+        "JISHOGI" => Termination::Jishogi,
+        "HIKIWAKE" => Termination::Draw,
+        "KACHI" => Termination::EnteringKing,
+        "TIME_UP" => Termination::Timeout,
+        "ILLEGAL_MOVE" => Termination::IllegalMove,
+        "+ILLEGAL_ACTION" => illegal(0),
+        "-ILLEGAL_ACTION" => illegal(1),
+        "MAX_MOVES" => Termination::MaxMoves,
+        "CHUDAN" | "FUZUMI" | "ERROR" => Termination::Interrupted,
+        _ => return None,
+    })
+}
+
+/// The CSA terminal code (without the `%`) for a termination, given the side to move after the
+/// last action.
+pub fn termination_to_csa(termination: Termination, stm: u8) -> String {
+    let sign = |side: u8| if side == 0 { '+' } else { '-' };
+    match termination {
+        Termination::Resign => "TORYO".to_owned(),
+        Termination::Checkmate => "TSUMI".to_owned(),
+        Termination::Sennichite => "SENNICHITE".to_owned(),
+        Termination::Jishogi => "JISHOGI".to_owned(),
+        Termination::Draw => "HIKIWAKE".to_owned(),
+        Termination::EnteringKing => "KACHI".to_owned(),
+        Termination::Timeout => "TIME_UP".to_owned(),
+        Termination::IllegalMove => format!("{}ILLEGAL_ACTION", sign(stm)),
+        Termination::OpponentIllegalMove => format!("{}ILLEGAL_ACTION", sign(1 - stm)),
+        Termination::MaxMoves => "MAX_MOVES".to_owned(),
+        Termination::Interrupted => "CHUDAN".to_owned(),
+    }
+}
+
+/// An action in CSA notation, like `+7776FU` or `-0055KA`. The board has to be in the position
+/// before the action.
+pub fn csa_action(board: &Board, action: Action) -> String {
+    let sign = if board.stm() == 0 { '+' } else { '-' };
+    let to = action.to();
+    if action.is_drop() {
+        return format!(
+            "{sign}00{}{}",
+            csa_square(to),
+            PIECE_CODES[action.piece().piece().as_usize()]
+        );
+    }
+    let from = action.from();
+    let mut piece = board.piece_on_square(from).piece();
+    if action.is_promo() {
+        piece = piece.promote();
+    }
+    format!(
+        "{sign}{}{}{}",
+        csa_square(from),
+        csa_square(to),
+        PIECE_CODES[piece.as_usize()]
+    )
+}
+
+/// Reads an action in CSA notation, anything after the piece (like a time) is ignored. Returns
+/// None if it is malformed or for the wrong side, but doesn't check that it is legal.
+pub fn parse_csa_action(board: &Board, text: &str) -> Option<Action> {
+    let text = text.get(..7).filter(|text| text.is_ascii())?;
+    let side = match text.as_bytes()[0] {
+        b'+' => 0,
+        b'-' => 1,
+        _ => return None,
+    };
+    if side != board.stm() {
+        return None;
+    }
+    let to = parse_square(&text[3..5])?;
+    let piece = parse_piece(&text[5..7])?;
+    if &text[1..3] == "00" {
+        return Some(Action::new_drop(piece.as_stm(side), to));
+    }
+    let from = parse_square(&text[1..3])?;
+    let moving = board.piece_on_square(from);
+    if moving == Piece::NONE {
+        return None;
+    }
+    Some(Action::new_move(from, to, moving.piece() != piece))
+}
+
+/// The position part of a CSA record for an SFEN, `PI` for the starting position, `PI` followed
+/// by the squares that are emptied for handicaps, and a `P1` to `P9` board with hands otherwise.
+pub fn fen_to_csa(fen: &str) -> String {
+    let board = Board::from_fen(fen);
+    let sign = |side: u8| if side == 0 { '+' } else { '-' };
+    let mut output = String::new();
+
+    let same_position = |a: &str, b: &str| {
+        a.split_ascii_whitespace()
+            .take(3)
+            .eq(b.split_ascii_whitespace().take(3))
+    };
+    if same_position(fen, STARTPOS) {
+        output += "PI\n";
+    } else if Handicap::from_fen(fen).is_some() {
+        let start = Board::from_fen(STARTPOS);
+        output += "PI";
+        for sq in (0..81).map(Square) {
+            let piece = start.piece_on_square(sq);
+            if piece != Piece::NONE && board.piece_on_square(sq) == Piece::NONE {
+                output += &csa_square(sq);
+                output += PIECE_CODES[piece.piece().as_usize()];
+            }
+        }
+        output.push('\n');
+    } else {
+        for rank in (0..9).rev() {
+            output += &format!("P{}", 9 - rank);
+            for file in 0..9 {
+                let piece = board.piece_on_square(Square::from_rf(rank, file));
+                if piece == Piece::NONE {
+                    output += " * ";
+                } else {
+                    output.push(sign(piece.side()));
+                    output += PIECE_CODES[piece.piece().as_usize()];
+                }
+            }
+            output.push('\n');
+        }
+        for side in 0..2 {
+            let hand = board.hand(side);
+            if hand.is_empty() {
+                continue;
+            }
+            output += &format!("P{}", sign(side));
+            for piece in HAND_ORDER {
+                for _ in 0..hand.num(piece) {
+                    output += &format!("00{}", PIECE_CODES[piece.as_usize()]);
+                }
+            }
+            output.push('\n');
+        }
+    }
+    output.push(sign(board.stm()));
+    output.push('\n');
+    output
+}
+
+/// The SFEN for the position part of a CSA record, everything that isn't a position line is
+/// ignored.
+pub fn csa_to_fen(text: &str) -> Result<String, RecordError> {
+    let mut position = PositionReader::default();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.starts_with('P') {
+            position.read_line(line, i + 1)?;
+        } else if line == "+" || line == "-" {
+            position.stm = u8::from(line == "-");
+        }
+    }
+    position.checked_fen(text.lines().count())
+}
+
+fn csa_square(sq: Square) -> String {
+    format!("{}{}", 9 - sq.file(), 9 - sq.rank())
+}
+
+fn parse_square(text: &str) -> Option<Square> {
+    let mut digits = text.chars().map(|c| c.to_digit(10));
+    let file = digits.next()?? as u8;
+    let rank = digits.next()?? as u8;
+    ((1..=9).contains(&file) && (1..=9).contains(&rank))
+        .then(|| Square::from_rf(9 - rank, 9 - file))
+}
+
+pub(crate) fn parse_piece(code: &str) -> Option<Piece> {
+    PIECE_CODES
+        .iter()
+        .position(|c| *c == code)
+        .map(|i| Piece(i as u8))
+}
+
+/// Builds a starting position from `PI`, `P1`..`P9` and `P+`/`P-` lines.
+#[derive(Debug, Clone)]
+pub(crate) struct PositionReader {
+    squares: [Piece; 81],
+    hands: [[u8; 8]; 2],
+    pub(crate) stm: u8,
+}
+
+impl Default for PositionReader {
+    fn default() -> Self {
+        let mut reader = Self {
+            squares: [Piece::NONE; 81],
+            hands: [[0; 8]; 2],
+            stm: 0,
+        };
+        reader.set_fen(STARTPOS);
+        reader
+    }
+}
+
+impl PositionReader {
+    fn set_fen(&mut self, fen: &str) {
+        let board = Board::from_fen(fen);
+        for (i, square) in self.squares.iter_mut().enumerate() {
+            *square = board.piece_on_square(Square(i as u8));
+        }
+        for side in 0..2 {
+            for piece in HAND_ORDER {
+                self.hands[side as usize][piece.as_usize()] = board.hand(side).num(piece);
+            }
+        }
+        self.stm = board.stm();
+    }
+
+    pub(crate) fn read_line(&mut self, line: &str, number: usize) -> Result<(), RecordError> {
+        let error = |message: &str| RecordError::new(number, format!("{message}: {line}"));
+        let rest = line
+            .strip_prefix('P')
+            .ok_or_else(|| error("not a position line"))?;
+
+        // the starting position, with the pieces that follow taken off
+        if let Some(removed) = rest.strip_prefix('I') {
+            self.set_fen(STARTPOS);
+            for chunk in removed.as_bytes().chunks(4) {
+                let chunk = std::str::from_utf8(chunk).map_err(|_| error("invalid square"))?;
+                let sq = chunk
+                    .get(..2)
+                    .and_then(parse_square)
+                    .ok_or_else(|| error("invalid square"))?;
+                self.squares[sq.as_usize()] = Piece::NONE;
+            }
+            return Ok(());
+        }
+
+        // a full rank, nine cells of three characters
+        if let Some(rank) = rest.chars().next().and_then(|c| c.to_digit(10)) {
+            // a board block replaces the starting position
+            if rank == 1 {
+                self.squares = [Piece::NONE; 81];
+                self.hands = [[0; 8]; 2];
+            }
+            // trailing spaces of an empty last cell are often trimmed away
+            let cells = format!("{:<27}", &rest[1..]);
+            if !(1..=9).contains(&rank) || cells.len() != 27 {
+                return Err(error("invalid rank"));
+            }
+            for file in 0..9 {
+                let cell = cells
+                    .get(file * 3..file * 3 + 3)
+                    .ok_or_else(|| error("invalid cell"))?;
+                let sq = Square::from_rf(9 - rank as u8, file as u8);
+                self.squares[sq.as_usize()] = match cell.as_bytes()[0] {
+                    b'+' | b'-' => parse_piece(&cell[1..])
+                        .ok_or_else(|| error("invalid piece"))?
+                        .as_stm(u8::from(cell.starts_with('-'))),
+                    _ => Piece::NONE,
+                };
+            }
+            return Ok(());
+        }
+
+        // single pieces, 00 puts them in hand and AL hands out everything left
+        let side = match rest.as_bytes().first() {
+            Some(b'+') => 0,
+            Some(b'-') => 1,
+            _ => return Err(error("unknown position line")),
+        };
+        for chunk in rest.as_bytes()[1..].chunks(4) {
+            let chunk = std::str::from_utf8(chunk).map_err(|_| error("invalid piece"))?;
+            if chunk.len() != 4 {
+                return Err(error("invalid piece"));
+            }
+            if chunk == "00AL" {
+                for piece in HAND_ORDER {
+                    let left = PIECE_COUNTS[piece.as_usize()]
+                        .checked_sub(self.count(piece))
+                        .ok_or_else(|| error("too many pieces"))?;
+                    self.hands[side as usize][piece.as_usize()] += left;
+                }
+                continue;
+            }
+            let piece = parse_piece(&chunk[2..]).ok_or_else(|| error("invalid piece"))?;
+            if &chunk[..2] == "00" {
+                if piece.piece() == Piece::KING || piece.piece() >= Piece::PROMO_PAWN {
+                    return Err(error("only unpromoted pieces can be in hand"));
+                }
+                let count = &mut self.hands[side as usize][piece.as_usize()];
+                *count = count.saturating_add(1);
+            } else {
+                let sq = parse_square(&chunk[..2]).ok_or_else(|| error("invalid square"))?;
+                self.squares[sq.as_usize()] = piece.as_stm(side);
+            }
+        }
+        Ok(())
+    }
+
+    // how many of a piece type are on the board or in hand, promoted or not
+    fn count(&self, piece: Piece) -> u8 {
+        let on_board = self
+            .squares
+            .iter()
+            .filter(|p| **p != Piece::NONE && p.piece().unpromote() == piece)
+            .count() as u8;
+        on_board
+            .saturating_add(self.hands[0][piece.as_usize()])
+            .saturating_add(self.hands[1][piece.as_usize()])
+    }
+
+    // the sfen, as long as it is a position the board can hold, for the line the position ends
+    pub(crate) fn checked_fen(&self, number: usize) -> Result<String, RecordError> {
+        let fen = self.to_fen();
+        Board::try_from_fen(&fen)
+            .map_err(|e| RecordError::new(number, format!("invalid position: {e}")))?;
+        Ok(fen)
+    }
+
+    pub(crate) fn to_fen(&self) -> String {
+        let mut fen = String::new();
+        for rank in (0..9).rev() {
+            let mut empty = 0;
+            for file in 0..9 {
+                let piece = self.squares[Square::from_rf(rank, file).as_usize()];
+                if piece == Piece::NONE {
+                    empty += 1;
+                    continue;
+                }
+                if empty > 0 {
+                    fen += &empty.to_string();
+                    empty = 0;
+                }
+                fen += &piece.to_string();
+            }
+            if empty > 0 {
+                fen += &empty.to_string();
+            }
+            if rank != 0 {
+                fen.push('/');
+            }
+        }
+
+        fen += if self.stm == 0 { " b " } else { " w " };
+        let mut hands = String::new();
+        for side in 0..2 {
+            for piece in HAND_ORDER {
+                let count = self.hands[side as usize][piece.as_usize()];
+                if count > 1 {
+                    hands += &count.to_string();
+                }
+                if count > 0 {
+                    hands += &piece.as_stm(side).to_string();
+                }
+            }
+        }
+        if hands.is_empty() {
+            hands.push('-');
+        }
+        fen + &hands + " 1"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{csa_to_fen, fen_to_csa, parse_csa, write_csa};
+    use crate::{
+        board::STARTPOS,
+        handicap::Handicap,
+        record::{GameResult, Termination},
+    };
+
+    #[test]
+    fn csa() {
+        let csa = "\
+V2.2
+N+sente
+N-gote
+$EVENT:test
+PI
++
++7776FU
+T3
+-3334FU,T2
+'* 30 8h2b+ 3a2b
++8822UM
+-3122GI
++0045KA
+%TORYO
+";
+        let game = parse_csa(csa).unwrap();
+        let usi: Vec<String> = game.actions.iter().map(|a| a.to_usi()).collect();
+        assert_eq!(usi, ["7g7f", "3c3d", "8h2b+", "3a2b", "B*4e"]);
+        assert_eq!(game.header("$EVENT"), Some("test"));
+        assert_eq!(game.times[..3], [Some(3), Some(2), None]);
+        assert_eq!(game.comments, [(2, "* 30 8h2b+ 3a2b".to_owned())]);
+        assert_eq!(game.termination, Some(Termination::Resign));
+        assert_eq!(game.result(), Some(GameResult::SenteWin));
+
+        assert_eq!(parse_csa(&write_csa(&game)).unwrap(), game);
+    }
+
+    #[test]
+    fn positions() {
+        assert_eq!(csa_to_fen("PI\n+\n").unwrap(), STARTPOS);
+        assert_eq!(
+            csa_to_fen("PI11KY\n-\n").unwrap(),
+            "lnsgkgsn1/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL w - 1"
+        );
+        // handicaps are written as the pieces taken off the starting position
+        assert_eq!(fen_to_csa(Handicap::TwoPiece.fen()), "PI82HI22KA\n-\n");
+        for handicap in Handicap::ALL {
+            assert_eq!(
+                csa_to_fen(&fen_to_csa(handicap.fen())).unwrap(),
+                handicap.fen()
+            );
+        }
+
+        for fen in [
+            "8k/9/7G1/9/9/9/9/9/K8 b G2Prs 1",
+            "4k4/9/9/9/9/9/9/9/4K4 w 4G18p 1",
+        ] {
+            assert_eq!(csa_to_fen(&fen_to_csa(fen)).unwrap(), fen);
+        }
+
+        // everything not placed goes to gote
+        let mut ranks = vec![" * ".repeat(9); 9];
+        ranks[0] = " * ".repeat(8) + "-OU";
+        ranks[2] = " * ".repeat(7) + "+KI * ";
+        ranks[8] = "+OU".to_owned() + &" * ".repeat(8);
+        let mut csa: String = (0..9)
+            .map(|i| format!("P{}{}\n", i + 1, ranks[i]))
+            .collect();
+        csa += "P+00KI00FU00FU\nP-00AL\n+\n";
+        assert_eq!(
+            csa_to_fen(&csa).unwrap(),
+            "8k/9/7G1/9/9/9/9/9/K8 b G2P2r2b2g4s4n4l16p 1"
+        );
+    }
+
+    #[test]
+    fn draws() {
+        let game = parse_csa("PI\n+\n+7776FU\n%HIKIWAKE\n").unwrap();
+        assert_eq!(game.termination, Some(Termination::Draw));
+        assert_eq!(game.result(), Some(GameResult::Draw));
+        assert!(write_csa(&game).ends_with("%HIKIWAKE\n"));
+        // an impasse stays an impasse
+        let game = parse_csa("PI\n+\n+7776FU\n%JISHOGI\n").unwrap();
+        assert_eq!(game.termination, Some(Termination::Jishogi));
+    }
+
+    #[test]
+    fn illegal_actions_are_rejected() {
+        assert_eq!(parse_csa("PI\n+\n+7775FU\n").unwrap_err().line, 3);
+        assert!(parse_csa("PI\n+\n-3334FU\n").is_err());
+        assert!(parse_csa("PI\n+\n+1あ2FU\n").is_err());
+    }
+
+    #[test]
+    fn impossible_positions_are_rejected() {
+        // no kings, two sente kings, more pawns in hand than exist and more than exist in all
+        let nineteen_pawns = format!("P+59OU\nP-51OU\nP+{}\n+\n", "00FU".repeat(19));
+        for csa in [
+            "PI59OU51OU\n+\n",
+            "P+59OU\nP+11OU\nP-51OU\n+\n",
+            &nineteen_pawns,
+            "PI\nP+00FU\nP-00AL\n+\n",
+        ] {
+            assert!(csa_to_fen(csa).is_err(), "{csa}");
+            assert!(parse_csa(csa).is_err(), "{csa}");
+            assert!(parse_csa(&format!("{csa}+5958OU\n")).is_err(), "{csa}");
+        }
+    }
+}
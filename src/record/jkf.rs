@@ -0,0 +1,563 @@
+//! JSON Kifu Format, the record format of json-kifu-format and the web viewers built on it.
+//! Actions are objects with `from`/`to` squares as `{"x": file, "y": rank}`, and alternative
+//! lines are stored as `forks` on the action they replace.
+
+use crate::{
+    board::Board,
+    types::{action::Action, piece::Piece, square::Square},
+};
+
+use super::{
+    csa::{parse_piece, termination_from_csa, termination_to_csa, PIECE_CODES},
+    json::{self, Value},
+    kif::{handicap_fen, handicap_name},
+    perform_checked, Game, RecordError, Variation,
+};
+
+// jkf presets and the kif names of the same handicaps
+const PRESETS: [(&str, &str); 10] = [
+    ("HIRATE", "平手"),
+    ("KY", "香落ち"),
+    ("KA", "角落ち"),
+    ("HI", "飛車落ち"),
+    ("HIKY", "飛香落ち"),
+    ("2", "二枚落ち"),
+    ("4", "四枚落ち"),
+    ("6", "六枚落ち"),
+    ("8", "八枚落ち"),
+    ("10", "十枚落ち"),
+];
+
+const HAND_KINDS: [Piece; 7] = [
+    Piece::PAWN,
+    Piece::LANCE,
+    Piece::KNIGHT,
+    Piece::SILVER,
+    Piece::GOLD,
+    Piece::BISHOP,
+    Piece::ROOK,
+];
+
+/// Reads a JKF record, checking that every action is legal. Errors in the json itself point at
+/// the line they are on, errors in the record have line 0 and name the action instead.
+pub fn parse_jkf(text: &str) -> Result<Game, RecordError> {
+    let root = json::parse(text).map_err(|offset| {
+        let line = text.as_bytes()[..offset]
+            .iter()
+            .filter(|&&b| b == b'\n')
+            .count()
+            + 1;
+        RecordError::new(line, "invalid json")
+    })?;
+    let error = |message: String| RecordError::new(0, message);
+
+    let mut game = Game::default();
+    if let Some(header) = root.get("header").and_then(Value::as_object) {
+        for (key, value) in header {
+            let value = value.as_str().unwrap_or_default();
+            game.headers.push((key.clone(), value.to_owned()));
+        }
+    }
+    if let Some(initial) = root.get("initial") {
+        game.start_fen = initial_to_fen(initial).map_err(error)?;
+    }
+
+    let moves = root
+        .get("moves")
+        .and_then(Value::as_array)
+        .unwrap_or_default();
+    let mut board = Board::from_fen(&game.start_fen);
+    let mut lines = Vec::new();
+    let main =
+        read_line(moves, &mut board, Vec::new(), &mut game, &mut lines, true).map_err(error)?;
+
+    game.times.resize(main.len(), None);
+    for actions in lines {
+        let ply = actions
+            .iter()
+            .zip(&main)
+            .take_while(|(a, b)| a == b)
+            .count();
+        game.variations.push(Variation { ply, actions });
+    }
+    game.actions = main;
+    Ok(game)
+}
+
+// reads the actions of one line, appending them to `actions`, and collects every fork below it
+fn read_line(
+    moves: &[Value],
+    board: &mut Board,
+    mut actions: Vec<Action>,
+    game: &mut Game,
+    lines: &mut Vec<Vec<Action>>,
+    main: bool,
+) -> Result<Vec<Action>, String> {
+    for entry in moves {
+        let Some(object) = entry.get("move") else {
+            if main {
+                add_comments(entry, game, actions.len());
+                if let Some(special) = entry.get("special").and_then(Value::as_str) {
+                    game.termination = Some(
+                        termination_from_csa(special, board.stm())
+                            .ok_or_else(|| format!("unknown special {special}"))?,
+                    );
+                }
+            }
+            continue;
+        };
+
+        // forks replace this action, so they start from the board before it
+        if let Some(forks) = entry.get("forks").and_then(Value::as_array) {
+            for fork in forks {
+                let fork = fork.as_array().ok_or("forks must be arrays of moves")?;
+                let mut fork_board = board.clone();
+                let line = read_line(fork, &mut fork_board, actions.clone(), game, lines, false)?;
+                lines.push(line);
+            }
+        }
+
+        let number = actions.len() + 1;
+        let action = read_action(object, board, actions.last().copied())
+            .map_err(|message| format!("action {number}: {message}"))?;
+        perform_checked(board, action, 0).map_err(|e| format!("action {number}: {}", e.message))?;
+        actions.push(action);
+        if main {
+            game.times.resize(actions.len() - 1, None);
+            game.times.push(entry.get("time").and_then(read_time));
+            add_comments(entry, game, actions.len());
+        }
+    }
+    Ok(actions)
+}
+
+fn add_comments(entry: &Value, game: &mut Game, ply: usize) {
+    for comment in entry
+        .get("comments")
+        .and_then(Value::as_array)
+        .unwrap_or_default()
+    {
+        if let Some(comment) = comment.as_str() {
+            game.comments.push((ply, comment.to_owned()));
+        }
+    }
+}
+
+fn read_time(time: &Value) -> Option<u32> {
+    let now = time.get("now")?;
+    let part = |key| now.get(key).and_then(Value::as_u32).unwrap_or(0);
+    Some(part("h") * 3600 + part("m") * 60 + part("s"))
+}
+
+fn read_square(value: &Value) -> Option<Square> {
+    let file = value.get("x")?.as_u32()?;
+    let rank = value.get("y")?.as_u32()?;
+    ((1..=9).contains(&file) && (1..=9).contains(&rank))
+        .then(|| Square::from_rf(9 - rank as u8, 9 - file as u8))
+}
+
+fn read_action(object: &Value, board: &Board, prev: Option<Action>) -> Result<Action, String> {
+    let stm = board.stm();
+    if let Some(color) = object.get("color").and_then(Value::as_u32) {
+        if color != u32::from(stm) {
+            return Err("wrong colour".to_owned());
+        }
+    }
+    let to = match object.get("to") {
+        Some(to) => read_square(to).ok_or("invalid destination")?,
+        None if object.get("same").and_then(Value::as_bool) == Some(true) => {
+            prev.ok_or("same with no previous action")?.to()
+        }
+        None => return Err("no destination".to_owned()),
+    };
+    let piece = object
+        .get("piece")
+        .and_then(Value::as_str)
+        .and_then(parse_piece)
+        .ok_or("invalid piece")?;
+
+    let Some(from) = object.get("from") else {
+        return Ok(Action::new_drop(piece.as_stm(stm), to));
+    };
+    let from = read_square(from).ok_or("invalid origin")?;
+    if board.piece_on_square(from).piece() != piece {
+        return Err(format!(
+            "no {} on the origin square",
+            PIECE_CODES[piece.as_usize()]
+        ));
+    }
+    let promote = object.get("promote").and_then(Value::as_bool) == Some(true);
+    Ok(Action::new_move(from, to, promote))
+}
+
+fn initial_to_fen(initial: &Value) -> Result<String, String> {
+    let preset = initial
+        .get("preset")
+        .and_then(Value::as_str)
+        .ok_or("initial position has no preset")?;
+    if preset != "OTHER" {
+        let (_, name) = PRESETS
+            .iter()
+            .find(|(p, _)| *p == preset)
+            .ok_or_else(|| format!("unknown preset {preset}"))?;
+        let fen = handicap_fen(name).expect("every preset is a kif handicap");
+        return Ok(fen.to_owned());
+    }
+
+    let data = initial.get("data").ok_or("OTHER preset without data")?;
+    let columns = data
+        .get("board")
+        .and_then(Value::as_array)
+        .filter(|columns| columns.len() == 9)
+        .ok_or("board must have 9 files")?;
+    let mut squares = [Piece::NONE; 81];
+    for (x, column) in columns.iter().enumerate() {
+        let column = column
+            .as_array()
+            .filter(|column| column.len() == 9)
+            .ok_or("each file needs 9 squares")?;
+        for (y, cell) in column.iter().enumerate() {
+            let Some(kind) = cell.get("kind").and_then(Value::as_str) else {
+                continue;
+            };
+            let piece = parse_piece(kind).ok_or_else(|| format!("unknown piece {kind}"))?;
+            let color = cell.get("color").and_then(Value::as_u32).unwrap_or(0) as u8;
+            squares[Square::from_rf(8 - y as u8, 8 - x as u8).as_usize()] = piece.as_stm(color);
+        }
+    }
+
+    let mut fen = String::new();
+    for rank in (0..9).rev() {
+        let mut empty = 0;
+        for file in 0..9 {
+            let piece = squares[Square::from_rf(rank, file).as_usize()];
+            if piece == Piece::NONE {
+                empty += 1;
+                continue;
+            }
+            if empty > 0 {
+                fen += &empty.to_string();
+                empty = 0;
+            }
+            fen += &piece.to_string();
+        }
+        if empty > 0 {
+            fen += &empty.to_string();
+        }
+        if rank != 0 {
+            fen.push('/');
+        }
+    }
+
+    let stm = data.get("color").and_then(Value::as_u32).unwrap_or(0);
+    fen += if stm == 0 { " b " } else { " w " };
+    let mut hands = String::new();
+    let hand_values = data
+        .get("hands")
+        .and_then(Value::as_array)
+        .unwrap_or_default();
+    for (side, hand) in hand_values.iter().take(2).enumerate() {
+        // sfen lists hands rook first
+        for piece in HAND_KINDS.iter().rev() {
+            let count = hand
+                .get(PIECE_CODES[piece.as_usize()])
+                .and_then(Value::as_u32)
+                .unwrap_or(0);
+            if count > 1 {
+                hands += &count.to_string();
+            }
+            if count > 0 {
+                hands += &piece.as_stm(side as u8).to_string();
+            }
+        }
+    }
+    if hands.is_empty() {
+        hands.push('-');
+    }
+    let fen = fen + &hands + " 1";
+    Board::try_from_fen(&fen).map_err(|e| format!("invalid position: {e}"))?;
+    Ok(fen)
+}
+
+/// Writes a game as JKF. Variations all become forks of the main line.
+pub fn write_jkf(game: &Game) -> String {
+    let string = |s: &str| Value::String(s.to_owned());
+    let number = |n: u32| Value::Number(f64::from(n));
+
+    let header = game
+        .headers
+        .iter()
+        .map(|(key, value)| (key.clone(), string(value)))
+        .collect();
+
+    let mut moves = vec![comment_entry(game, 0)];
+    let mut board = Board::from_fen(&game.start_fen);
+    for (ply, action) in game.actions.iter().enumerate() {
+        let mut entry = vec![(
+            "move".to_owned(),
+            action_object(
+                &mut board,
+                *action,
+                ply.checked_sub(1).map(|p| game.actions[p]),
+            ),
+        )];
+        if let Some(time) = game.times.get(ply).copied().flatten() {
+            let now = vec![
+                ("m".to_owned(), number(time / 60)),
+                ("s".to_owned(), number(time % 60)),
+            ];
+            entry.push((
+                "time".to_owned(),
+                Value::Object(vec![("now".to_owned(), Value::Object(now))]),
+            ));
+        }
+        if let Value::Object(comments) = comment_entry(game, ply + 1) {
+            entry.extend(comments);
+        }
+
+        let forks: Vec<Value> = game
+            .variations
+            .iter()
+            .filter(|variation| variation.ply == ply)
+            .map(|variation| fork(&board, variation))
+            .collect();
+        if !forks.is_empty() {
+            entry.push(("forks".to_owned(), Value::Array(forks)));
+        }
+
+        moves.push(Value::Object(entry));
+        board.perform_action(*action);
+    }
+    if let Some(termination) = game.termination {
+        let special = termination_to_csa(termination, board.stm());
+        moves.push(Value::Object(vec![(
+            "special".to_owned(),
+            string(&special),
+        )]));
+    }
+
+    let root = Value::Object(vec![
+        ("header".to_owned(), Value::Object(header)),
+        ("initial".to_owned(), fen_to_initial(&game.start_fen)),
+        ("moves".to_owned(), Value::Array(moves)),
+    ]);
+    let mut output = String::new();
+    root.write(&mut output);
+    output
+}
+
+// the comments before any action go in an entry of their own
+fn comment_entry(game: &Game, ply: usize) -> Value {
+    let comments: Vec<Value> = game
+        .comments
+        .iter()
+        .filter(|(at, _)| *at == ply)
+        .map(|(_, comment)| Value::String(comment.clone()))
+        .collect();
+    if comments.is_empty() {
+        return Value::Object(Vec::new());
+    }
+    Value::Object(vec![("comments".to_owned(), Value::Array(comments))])
+}
+
+fn fork(board: &Board, variation: &Variation) -> Value {
+    let mut board = board.clone();
+    let mut moves = Vec::new();
+    for (i, action) in variation.actions.iter().enumerate().skip(variation.ply) {
+        let prev = i.checked_sub(1).map(|p| variation.actions[p]);
+        let object = action_object(&mut board, *action, prev);
+        moves.push(Value::Object(vec![("move".to_owned(), object)]));
+        board.perform_action(*action);
+    }
+    Value::Array(moves)
+}
+
+fn square_object(sq: Square) -> Value {
+    Value::Object(vec![
+        ("x".to_owned(), Value::Number(f64::from(9 - sq.file()))),
+        ("y".to_owned(), Value::Number(f64::from(9 - sq.rank()))),
+    ])
+}
+
+fn action_object(board: &mut Board, action: Action, prev: Option<Action>) -> Value {
+    let to = action.to();
+    let mut fields = vec![("color".to_owned(), Value::Number(f64::from(board.stm())))];
+    if action.is_drop() {
+        fields.push(("to".to_owned(), square_object(to)));
+        let kind = PIECE_CODES[action.piece().piece().as_usize()];
+        fields.push(("piece".to_owned(), Value::String(kind.to_owned())));
+        return Value::Object(fields);
+    }
+
+    let from = action.from();
+    fields.push(("from".to_owned(), square_object(from)));
+    fields.push(("to".to_owned(), square_object(to)));
+    let piece = board.piece_on_square(from).piece();
+    fields.push((
+        "piece".to_owned(),
+        Value::String(PIECE_CODES[piece.as_usize()].to_owned()),
+    ));
+    if prev.is_some_and(|prev| prev.to() == to) {
+        fields.push(("same".to_owned(), Value::Bool(true)));
+    }
+    // promote is only there when there was a choice
+    if action.is_promo()
+        || board
+            .legal_actions()
+            .contains(&Action::new_move(from, to, true))
+    {
+        fields.push(("promote".to_owned(), Value::Bool(action.is_promo())));
+    }
+    let victim = board.piece_on_square(to);
+    if victim != Piece::NONE {
+        fields.push((
+            "capture".to_owned(),
+            Value::String(PIECE_CODES[victim.piece().as_usize()].to_owned()),
+        ));
+    }
+    Value::Object(fields)
+}
+
+fn fen_to_initial(fen: &str) -> Value {
+    let preset =
+        handicap_name(fen).and_then(|handicap| PRESETS.iter().find(|(_, name)| *name == handicap));
+    if let Some((preset, _)) = preset {
+        return Value::Object(vec![(
+            "preset".to_owned(),
+            Value::String(preset.to_string()),
+        )]);
+    }
+
+    let board = Board::from_fen(fen);
+    let mut columns = Vec::new();
+    for x in 0..9 {
+        let mut column = Vec::new();
+        for y in 0..9 {
+            let piece = board.piece_on_square(Square::from_rf(8 - y, 8 - x));
+            if piece == Piece::NONE {
+                column.push(Value::Object(Vec::new()));
+                continue;
+            }
+            column.push(Value::Object(vec![
+                ("color".to_owned(), Value::Number(f64::from(piece.side()))),
+                (
+                    "kind".to_owned(),
+                    Value::String(PIECE_CODES[piece.piece().as_usize()].to_owned()),
+                ),
+            ]));
+        }
+        columns.push(Value::Array(column));
+    }
+    let hands = (0..2)
+        .map(|side| {
+            let hand = board.hand(side);
+            Value::Object(
+                HAND_KINDS
+                    .iter()
+                    .map(|piece| {
+                        (
+                            PIECE_CODES[piece.as_usize()].to_owned(),
+                            Value::Number(f64::from(hand.num(*piece))),
+                        )
+                    })
+                    .collect(),
+            )
+        })
+        .collect();
+
+    Value::Object(vec![
+        ("preset".to_owned(), Value::String("OTHER".to_owned())),
+        (
+            "data".to_owned(),
+            Value::Object(vec![
+                ("board".to_owned(), Value::Array(columns)),
+                ("color".to_owned(), Value::Number(f64::from(board.stm()))),
+                ("hands".to_owned(), Value::Array(hands)),
+            ]),
+        ),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_jkf, write_jkf};
+    use crate::record::{kif::parse_kif, Game, Termination};
+
+    #[test]
+    fn jkf() {
+        let jkf = r#"{
+  "header": {"先手": "sente", "後手": "gote"},
+  "initial": {"preset": "HIRATE"},
+  "moves": [
+    {"comments": ["start"]},
+    {"move": {"from": {"x": 7, "y": 7}, "to": {"x": 7, "y": 6}, "color": 0, "piece": "FU"},
+     "time": {"now": {"m": 0, "s": 12}, "total": {"h": 0, "m": 0, "s": 12}}},
+    {"move": {"from": {"x": 3, "y": 3}, "to": {"x": 3, "y": 4}, "color": 1, "piece": "FU"},
+     "forks": [[{"move": {"from": {"x": 8, "y": 3}, "to": {"x": 8, "y": 4}, "color": 1, "piece": "FU"}}]]},
+    {"move": {"from": {"x": 8, "y": 8}, "to": {"x": 2, "y": 2}, "color": 0, "piece": "KA",
+              "promote": true, "capture": "KA"}},
+    {"move": {"from": {"x": 3, "y": 1}, "color": 1, "piece": "GI", "same": true, "capture": "UM"},
+     "comments": ["同　銀"]},
+    {"move": {"to": {"x": 4, "y": 5}, "color": 0, "piece": "KA"}},
+    {"special": "TORYO"}
+  ]
+}"#;
+        let game = parse_jkf(jkf).unwrap();
+        let usi: Vec<String> = game.actions.iter().map(|a| a.to_usi()).collect();
+        assert_eq!(usi, ["7g7f", "3c3d", "8h2b+", "3a2b", "B*4e"]);
+        assert_eq!(game.times[0], Some(12));
+        assert_eq!(
+            game.comments,
+            [(0, "start".to_owned()), (4, "同　銀".to_owned())]
+        );
+        assert_eq!(game.variations[0].ply, 1);
+        assert_eq!(game.variations[0].actions[1].to_usi(), "8c8d");
+        assert_eq!(game.termination, Some(Termination::Resign));
+        assert_eq!(parse_jkf(&write_jkf(&game)).unwrap(), game);
+    }
+
+    #[test]
+    fn positions() {
+        let kif = "手合割：香落ち\n手数----指手---------消費時間--\n   1 １一角(22)\n";
+        let game = parse_kif(kif).unwrap();
+        assert!(write_jkf(&game).contains(r#""preset":"KY""#));
+        assert_eq!(
+            parse_jkf(&write_jkf(&game)).unwrap().start_fen,
+            game.start_fen
+        );
+
+        let game = Game::new("8k/9/7G1/9/9/9/9/9/K8 w G2P2r 1");
+        let written = write_jkf(&game);
+        assert!(written.contains(r#""preset":"OTHER""#));
+        assert_eq!(parse_jkf(&written).unwrap().start_fen, game.start_fen);
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(parse_jkf("{\n\"moves\": [\n}").unwrap_err().line, 3);
+        let illegal = r#"{"moves": [{}, {"move": {"from": {"x": 7, "y": 7}, "to": {"x": 7, "y": 5}, "piece": "FU"}}]}"#;
+        assert!(parse_jkf(illegal)
+            .unwrap_err()
+            .message
+            .starts_with("action 1"));
+
+        // thirty pawns in hand, and a board without kings
+        let empty_board = format!("[{}]", ["[{},{},{},{},{},{},{},{},{}]"; 9].join(","));
+        for hands in [r#"[{"FU":30},{}]"#, "[{},{}]"] {
+            let mut board = empty_board.clone();
+            if hands.contains("FU") {
+                board = board.replacen("{}", r#"{"color":0,"kind":"OU"}"#, 1);
+                board = board.replacen("[{}", r#"[{"color":1,"kind":"OU"}"#, 1);
+            }
+            let jkf = format!(
+                r#"{{"initial":{{"preset":"OTHER","data":{{"board":{board},"color":0,"hands":{hands}}}}},"moves":[{{}}]}}"#
+            );
+            let error = parse_jkf(&jkf).unwrap_err();
+            assert!(error.message.starts_with("invalid position"), "{error}");
+        }
+
+        // nesting deep enough to overflow the stack
+        let nested = "[".repeat(100_000) + &"]".repeat(100_000);
+        assert!(parse_jkf(&nested).is_err());
+    }
+}
@@ -0,0 +1,312 @@
+// just enough json for jkf, objects keep their key order so records round trip unchanged
+
+use std::fmt::Write;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    pub(crate) fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Self::Object(fields) => fields.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_u32(&self) -> Option<u32> {
+        match self {
+            Self::Number(n) if *n >= 0.0 && n.fract() == 0.0 => Some(*n as u32),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_bool(&self) -> Option<bool> {
+        match self {
+            Self::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_array(&self) -> Option<&[Value]> {
+        match self {
+            Self::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_object(&self) -> Option<&[(String, Value)]> {
+        match self {
+            Self::Object(fields) => Some(fields),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn write(&self, output: &mut String) {
+        match self {
+            Self::Null => output.push_str("null"),
+            Self::Bool(b) => output.push_str(if *b { "true" } else { "false" }),
+            Self::Number(n) => write!(output, "{n}").expect("writing to a string can't fail"),
+            Self::String(s) => write_string(s, output),
+            Self::Array(values) => {
+                output.push('[');
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        output.push(',');
+                    }
+                    value.write(output);
+                }
+                output.push(']');
+            }
+            Self::Object(fields) => {
+                output.push('{');
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        output.push(',');
+                    }
+                    write_string(key, output);
+                    output.push(':');
+                    value.write(output);
+                }
+                output.push('}');
+            }
+        }
+    }
+}
+
+fn write_string(s: &str, output: &mut String) {
+    output.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => output.push_str("\\\""),
+            '\\' => output.push_str("\\\\"),
+            '\n' => output.push_str("\\n"),
+            '\r' => output.push_str("\\r"),
+            '\t' => output.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                write!(output, "\\u{:04x}", c as u32).expect("writing to a string can't fail")
+            }
+            c => output.push(c),
+        }
+    }
+    output.push('"');
+}
+
+// arrays and objects nested deeper than this are rejected rather than risk the stack, records
+// nest a handful of levels and forks a few more
+const MAX_DEPTH: usize = 256;
+
+/// Parses a json document, the error is the byte offset where it went wrong.
+pub(crate) fn parse(text: &str) -> Result<Value, usize> {
+    let mut parser = Parser {
+        bytes: text.as_bytes(),
+        text,
+        pos: 0,
+        depth: 0,
+    };
+    let value = parser.value()?;
+    parser.skip_whitespace();
+    if parser.pos != parser.bytes.len() {
+        return Err(parser.pos);
+    }
+    Ok(value)
+}
+
+struct Parser<'a> {
+    bytes: &'a [u8],
+    text: &'a str,
+    pos: usize,
+    depth: usize,
+}
+
+impl Parser<'_> {
+    fn skip_whitespace(&mut self) {
+        while self
+            .bytes
+            .get(self.pos)
+            .is_some_and(|b| b.is_ascii_whitespace())
+        {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), usize> {
+        self.skip_whitespace();
+        if self.bytes.get(self.pos) == Some(&byte) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(self.pos)
+        }
+    }
+
+    fn literal(&mut self, word: &str, value: Value) -> Result<Value, usize> {
+        if self.text[self.pos..].starts_with(word) {
+            self.pos += word.len();
+            Ok(value)
+        } else {
+            Err(self.pos)
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, usize> {
+        self.skip_whitespace();
+        match self.bytes.get(self.pos) {
+            Some(b'{' | b'[') => {
+                if self.depth == MAX_DEPTH {
+                    return Err(self.pos);
+                }
+                self.depth += 1;
+                let value = if self.bytes[self.pos] == b'{' {
+                    self.object()
+                } else {
+                    self.array()
+                };
+                self.depth -= 1;
+                value
+            }
+            Some(b'"') => self.string().map(Value::String),
+            Some(b't') => self.literal("true", Value::Bool(true)),
+            Some(b'f') => self.literal("false", Value::Bool(false)),
+            Some(b'n') => self.literal("null", Value::Null),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            _ => Err(self.pos),
+        }
+    }
+
+    fn object(&mut self) -> Result<Value, usize> {
+        self.expect(b'{')?;
+        let mut fields = Vec::new();
+        self.skip_whitespace();
+        if self.bytes.get(self.pos) == Some(&b'}') {
+            self.pos += 1;
+            return Ok(Value::Object(fields));
+        }
+        loop {
+            self.skip_whitespace();
+            let key = self.string()?;
+            self.expect(b':')?;
+            fields.push((key, self.value()?));
+            self.skip_whitespace();
+            match self.bytes.get(self.pos) {
+                Some(b',') => self.pos += 1,
+                Some(b'}') => {
+                    self.pos += 1;
+                    return Ok(Value::Object(fields));
+                }
+                _ => return Err(self.pos),
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Value, usize> {
+        self.expect(b'[')?;
+        let mut values = Vec::new();
+        self.skip_whitespace();
+        if self.bytes.get(self.pos) == Some(&b']') {
+            self.pos += 1;
+            return Ok(Value::Array(values));
+        }
+        loop {
+            values.push(self.value()?);
+            self.skip_whitespace();
+            match self.bytes.get(self.pos) {
+                Some(b',') => self.pos += 1,
+                Some(b']') => {
+                    self.pos += 1;
+                    return Ok(Value::Array(values));
+                }
+                _ => return Err(self.pos),
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<Value, usize> {
+        let start = self.pos;
+        while self
+            .bytes
+            .get(self.pos)
+            .is_some_and(|b| matches!(b, b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9'))
+        {
+            self.pos += 1;
+        }
+        self.text[start..self.pos]
+            .parse()
+            .map(Value::Number)
+            .map_err(|_| start)
+    }
+
+    fn hex4(&mut self) -> Result<u32, usize> {
+        let digits = self.text.get(self.pos..self.pos + 4).ok_or(self.pos)?;
+        let code = u32::from_str_radix(digits, 16).map_err(|_| self.pos)?;
+        self.pos += 4;
+        Ok(code)
+    }
+
+    fn string(&mut self) -> Result<String, usize> {
+        self.expect(b'"')?;
+        let mut output = String::new();
+        loop {
+            let start = self.pos;
+            // copy everything up to the next quote or escape in one go
+            while self
+                .bytes
+                .get(self.pos)
+                .is_some_and(|b| *b != b'"' && *b != b'\\')
+            {
+                self.pos += 1;
+            }
+            output.push_str(&self.text[start..self.pos]);
+            match self.bytes.get(self.pos) {
+                Some(b'"') => {
+                    self.pos += 1;
+                    return Ok(output);
+                }
+                Some(b'\\') => {
+                    self.pos += 1;
+                    let escape = *self.bytes.get(self.pos).ok_or(self.pos)?;
+                    self.pos += 1;
+                    match escape {
+                        b'"' => output.push('"'),
+                        b'\\' => output.push('\\'),
+                        b'/' => output.push('/'),
+                        b'b' => output.push('\u{8}'),
+                        b'f' => output.push('\u{c}'),
+                        b'n' => output.push('\n'),
+                        b'r' => output.push('\r'),
+                        b't' => output.push('\t'),
+                        b'u' => {
+                            let mut code = self.hex4()?;
+                            // characters outside the basic plane come as surrogate pairs
+                            if (0xd800..0xdc00).contains(&code)
+                                && self.text[self.pos..].starts_with("\\u")
+                            {
+                                self.pos += 2;
+                                let low = self.hex4()?;
+                                code = 0x10000
+                                    + ((code - 0xd800) << 10)
+                                    + (low.wrapping_sub(0xdc00) & 0x3ff);
+                            }
+                            output.push(char::from_u32(code).ok_or(self.pos)?);
+                        }
+                        _ => return Err(self.pos - 1),
+                    }
+                }
+                _ => return Err(self.pos),
+            }
+        }
+    }
+}
@@ -0,0 +1,878 @@
+//! KIF and KI2, the Japanese record formats used by most shogi sites and GUIs. KIF lists every
+//! action with its origin square (`７六歩(77)`), KI2 only has the destination and relies on
+//! disambiguators like `右` and `上` when more than one piece could get there.
+
+use crate::{
+    board::{Board, STARTPOS},
+    handicap::Handicap,
+    notation::{movers, select, Relative},
+    types::{action::Action, piece::Piece, square::Square},
+};
+
+use super::{perform_checked, Game, RecordError, Termination, Variation};
+
+pub(crate) const FILE_DIGITS: [char; 9] = ['１', '２', '３', '４', '５', '６', '７', '８', '９'];
+pub(crate) const RANK_KANJI: [char; 9] = ['一', '二', '三', '四', '五', '六', '七', '八', '九'];
+
+// names used when reading, the first name for each piece is also the one that gets written
+const PIECE_NAMES: [(&str, Piece); 20] = [
+    ("歩", Piece::PAWN),
+    ("香", Piece::LANCE),
+    ("桂", Piece::KNIGHT),
+    ("銀", Piece::SILVER),
+    ("金", Piece::GOLD),
+    ("角", Piece::BISHOP),
+    ("飛", Piece::ROOK),
+    ("玉", Piece::KING),
+    ("と", Piece::PROMO_PAWN),
+    ("成香", Piece::PROMO_LANCE),
+    ("成桂", Piece::PROMO_KNIGHT),
+    ("成銀", Piece::PROMO_SILVER),
+    ("馬", Piece::PROMO_BISHOP),
+    ("龍", Piece::PROMO_ROOK),
+    ("王", Piece::KING),
+    ("竜", Piece::PROMO_ROOK),
+    ("杏", Piece::PROMO_LANCE),
+    ("圭", Piece::PROMO_KNIGHT),
+    ("全", Piece::PROMO_SILVER),
+    ("飛車", Piece::ROOK),
+];
+
+// board diagrams only have room for one character per piece
+const BOD_NAMES: [(char, Piece); 14] = [
+    ('歩', Piece::PAWN),
+    ('香', Piece::LANCE),
+    ('桂', Piece::KNIGHT),
+    ('銀', Piece::SILVER),
+    ('金', Piece::GOLD),
+    ('角', Piece::BISHOP),
+    ('飛', Piece::ROOK),
+    ('玉', Piece::KING),
+    ('と', Piece::PROMO_PAWN),
+    ('杏', Piece::PROMO_LANCE),
+    ('圭', Piece::PROMO_KNIGHT),
+    ('全', Piece::PROMO_SILVER),
+    ('馬', Piece::PROMO_BISHOP),
+    ('龍', Piece::PROMO_ROOK),
+];
+
+// hands are written in this order in board diagrams
+const HAND_ORDER: [Piece; 7] = [
+    Piece::ROOK,
+    Piece::BISHOP,
+    Piece::GOLD,
+    Piece::SILVER,
+    Piece::KNIGHT,
+    Piece::LANCE,
+    Piece::PAWN,
+];
+
+/// The starting position for a `手合割` value, `平手` or one of the [`Handicap`] names.
+pub fn handicap_fen(name: &str) -> Option<&'static str> {
+    match name {
+        "平手" => Some(STARTPOS),
+        _ => Handicap::from_japanese_name(name).map(Handicap::fen),
+    }
+}
+
+/// The `手合割` value for a starting position, None if it isn't the even or a handicap one.
+pub fn handicap_name(fen: &str) -> Option<&'static str> {
+    if without_ply(fen) == without_ply(STARTPOS) {
+        return Some("平手");
+    }
+    Handicap::from_fen(fen).map(Handicap::japanese_name)
+}
+
+const TERMINATIONS: [(&str, Termination); 14] = [
+    ("投了", Termination::Resign),
+    ("詰み", Termination::Checkmate),
+    ("千日手", Termination::Sennichite),
+    ("持将棋", Termination::Jishogi),
+    ("引き分け", Termination::Draw),
+    ("入玉勝ち", Termination::EnteringKing),
+    ("宣言勝ち", Termination::EnteringKing),
+    ("切れ負け", Termination::Timeout),
+    ("時間切れ", Termination::Timeout),
+    ("反則負け", Termination::IllegalMove),
+    ("反則勝ち", Termination::OpponentIllegalMove),
+    ("最大手数", Termination::MaxMoves),
+    ("中断", Termination::Interrupted),
+    ("封じ手", Termination::Interrupted),
+];
+
+const KI2_MARKERS: [char; 4] = ['▲', '△', '☗', '☖'];
+
+/// Reads a KIF record, checking that every action is legal.
+pub fn parse_kif(text: &str) -> Result<Game, RecordError> {
+    Parser::new(false).parse(text)
+}
+
+/// Reads a KI2 record, checking that every action is legal.
+pub fn parse_ki2(text: &str) -> Result<Game, RecordError> {
+    Parser::new(true).parse(text)
+}
+
+/// Writes a game as KIF, including comments, times and variations.
+pub fn write_kif(game: &Game) -> String {
+    // the start position goes where the 手合割 header was, or after the headers if there wasn't one
+    let position = match handicap_name(&game.start_fen) {
+        Some(name) => format!("手合割：{name}\n"),
+        None => board_diagram(&Board::from_fen(&game.start_fen)),
+    };
+    let mut output = String::new();
+    let mut position_written = false;
+    for (key, value) in &game.headers {
+        if key == "手合割" {
+            output += &position;
+            position_written = true;
+        } else {
+            output += &format!("{key}：{value}\n");
+        }
+    }
+    if !position_written {
+        output += &position;
+    }
+
+    output += "手数----指手---------消費時間--\n";
+    write_comments(&mut output, game, 0);
+    write_line(&mut output, game, &game.actions, 0, true);
+
+    for variation in &game.variations {
+        output += &format!("\n変化：{}手\n", variation.ply + 1);
+        write_line(&mut output, game, &variation.actions, variation.ply, false);
+    }
+    output
+}
+
+fn without_ply(fen: &str) -> String {
+    fen.split_ascii_whitespace()
+        .take(3)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn write_comments(output: &mut String, game: &Game, ply: usize) {
+    for (_, comment) in game.comments.iter().filter(|(at, _)| *at == ply) {
+        *output += &format!("*{comment}\n");
+    }
+}
+
+fn write_line(output: &mut String, game: &Game, actions: &[Action], from_ply: usize, main: bool) {
+    let mut board = Board::from_fen(&game.start_fen);
+    let mut prev_to = None;
+    let mut totals = [0; 2];
+    for (ply, action) in actions.iter().enumerate() {
+        let time = game.times.get(ply).copied().flatten().filter(|_| main);
+        totals[board.stm() as usize] += time.unwrap_or(0);
+        if ply >= from_ply {
+            let text = kif_action(&mut board, *action, prev_to);
+            let mut line = format!("{:>4} {}", ply + 1, text);
+            if let Some(time) = time {
+                let total = totals[board.stm() as usize];
+                line += &" ".repeat(14usize.saturating_sub(display_width(&text)));
+                line += &format!(
+                    "({:>2}:{:02}/{:02}:{:02}:{:02})",
+                    time / 60,
+                    time % 60,
+                    total / 3600,
+                    total / 60 % 60,
+                    total % 60
+                );
+            }
+            *output += &line;
+            output.push('\n');
+            if main {
+                write_comments(output, game, ply + 1);
+            }
+        }
+        prev_to = Some(action.to());
+        board.perform_action(*action);
+    }
+
+    if !main {
+        return;
+    }
+    if let Some(termination) = game.termination {
+        let name = TERMINATIONS
+            .iter()
+            .find(|(_, t)| *t == termination)
+            .map_or("中断", |(name, _)| name);
+        *output += &format!("{:>4} {}\n", actions.len() + 1, name);
+        let side = |side: u8| if side == 0 { "先手" } else { "後手" };
+        let summary = match game.result() {
+            Some(super::GameResult::SenteWin) => format!("{}の勝ち", side(0)),
+            Some(super::GameResult::GoteWin) => format!("{}の勝ち", side(1)),
+            Some(super::GameResult::Draw) | None => name.to_owned(),
+        };
+        *output += &format!("まで{}手で{}\n", actions.len(), summary);
+    }
+}
+
+fn display_width(text: &str) -> usize {
+    text.chars().map(|c| if c.is_ascii() { 1 } else { 2 }).sum()
+}
+
+/// An action in KIF notation, like `７六歩(77)`, `同　角成(88)` or `５五角打`. The board has to be
+/// in the position before the action.
+pub fn kif_action(board: &mut Board, action: Action, prev_to: Option<Square>) -> String {
+    let to = action.to();
+    let mut text = if prev_to == Some(to) {
+        "同　".to_owned()
+    } else {
+        format!(
+            "{}{}",
+            FILE_DIGITS[(8 - to.file()) as usize],
+            RANK_KANJI[(8 - to.rank()) as usize]
+        )
+    };
+
+    if action.is_drop() {
+        text += piece_name(action.piece().piece());
+        text += "打";
+        return text;
+    }
+
+    let from = action.from();
+    text += piece_name(board.piece_on_square(from).piece());
+    if action.is_promo() {
+        text += "成";
+    } else if board
+        .legal_actions()
+        .contains(&Action::new_move(from, to, true))
+    {
+        text += "不成";
+    }
+    text += &format!("({}{})", 9 - from.file(), 9 - from.rank());
+    text
+}
+
+pub(crate) fn piece_name(piece: Piece) -> &'static str {
+    PIECE_NAMES
+        .iter()
+        .find(|(_, p)| *p == piece)
+        .map(|(name, _)| *name)
+        .expect("no name for piece")
+}
+
+fn board_diagram(board: &Board) -> String {
+    let mut output = format!("後手の持駒：{}\n", japanese_hand(board, 1));
+    output += "  ９ ８ ７ ６ ５ ４ ３ ２ １\n";
+    output += "+---------------------------+\n";
+    for rank in (0..9).rev() {
+        output.push('|');
+        for file in 0..9 {
+            let piece = board.piece_on_square(Square::from_rf(rank, file));
+            if piece == Piece::NONE {
+                output += " ・";
+                continue;
+            }
+            output.push(if piece.side() == 0 { ' ' } else { 'v' });
+            output.push(
+                BOD_NAMES
+                    .iter()
+                    .find(|(_, p)| *p == piece.piece())
+                    .map(|(c, _)| *c)
+                    .expect("no name for piece"),
+            );
+        }
+        output += &format!("|{}\n", RANK_KANJI[(8 - rank) as usize]);
+    }
+    output += "+---------------------------+\n";
+    output += &format!("先手の持駒：{}\n", japanese_hand(board, 0));
+    if board.stm() == 1 {
+        output += "後手番\n";
+    }
+    output
+}
+
+fn japanese_hand(board: &Board, side: u8) -> String {
+    let hand = board.hand(side);
+    if hand.is_empty() {
+        return "なし".to_owned();
+    }
+    let mut output = String::new();
+    for piece in HAND_ORDER {
+        let count = hand.num(piece);
+        if count > 0 {
+            output += piece_name(piece);
+            if count > 1 {
+                output += &kanji_number(count as u32);
+            }
+            output.push('　');
+        }
+    }
+    output
+}
+
+fn kanji_number(n: u32) -> String {
+    let mut output = String::new();
+    if n >= 10 {
+        if n >= 20 {
+            output.push(RANK_KANJI[(n / 10 - 1) as usize]);
+        }
+        output.push('十');
+    }
+    if !n.is_multiple_of(10) {
+        output.push(RANK_KANJI[(n % 10 - 1) as usize]);
+    }
+    output
+}
+
+fn parse_kanji_number(text: &str) -> Option<u32> {
+    if text.is_empty() {
+        return Some(1);
+    }
+    let digit = |c: char| {
+        RANK_KANJI
+            .iter()
+            .position(|&k| k == c)
+            .map(|i| i as u32 + 1)
+    };
+    let mut total = 0;
+    let mut current = 0;
+    for c in text.chars() {
+        if c == '十' {
+            total += current.max(1) * 10;
+            current = 0;
+        } else {
+            current = digit(c)?;
+        }
+    }
+    Some(total + current)
+}
+
+// everything needed to resolve one action, the destination is None for 同
+#[derive(Debug, Default)]
+struct ParsedAction {
+    to: Option<Square>,
+    piece: Option<Piece>,
+    promote: Option<bool>,
+    drop: bool,
+    from: Option<Square>,
+    relative: Relative,
+}
+
+fn parse_action(text: &str) -> Option<(ParsedAction, &str)> {
+    let mut parsed = ParsedAction::default();
+    let mut rest = text.trim_start();
+
+    if let Some(after) = rest.strip_prefix('同') {
+        rest = after.trim_start_matches(['　', ' ']);
+    } else {
+        let mut chars = rest.chars();
+        let file = chars.next()?;
+        let rank = chars.next()?;
+        let file = FILE_DIGITS.iter().position(|&c| c == file).or_else(|| {
+            file.to_digit(10)
+                .map(|d| d as usize)
+                .filter(|d| *d > 0)
+                .map(|d| d - 1)
+        })?;
+        let rank = RANK_KANJI.iter().position(|&c| c == rank).or_else(|| {
+            rank.to_digit(10)
+                .map(|d| d as usize)
+                .filter(|d| *d > 0)
+                .map(|d| d - 1)
+        })?;
+        parsed.to = Some(Square::from_rf(8 - rank as u8, 8 - file as u8));
+        rest = chars.as_str();
+    }
+
+    // longest names first so that 成香 isn't read as a promotion
+    let mut names = PIECE_NAMES.to_vec();
+    names.sort_by_key(|(name, _)| std::cmp::Reverse(name.len()));
+    let (name, piece) = names.iter().find(|(name, _)| rest.starts_with(name))?;
+    parsed.piece = Some(*piece);
+    rest = &rest[name.len()..];
+
+    while let Some(c) = rest.chars().next() {
+        match c {
+            '右' => parsed.relative.right = true,
+            '左' => parsed.relative.left = true,
+            '直' => parsed.relative.straight = true,
+            '上' | '行' | '入' => parsed.relative.up = true,
+            '引' => parsed.relative.back = true,
+            '寄' => parsed.relative.sideways = true,
+            '打' => parsed.drop = true,
+            '成' => parsed.promote = Some(true),
+            '生' => parsed.promote = Some(false),
+            '不' if rest.starts_with("不成") => {
+                parsed.promote = Some(false);
+                rest = &rest["不".len()..];
+            }
+            _ => break,
+        }
+        rest = &rest[c.len_utf8()..];
+    }
+
+    // kif gives the origin square, like (77)
+    let bytes = rest.as_bytes();
+    if bytes.len() >= 4 && bytes[0] == b'(' && bytes[3] == b')' {
+        if let (Some(file), Some(rank)) = (
+            (bytes[1] as char).to_digit(10),
+            (bytes[2] as char).to_digit(10),
+        ) {
+            if (1..=9).contains(&file) && (1..=9).contains(&rank) {
+                parsed.from = Some(Square::from_rf(9 - rank as u8, 9 - file as u8));
+                rest = &rest[4..];
+            }
+        }
+    }
+    Some((parsed, rest))
+}
+
+fn resolve_action(
+    board: &mut Board,
+    parsed: &ParsedAction,
+    prev_to: Option<Square>,
+) -> Result<Action, String> {
+    let to = parsed.to.or(prev_to).ok_or("同 with no previous action")?;
+    let piece = parsed.piece.expect("parsed actions always have a piece");
+    let stm = board.stm();
+
+    if parsed.drop || parsed.from.is_some() {
+        return Ok(match parsed.from {
+            Some(from) if !parsed.drop => Action::new_move(from, to, parsed.promote == Some(true)),
+            _ => Action::new_drop(piece.as_stm(stm), to),
+        });
+    }
+
+    let mut froms = movers(board, piece, to);
+    // nothing on the board can get there, so it has to be a drop
+    if froms.is_empty() {
+        return Ok(Action::new_drop(piece.as_stm(stm), to));
+    }
+    if froms.len() > 1 {
+        froms = select(stm, &froms, to, parsed.relative);
+    }
+    match froms[..] {
+        [from] => Ok(Action::new_move(from, to, parsed.promote == Some(true))),
+        [] => Err("no legal action matches".to_owned()),
+        _ => Err("ambiguous action".to_owned()),
+    }
+}
+
+struct Parser {
+    ki2: bool,
+    game: Game,
+    // every line read so far, the main line first, all of them from the start of the game
+    lines: Vec<Vec<Action>>,
+    current: Vec<Action>,
+    board: Board,
+    prev_to: Option<Square>,
+    in_actions: bool,
+    // board diagram, rows from the top
+    diagram: Vec<String>,
+    hands: [String; 2],
+    diagram_stm: u8,
+}
+
+impl Parser {
+    fn new(ki2: bool) -> Self {
+        Self {
+            ki2,
+            game: Game::default(),
+            lines: Vec::new(),
+            current: Vec::new(),
+            board: Board::from_fen(STARTPOS),
+            prev_to: None,
+            in_actions: false,
+            diagram: Vec::new(),
+            hands: [String::new(), String::new()],
+            diagram_stm: 0,
+        }
+    }
+
+    fn parse(mut self, text: &str) -> Result<Game, RecordError> {
+        let text = text.trim_start_matches('\u{feff}');
+        for (i, line) in text.lines().enumerate() {
+            self.parse_line(line.trim_end(), i + 1)?;
+        }
+        // a record can be just a position
+        if !self.in_actions {
+            self.start_actions(text.lines().count())?;
+        }
+        self.finish_line();
+        let mut lines = self.lines.into_iter();
+        self.game.actions = lines.next().unwrap_or_default();
+        self.game.times.resize(self.game.actions.len(), None);
+        for actions in lines {
+            // the first action that isn't in the main line
+            let ply = actions
+                .iter()
+                .zip(&self.game.actions)
+                .take_while(|(a, b)| a == b)
+                .count();
+            self.game.variations.push(Variation { ply, actions });
+        }
+        Ok(self.game)
+    }
+
+    fn parse_line(&mut self, line: &str, number: usize) -> Result<(), RecordError> {
+        let trimmed = line.trim_start();
+        if trimmed.is_empty() || trimmed.starts_with('#') || trimmed.starts_with('&') {
+            return Ok(());
+        }
+        if let Some(comment) = trimmed.strip_prefix('*') {
+            // comments in variations are dropped
+            if self.lines.is_empty() {
+                self.game
+                    .comments
+                    .push((self.current.len(), comment.to_owned()));
+            }
+            return Ok(());
+        }
+        if let Some(rest) = trimmed.strip_prefix("変化：") {
+            let ply: usize = rest
+                .trim_end_matches('手')
+                .trim()
+                .parse()
+                .map_err(|_| RecordError::new(number, "invalid variation"))?;
+            return self.start_variation(ply, number);
+        }
+        if let Some(rest) = trimmed.strip_prefix("まで") {
+            if self.lines.is_empty() && self.game.termination.is_none() {
+                self.game.termination = self.summary_termination(rest);
+            }
+            return Ok(());
+        }
+
+        if !self.in_actions {
+            let starts_actions = if self.ki2 {
+                trimmed.starts_with(KI2_MARKERS)
+            } else {
+                trimmed.starts_with("手数") || trimmed.starts_with(|c: char| c.is_ascii_digit())
+            };
+            if !starts_actions {
+                return self.parse_header(trimmed, number);
+            }
+            self.start_actions(number)?;
+            if trimmed.starts_with("手数") {
+                return Ok(());
+            }
+        }
+
+        if self.ki2 {
+            for text in trimmed.split(KI2_MARKERS).filter(|t| !t.trim().is_empty()) {
+                self.parse_action_text(text.trim(), number)?;
+            }
+            Ok(())
+        } else {
+            let text = trimmed.trim_start_matches(|c: char| c.is_ascii_digit());
+            if text.len() == trimmed.len() {
+                return Err(RecordError::new(number, "expected a move number"));
+            }
+            self.parse_action_text(text.trim_start(), number)
+        }
+    }
+
+    fn parse_header(&mut self, line: &str, number: usize) -> Result<(), RecordError> {
+        if line.starts_with('|') {
+            self.diagram.push(line.to_owned());
+            return Ok(());
+        }
+        if line.starts_with('+') || line.starts_with('９') {
+            return Ok(());
+        }
+        if line.starts_with("後手番") || line.starts_with("上手番") {
+            self.diagram_stm = 1;
+            return Ok(());
+        }
+        if line.starts_with("先手番") || line.starts_with("下手番") {
+            self.diagram_stm = 0;
+            return Ok(());
+        }
+        let Some((key, value)) = line.split_once('：').or_else(|| line.split_once(':')) else {
+            // the file labels above a board diagram
+            if line.starts_with(FILE_DIGITS) {
+                return Ok(());
+            }
+            return Err(RecordError::new(number, format!("unknown line {line}")));
+        };
+        match key {
+            "後手の持駒" | "上手の持駒" => self.hands[1] = value.to_owned(),
+            "先手の持駒" | "下手の持駒" => self.hands[0] = value.to_owned(),
+            "手番" => {
+                self.diagram_stm = u8::from(value.starts_with('後') || value.starts_with('上'))
+            }
+            "手合割" => {
+                let fen = handicap_fen(value.trim())
+                    .ok_or_else(|| RecordError::new(number, format!("unknown handicap {value}")))?;
+                self.game.start_fen = fen.to_string();
+                self.game.headers.push((key.to_owned(), value.to_owned()));
+            }
+            _ => self.game.headers.push((key.to_owned(), value.to_owned())),
+        }
+        Ok(())
+    }
+
+    fn start_actions(&mut self, number: usize) -> Result<(), RecordError> {
+        self.in_actions = true;
+        if !self.diagram.is_empty() {
+            self.game.start_fen = self.diagram_fen(number)?;
+        }
+        self.board = Board::try_from_fen(&self.game.start_fen)
+            .map_err(|e| RecordError::new(number, format!("invalid position: {e}")))?;
+        Ok(())
+    }
+
+    fn diagram_fen(&self, number: usize) -> Result<String, RecordError> {
+        let error = |message: &str| RecordError::new(number, message);
+        if self.diagram.len() != 9 {
+            return Err(error("board diagram needs 9 ranks"));
+        }
+        let mut ranks = Vec::new();
+        for row in &self.diagram {
+            let cells: Vec<char> = row.chars().skip(1).take(18).collect();
+            if cells.len() != 18 {
+                return Err(error("board diagram rank is too short"));
+            }
+            let mut rank = String::new();
+            let mut empty = 0;
+            for cell in cells.chunks(2) {
+                if cell[1] == '・' {
+                    empty += 1;
+                    continue;
+                }
+                let piece = BOD_NAMES
+                    .iter()
+                    .find(|(c, _)| *c == cell[1] || (cell[1] == '王' && *c == '玉'))
+                    .map(|(_, p)| *p)
+                    .ok_or_else(|| error("unknown piece in board diagram"))?;
+                if empty > 0 {
+                    rank += &empty.to_string();
+                    empty = 0;
+                }
+                let side = u8::from(cell[0] == 'v');
+                rank += &piece.as_stm(side).to_string();
+            }
+            if empty > 0 {
+                rank += &empty.to_string();
+            }
+            ranks.push(rank);
+        }
+
+        let mut hands = String::new();
+        for side in 0..2 {
+            for token in self.hands[side as usize]
+                .split(['　', ' '])
+                .filter(|t| !t.is_empty() && *t != "なし")
+            {
+                let mut chars = token.chars();
+                let name = chars.next().expect("tokens are not empty");
+                let piece = BOD_NAMES
+                    .iter()
+                    .find(|(c, _)| *c == name)
+                    .map(|(_, p)| *p)
+                    .ok_or_else(|| error("unknown piece in hand"))?;
+                let count = parse_kanji_number(chars.as_str())
+                    .ok_or_else(|| error("invalid hand count"))?;
+                if count > 1 {
+                    hands += &count.to_string();
+                }
+                hands += &piece.as_stm(side).to_string();
+            }
+        }
+        if hands.is_empty() {
+            hands.push('-');
+        }
+
+        Ok(format!(
+            "{} {} {} 1",
+            ranks.join("/"),
+            if self.diagram_stm == 0 { 'b' } else { 'w' },
+            hands
+        ))
+    }
+
+    fn parse_action_text(&mut self, text: &str, number: usize) -> Result<(), RecordError> {
+        if let Some((_, termination)) = TERMINATIONS.iter().find(|(name, _)| text.starts_with(name))
+        {
+            if self.lines.is_empty() {
+                self.game.termination = Some(*termination);
+            }
+            return Ok(());
+        }
+        let (parsed, rest) = parse_action(text)
+            .ok_or_else(|| RecordError::new(number, format!("invalid action {text}")))?;
+        let action = resolve_action(&mut self.board, &parsed, self.prev_to)
+            .map_err(|message| RecordError::new(number, format!("{message}: {text}")))?;
+        perform_checked(&mut self.board, action, number)?;
+        self.prev_to = Some(action.to());
+        self.current.push(action);
+        if self.lines.is_empty() {
+            self.game.times.resize(self.current.len() - 1, None);
+            self.game.times.push(parse_time(rest));
+        }
+        Ok(())
+    }
+
+    fn finish_line(&mut self) {
+        self.lines.push(std::mem::take(&mut self.current));
+    }
+
+    // a variation replaces action `ply` of the closest line before it that is long enough
+    fn start_variation(&mut self, ply: usize, number: usize) -> Result<(), RecordError> {
+        if !self.in_actions {
+            self.start_actions(number)?;
+        }
+        self.finish_line();
+        let parent = self
+            .lines
+            .iter()
+            .rev()
+            .find(|line| line.len() >= ply)
+            .ok_or_else(|| RecordError::new(number, "variation doesn't branch off anything"))?;
+        self.current = parent[..ply - 1].to_vec();
+        self.board = Board::from_fen(&self.game.start_fen);
+        for action in &self.current {
+            self.board.perform_action(*action);
+        }
+        self.prev_to = self.current.last().map(Action::to);
+        Ok(())
+    }
+
+    fn summary_termination(&self, summary: &str) -> Option<Termination> {
+        if summary.contains("千日手") {
+            return Some(Termination::Sennichite);
+        }
+        if summary.contains("持将棋") {
+            return Some(Termination::Jishogi);
+        }
+        if summary.contains("引き分け") {
+            return Some(Termination::Draw);
+        }
+        if summary.contains("詰") {
+            return Some(Termination::Checkmate);
+        }
+        if !summary.contains("勝ち") {
+            return Some(Termination::Interrupted);
+        }
+        let winner = u8::from(summary.contains("後手") || summary.contains("上手"));
+        let stm = self.board.stm();
+        Some(if winner != stm {
+            if summary.contains("反則") {
+                Termination::IllegalMove
+            } else {
+                Termination::Resign
+            }
+        } else if summary.contains("反則") {
+            Termination::OpponentIllegalMove
+        } else {
+            Termination::EnteringKing
+        })
+    }
+}
+
+// times look like ( 0:12/00:01:34), only the time for the action itself is kept
+fn parse_time(text: &str) -> Option<u32> {
+    let inside = text.trim().strip_prefix('(')?;
+    let (this_move, _) = inside.split_once('/')?;
+    let (minutes, seconds) = this_move.trim().split_once(':')?;
+    Some(minutes.trim().parse::<u32>().ok()? * 60 + seconds.trim().parse::<u32>().ok()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_ki2, parse_kif, write_kif};
+    use crate::record::{GameResult, Termination};
+
+    const KIF: &str = "\
+# ---- Kifu for Windows ----
+開始日時：2024/01/01 10:00:00
+手合割：平手
+先手：sente
+後手：gote
+手数----指手---------消費時間--
+*opening comment
+   1 ７六歩(77)   ( 0:01/00:00:01)
+   2 ３四歩(33)   ( 0:02/00:00:02)
+   3 ２二角成(88)   ( 0:03/00:00:04)
+*bishop trade
+   4 同　銀(31)   ( 0:01/00:00:03)
+   5 ４五角打   ( 0:05/00:00:09)
+   6 投了   ( 0:10/00:00:13)
+まで5手で先手の勝ち
+
+変化：4手
+   4 ４四歩(43)   ( 0:00/00:00:02)
+
+変化：3手
+   3 ６六歩(67)   ( 0:00/00:00:01)
+   4 ８四歩(83)   ( 0:00/00:00:02)
+";
+
+    #[test]
+    fn kif() {
+        let game = parse_kif(KIF).unwrap();
+        let usi: Vec<String> = game.actions.iter().map(|a| a.to_usi()).collect();
+        assert_eq!(usi, ["7g7f", "3c3d", "8h2b+", "3a2b", "B*4e"]);
+        assert_eq!(game.header("先手"), Some("sente"));
+        assert_eq!(game.times[2], Some(3));
+        assert_eq!(
+            game.comments,
+            [
+                (0, "opening comment".to_owned()),
+                (3, "bishop trade".to_owned())
+            ]
+        );
+        assert_eq!(game.termination, Some(Termination::Resign));
+        assert_eq!(game.result(), Some(GameResult::SenteWin));
+
+        assert_eq!(game.variations.len(), 2);
+        assert_eq!(game.variations[0].ply, 3);
+        assert_eq!(game.variations[0].actions[3].to_usi(), "4c4d");
+        assert_eq!(game.variations[1].ply, 2);
+        assert_eq!(game.variations[1].actions.len(), 4);
+
+        assert_eq!(parse_kif(&write_kif(&game)).unwrap(), game);
+    }
+
+    #[test]
+    fn ki2() {
+        let ki2 = "\
+手合割：平手
+▲７六歩    △３四歩    ▲２二角成  △同　銀
+▲５八金右  △８八角打  ▲７八銀
+まで7手で中断
+";
+        let game = parse_ki2(ki2).unwrap();
+        let usi: Vec<String> = game.actions.iter().map(|a| a.to_usi()).collect();
+        assert_eq!(
+            usi,
+            ["7g7f", "3c3d", "8h2b+", "3a2b", "4i5h", "B*8h", "7i7h"]
+        );
+        assert_eq!(game.termination, Some(Termination::Interrupted));
+    }
+
+    #[test]
+    fn draws() {
+        let kif = "手合割：平手\n手数----指手---------消費時間--\n   1 ７六歩(77)\n   2 引き分け\n";
+        let game = parse_kif(kif).unwrap();
+        assert_eq!(game.termination, Some(Termination::Draw));
+        assert_eq!(game.result(), Some(GameResult::Draw));
+        let written = write_kif(&game);
+        assert!(written.ends_with("まで1手で引き分け\n"));
+        assert_eq!(parse_kif(&written).unwrap(), game);
+    }
+
+    #[test]
+    fn illegal_actions_are_rejected() {
+        let kif = "手合割：平手\n手数----指手---------消費時間--\n   1 ７五歩(77)\n";
+        assert_eq!(parse_kif(kif).unwrap_err().line, 3);
+    }
+
+    #[test]
+    fn handicap_and_diagram() {
+        let kif = "手合割：香落ち\n手数----指手---------消費時間--\n   1 １一角(22)\n";
+        let game = parse_kif(kif).unwrap();
+        assert_eq!(game.actions[0].to_usi(), "2b1a");
+
+        // positions that aren't handicaps get written as a board diagram
+        let mut game = crate::record::Game::new("8k/9/7G1/9/9/9/9/9/K8 b G2Prs 1");
+        game.actions.clear();
+        let written = write_kif(&game);
+        assert!(written.contains("先手の持駒：金　歩二"));
+        assert_eq!(parse_kif(&written).unwrap().start_fen, game.start_fen);
+    }
+}
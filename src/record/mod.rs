@@ -0,0 +1,181 @@
+//! Game records: the moves of a game along with its starting position, headers, comments and
+//! result, plus readers and writers for the common record formats.
+
+pub mod csa;
+pub mod jkf;
+mod json;
+pub mod kif;
+
+use std::{error::Error, fmt};
+
+use crate::{
+    board::{Board, STARTPOS},
+    types::action::Action,
+};
+
+/// How a game ended, from the point of view of the side to move after the last action.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Termination {
+    /// The side to move resigned.
+    Resign,
+    /// The side to move is checkmated.
+    Checkmate,
+    /// Fourfold repetition.
+    Sennichite,
+    /// Impasse, agreed or judged as a draw.
+    Jishogi,
+    /// Any other agreed or adjudicated draw.
+    Draw,
+    /// The side to move declared an entering king win.
+    EnteringKing,
+    /// The side to move ran out of time.
+    Timeout,
+    /// The side to move made an illegal action and lost.
+    IllegalMove,
+    /// The previous action was illegal, so the side to move wins.
+    OpponentIllegalMove,
+    /// The game hit the move limit, scored as a draw.
+    MaxMoves,
+    /// The game was stopped without a result.
+    Interrupted,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GameResult {
+    SenteWin,
+    GoteWin,
+    Draw,
+}
+
+impl Termination {
+    /// The result of the game given the side to move when it ended.
+    pub fn result(&self, stm: u8) -> Option<GameResult> {
+        let win_for = |side: u8| {
+            if side == 0 {
+                GameResult::SenteWin
+            } else {
+                GameResult::GoteWin
+            }
+        };
+        match self {
+            Self::Resign | Self::Checkmate | Self::Timeout | Self::IllegalMove => {
+                Some(win_for(1 - stm))
+            }
+            Self::EnteringKing | Self::OpponentIllegalMove => Some(win_for(stm)),
+            Self::Sennichite | Self::Jishogi | Self::Draw | Self::MaxMoves => {
+                Some(GameResult::Draw)
+            }
+            Self::Interrupted => None,
+        }
+    }
+}
+
+/// An alternative line, stored from the start of the game so it can be replayed on its own.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Variation {
+    /// The index of the first action that differs from the main line.
+    pub ply: usize,
+    pub actions: Vec<Action>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Game {
+    /// Header fields in the order they were read, with the keys as they appear in the record.
+    pub headers: Vec<(String, String)>,
+    pub start_fen: String,
+    pub actions: Vec<Action>,
+    /// Seconds spent on each action, if the record has them.
+    pub times: Vec<Option<u32>>,
+    /// Comments along with the number of actions played before them.
+    pub comments: Vec<(usize, String)>,
+    pub variations: Vec<Variation>,
+    pub termination: Option<Termination>,
+}
+
+impl Default for Game {
+    fn default() -> Self {
+        Self::new(STARTPOS)
+    }
+}
+
+impl Game {
+    pub fn new(start_fen: &str) -> Self {
+        Self {
+            headers: Vec::new(),
+            start_fen: start_fen.to_owned(),
+            actions: Vec::new(),
+            times: Vec::new(),
+            comments: Vec::new(),
+            variations: Vec::new(),
+            termination: None,
+        }
+    }
+
+    pub fn header(&self, key: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// The board after every action of the main line.
+    pub fn board(&self) -> Board {
+        let mut board = Board::from_fen(&self.start_fen);
+        for action in &self.actions {
+            board.perform_action(*action);
+        }
+        board
+    }
+
+    /// The side to move after the last action.
+    pub fn final_stm(&self) -> u8 {
+        Board::from_fen(&self.start_fen).stm() ^ (self.actions.len() % 2) as u8
+    }
+
+    pub fn result(&self) -> Option<GameResult> {
+        self.termination
+            .and_then(|termination| termination.result(self.final_stm()))
+    }
+}
+
+/// An error while reading a record, with the (1 based) line it happened on, or 0 for formats
+/// where lines don't mean much.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl RecordError {
+    pub fn new(line: usize, message: impl Into<String>) -> Self {
+        Self {
+            line,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for RecordError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for RecordError {}
+
+// makes an action only if it is legal, which every reader does with every action it parses
+pub(crate) fn perform_checked(
+    board: &mut Board,
+    action: Action,
+    line: usize,
+) -> Result<(), RecordError> {
+    if board.legal_actions().contains(&action) {
+        board.perform_action(action);
+        Ok(())
+    } else {
+        Err(RecordError::new(
+            line,
+            format!("illegal action {} in {}", action.to_usi(), board.to_fen()),
+        ))
+    }
+}
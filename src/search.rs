@@ -0,0 +1,290 @@
+//! A plain alpha-beta search with iterative deepening and a capture search at the leaves. It is
+//! small on purpose, enough to play legal games with sensible scores.
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{
+    board::Board,
+    eval::{evaluate, PIECE_VALUES},
+    nnue::{network, Network},
+    types::{action::Action, piece::Piece},
+};
+
+pub const MATE: i32 = 30000;
+pub const MAX_PLY: usize = 128;
+// anything above this is a mate score
+pub const MATE_BOUND: i32 = MATE - MAX_PLY as i32;
+const INFINITY: i32 = MATE + 1;
+
+/// When to stop searching, the search stops at whichever limit comes first.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SearchLimits {
+    pub depth: Option<u8>,
+    pub nodes: Option<u64>,
+    pub time: Option<Duration>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SearchResult {
+    /// None if there is no legal action.
+    pub best: Option<Action>,
+    /// From the point of view of the side to move, mates are `MATE` minus the distance in plies.
+    pub score: i32,
+    pub pv: Vec<Action>,
+    pub depth: u8,
+    pub nodes: u64,
+    pub time: Duration,
+}
+
+struct Searcher<'a> {
+    limits: SearchLimits,
+    stop: &'a AtomicBool,
+    start: Instant,
+    nodes: u64,
+    stopped: bool,
+    pv: Vec<[Action; MAX_PLY]>,
+    pv_len: [usize; MAX_PLY + 1],
+    // taken once so that a network loaded mid search doesn't change the evaluation under it
+    network: Option<Arc<Network>>,
+}
+
+/// Searches the board until a limit is hit or `stop` is set, calling `info` after every completed
+/// depth. The result is the one from the last completed depth, or the best action found so far if
+/// not even the first depth finished.
+pub fn search(
+    board: &mut Board,
+    limits: SearchLimits,
+    stop: &AtomicBool,
+    mut info: impl FnMut(&SearchResult),
+) -> SearchResult {
+    let mut searcher = Searcher {
+        limits,
+        stop,
+        start: Instant::now(),
+        nodes: 0,
+        stopped: false,
+        pv: vec![[Action::default(); MAX_PLY]; MAX_PLY + 1],
+        pv_len: [0; MAX_PLY + 1],
+        network: network(),
+    };
+
+    let mut result = SearchResult::default();
+    let max_depth = limits
+        .depth
+        .unwrap_or(MAX_PLY as u8 - 1)
+        .min(MAX_PLY as u8 - 1);
+    for depth in 1..=max_depth {
+        let score = searcher.negamax(board, depth as i32, 0, -INFINITY, INFINITY, &result.pv);
+        let pv = searcher.pv[0][..searcher.pv_len[0]].to_vec();
+        if searcher.stopped {
+            // a partial first iteration is still better than nothing
+            if result.best.is_none() && !pv.is_empty() {
+                result.best = Some(pv[0]);
+                result.pv = pv;
+            }
+            break;
+        }
+
+        result.best = pv.first().copied();
+        result.score = score;
+        result.pv = pv;
+        result.depth = depth;
+        result.nodes = searcher.nodes;
+        result.time = searcher.start.elapsed();
+        info(&result);
+
+        // no legal actions or a forced mate, searching deeper won't change anything
+        if result.best.is_none() || score.abs() >= MATE_BOUND {
+            break;
+        }
+    }
+    result.nodes = searcher.nodes;
+    result.time = searcher.start.elapsed();
+    result
+}
+
+impl Searcher<'_> {
+    fn should_stop(&mut self) -> bool {
+        if self.nodes.is_multiple_of(1024) {
+            self.stopped |= self.stop.load(Ordering::Relaxed)
+                || self.limits.nodes.is_some_and(|nodes| self.nodes >= nodes)
+                || self
+                    .limits
+                    .time
+                    .is_some_and(|time| self.start.elapsed() >= time);
+        }
+        self.stopped
+    }
+
+    fn negamax(
+        &mut self,
+        board: &mut Board,
+        depth: i32,
+        ply: usize,
+        mut alpha: i32,
+        beta: i32,
+        prev_pv: &[Action],
+    ) -> i32 {
+        self.pv_len[ply] = 0;
+        if depth <= 0 || ply >= MAX_PLY - 1 {
+            return self.quiescence(board, ply, alpha, beta);
+        }
+        self.nodes += 1;
+        if ply > 0 && self.should_stop() {
+            return 0;
+        }
+
+        let mut actions = board.get_actions();
+        let pv_action = prev_pv.get(ply).copied();
+        order_actions(board, &mut actions, pv_action);
+
+        let mut best = -INFINITY;
+        for action in actions {
+            if !board.perform_action(action) {
+                continue;
+            }
+            // only the first child of a pv node gets to follow the old pv
+            let child_pv = if Some(action) == pv_action {
+                prev_pv
+            } else {
+                &[]
+            };
+            let score = -self.negamax(board, depth - 1, ply + 1, -beta, -alpha, child_pv);
+            board.undo_action();
+            if self.stopped {
+                return 0;
+            }
+
+            if score > best {
+                best = score;
+                if score > alpha {
+                    alpha = score;
+                    self.update_pv(ply, action);
+                    if score >= beta {
+                        break;
+                    }
+                }
+            }
+        }
+
+        // no legal actions is a loss in shogi, stalemate included
+        if best == -INFINITY {
+            return -MATE + ply as i32;
+        }
+        best
+    }
+
+    fn quiescence(&mut self, board: &mut Board, ply: usize, mut alpha: i32, beta: i32) -> i32 {
+        self.nodes += 1;
+        self.pv_len[ply] = 0;
+        if self.should_stop() {
+            return 0;
+        }
+        let stand_pat = match &self.network {
+            Some(network) => network.evaluate(board),
+            None => evaluate(board),
+        };
+        if stand_pat >= beta || ply >= MAX_PLY - 1 {
+            return stand_pat;
+        }
+        alpha = alpha.max(stand_pat);
+
+        let mut actions = board.get_actions();
+        actions.retain(|action| {
+            !action.is_drop() && board.piece_on_square(action.to()) != Piece::NONE
+        });
+        order_actions(board, &mut actions, None);
+
+        let mut best = stand_pat;
+        for action in actions {
+            if !board.perform_action(action) {
+                continue;
+            }
+            let score = -self.quiescence(board, ply + 1, -beta, -alpha);
+            board.undo_action();
+            if self.stopped {
+                return 0;
+            }
+            if score > best {
+                best = score;
+                if score > alpha {
+                    alpha = score;
+                    self.update_pv(ply, action);
+                    if score >= beta {
+                        break;
+                    }
+                }
+            }
+        }
+        best
+    }
+
+    fn update_pv(&mut self, ply: usize, action: Action) {
+        let child_len = self.pv_len[ply + 1];
+        let (parent, child) = self.pv.split_at_mut(ply + 1);
+        parent[ply][0] = action;
+        parent[ply][1..=child_len].copy_from_slice(&child[0][..child_len]);
+        self.pv_len[ply] = child_len + 1;
+    }
+}
+
+// the pv action first, then captures of valuable pieces by cheap ones, then promotions
+fn order_actions(board: &Board, actions: &mut [Action], pv_action: Option<Action>) {
+    actions.sort_by_cached_key(|action| {
+        if Some(*action) == pv_action {
+            return i32::MIN;
+        }
+        let mut score = 0;
+        if !action.is_drop() {
+            let victim = board.piece_on_square(action.to());
+            if victim != Piece::NONE {
+                let attacker = board.piece_on_square(action.from());
+                score += PIECE_VALUES[victim.piece().as_usize()] * 16
+                    - PIECE_VALUES[attacker.piece().as_usize()];
+            }
+            if action.is_promo() {
+                score += 500;
+            }
+        }
+        -score
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicBool;
+
+    use super::{search, SearchLimits, MATE};
+    use crate::board::Board;
+
+    fn best(fen: &str, depth: u8) -> (String, i32) {
+        let mut board = Board::from_fen(fen);
+        let limits = SearchLimits {
+            depth: Some(depth),
+            ..Default::default()
+        };
+        let result = search(&mut board, limits, &AtomicBool::new(false), |_| {});
+        (result.best.unwrap().to_usi(), result.score)
+    }
+
+    #[test]
+    fn finds_mate_in_one() {
+        assert_eq!(
+            best("4k4/9/4P4/9/9/9/9/9/4K4 b G 1", 3),
+            ("G*5b".to_owned(), MATE - 1)
+        );
+    }
+
+    #[test]
+    fn takes_hanging_pieces() {
+        let (action, score) = best("4k4/9/9/9/4r4/9/9/4R4/4K4 b - 1", 2);
+        assert_eq!(action, "5h5e");
+        assert!(score > 0);
+    }
+}
@@ -0,0 +1,81 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    time::Instant,
+};
+
+use ctenophore::datagen::{generate, DatagenConfig};
+
+// usage: datagen <output> [games n] [threads n] [depth n] [nodes n] [random n] [maxplies n]
+// [seed n] [resign score,moves] [draw score,moves,ply], giving nodes searches by node count
+// instead of depth
+pub fn run(args: &[String]) {
+    let [path, rest @ ..] = args else {
+        println!(
+            "usage: datagen <output> [games n] [threads n] [depth n] [nodes n] [random n] \
+             [maxplies n] [seed n] [resign score,moves] [draw score,moves,ply]"
+        );
+        return;
+    };
+
+    let mut config = DatagenConfig::default();
+    for pair in rest.chunks(2) {
+        let [name, value] = pair else {
+            println!("missing value for {}", pair[0]);
+            return;
+        };
+        // the adjudication rules are the only values that aren't plain numbers
+        if name == "resign" || name == "draw" {
+            let parsed = if name == "resign" {
+                value
+                    .parse()
+                    .map(|rule| config.adjudication.resign = Some(rule))
+            } else {
+                value
+                    .parse()
+                    .map(|rule| config.adjudication.draw = Some(rule))
+            };
+            if let Err(e) = parsed {
+                println!("invalid {name}: {e}");
+                return;
+            }
+            continue;
+        }
+        let Ok(value) = value.parse::<u64>() else {
+            println!("invalid {name}: {value}");
+            return;
+        };
+        match name.as_str() {
+            "games" => config.games = value as usize,
+            "threads" => config.threads = value as usize,
+            "depth" => config.limits.depth = Some(value.min(u8::MAX.into()) as u8),
+            "nodes" => {
+                config.limits.depth = None;
+                config.limits.nodes = Some(value);
+            }
+            "random" => config.random_plies = value as usize,
+            "maxplies" => config.max_plies = value as usize,
+            "seed" => config.seed = value,
+            _ => {
+                println!("unknown option: {name}");
+                return;
+            }
+        }
+    }
+
+    let file = File::create(path).expect("failed to create the output file");
+    let mut output = BufWriter::new(file);
+    let start = Instant::now();
+    let samples = generate(&config, &mut output, |games, samples| {
+        if games % 10 == 0 || games == config.games {
+            println!(
+                "{games}/{} games, {samples} positions, {:.0} positions/s",
+                config.games,
+                samples as f64 / start.elapsed().as_secs_f64()
+            );
+        }
+    })
+    .expect("failed to write samples");
+    output.flush().expect("failed to write samples");
+    println!("wrote {samples} positions to {path}");
+}
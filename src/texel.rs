@@ -0,0 +1,62 @@
+use std::{fs, time::Instant};
+
+use ctenophore::{
+    packed_position::PackedFile,
+    tune::{load_packed, Tuner},
+};
+
+// usage: tune <input> <output> [epochs n] [lr x]
+// fits k on datagen's packed entries, runs full batch gradient descent and writes the tuned
+// constants to <output> as rust
+pub fn run(args: &[String]) {
+    let usage = "usage: tune <input> <output> [epochs n] [lr x]";
+    let [input, output, rest @ ..] = args else {
+        println!("{usage}");
+        return;
+    };
+
+    let mut epochs = 1000;
+    let mut learning_rate = 1.0;
+    for pair in rest.chunks(2) {
+        let [name, value] = pair else {
+            println!("missing value for {}", pair[0]);
+            return;
+        };
+        let parsed = match name.as_str() {
+            "epochs" => value.parse().map(|value| epochs = value).map_err(|_| ()),
+            "lr" => value
+                .parse()
+                .map(|value| learning_rate = value)
+                .map_err(|_| ()),
+            _ => {
+                println!("unknown option: {name}\n{usage}");
+                return;
+            }
+        };
+        if parsed.is_err() {
+            println!("invalid {name}: {value}");
+            return;
+        }
+    }
+
+    let entries = load_packed(&PackedFile::open(input).expect("failed to open the input file"));
+    println!("loaded {} positions", entries.len());
+
+    let mut tuner = Tuner::default();
+    let k = tuner.fit_k(&entries);
+    println!("k = {k:.6}, loss {:.6}", tuner.loss(&entries));
+
+    let start = Instant::now();
+    for epoch in 1..=epochs {
+        let loss = tuner.step(&entries, learning_rate);
+        if epoch % 50 == 0 || epoch == epochs {
+            println!(
+                "epoch {epoch}/{epochs}, loss {loss:.6}, {:.1}s",
+                start.elapsed().as_secs_f64()
+            );
+            // written as it goes so that a long run can be stopped at any point
+            fs::write(output, tuner.to_rust()).expect("failed to write the output file");
+        }
+    }
+    println!("wrote the tuned constants to {output}");
+}
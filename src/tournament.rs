@@ -0,0 +1,865 @@
+//! Matches between USI engines, for testing changes. Two engine processes play games from a list
+//! of openings, each opening twice with colours swapped, and every game is adjudicated with this
+//! crate's rules rather than trusting either engine. Results are summed up as an Elo estimate and,
+//! if configured, a sequential probability ratio test that can end the match early.
+
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    process::{Child, ChildStdin, Command, Stdio},
+    sync::mpsc::{self, Receiver, RecvTimeoutError},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    adjudication::{AdjudicationConfig, Adjudicator},
+    board::{Board, STARTPOS},
+    handicap::Handicap,
+    record::{Game, GameResult, Termination},
+    search::MATE,
+    types::{
+        action::Action,
+        piece::Piece,
+        square::{Square, NUM_SQUARES},
+    },
+};
+
+/// How to start an engine and the USI options to give it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EngineConfig {
+    pub command: String,
+    pub args: Vec<String>,
+    pub options: Vec<(String, String)>,
+}
+
+/// Both sides start with `time` and get `increment` after every action. Once the main time is
+/// gone every action gets `byoyomi` on its own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeControl {
+    pub time: Duration,
+    pub increment: Duration,
+    pub byoyomi: Duration,
+}
+
+impl Default for TimeControl {
+    fn default() -> Self {
+        Self {
+            time: Duration::from_secs(10),
+            increment: Duration::from_millis(100),
+            byoyomi: Duration::ZERO,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MatchConfig {
+    pub engines: [EngineConfig; 2],
+    pub openings: Vec<Opening>,
+    /// The openings are used in order and start over once they run out.
+    pub games: usize,
+    pub time_control: TimeControl,
+    /// How far an engine may go over its clock before it loses on time.
+    pub timeout_margin: Duration,
+    /// Games that go on this long are scored as draws, opening actions included.
+    pub max_moves: usize,
+    /// Resign and draw adjudication from the scores in the engines' `info` lines.
+    pub adjudication: AdjudicationConfig,
+    pub sprt: Option<Sprt>,
+}
+
+impl Default for MatchConfig {
+    fn default() -> Self {
+        Self {
+            engines: Default::default(),
+            openings: vec![Opening::default()],
+            games: 100,
+            time_control: TimeControl::default(),
+            timeout_margin: Duration::from_millis(100),
+            max_moves: 512,
+            adjudication: AdjudicationConfig::default(),
+            sprt: None,
+        }
+    }
+}
+
+/// A position to start games from, with the actions that led to it if there were any.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Opening {
+    pub fen: String,
+    pub actions: Vec<Action>,
+}
+
+impl Default for Opening {
+    fn default() -> Self {
+        Self {
+            fen: STARTPOS.to_owned(),
+            actions: Vec::new(),
+        }
+    }
+}
+
+impl Opening {
+    /// Parses a line of an opening file: an SFEN, optionally after `sfen `, `startpos` or
+    /// `startpos handicap <name>`, then optionally `moves` and USI actions.
+    pub fn parse(line: &str) -> Result<Self, String> {
+        let line = line.trim();
+        let (position, moves) = match line.split_once("moves") {
+            Some((position, moves)) => (position.trim(), moves),
+            None => (line, ""),
+        };
+        let fen = match position {
+            "startpos" => STARTPOS.to_owned(),
+            _ => match position.strip_prefix("startpos handicap ") {
+                Some(name) => name.trim().parse::<Handicap>()?.fen().to_owned(),
+                None => position
+                    .strip_prefix("sfen ")
+                    .unwrap_or(position)
+                    .to_owned(),
+            },
+        };
+        let mut board = Board::try_from_fen(&fen)?;
+        let mut actions = Vec::new();
+        for usi in moves.split_ascii_whitespace() {
+            let action =
+                Action::from_usi(usi, &board).map_err(|e| format!("invalid move {usi}: {e}"))?;
+            board.perform_action(action);
+            actions.push(action);
+        }
+        Ok(Self { fen, actions })
+    }
+}
+
+/// What an engine said after `go`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EngineReply {
+    /// The `bestmove` argument: a USI action, `resign` or `win`.
+    pub bestmove: String,
+    /// The last score from an `info` line, from the engine's point of view, mates are `MATE`
+    /// minus the distance in plies like the search's own scores.
+    pub score: Option<i32>,
+}
+
+/// A running USI engine process.
+pub struct UsiEngine {
+    /// From `id name`, or the command if the engine didn't send one.
+    pub name: String,
+    child: Child,
+    stdin: ChildStdin,
+    // stdout is read on a thread of its own so that waiting for it can time out
+    lines: Receiver<String>,
+}
+
+// how long an engine gets to answer anything that isn't a search
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn timed_out(waiting_for: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::TimedOut,
+        format!("engine didn't send {waiting_for}"),
+    )
+}
+
+impl UsiEngine {
+    /// Starts the engine, sets its options and waits until it is ready.
+    pub fn start(config: &EngineConfig) -> io::Result<Self> {
+        let mut child = Command::new(&config.command)
+            .args(&config.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+        let stdin = child.stdin.take().expect("stdin is piped");
+        let stdout = child.stdout.take().expect("stdout is piped");
+        let (sender, lines) = mpsc::channel();
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                let Ok(line) = line else {
+                    break;
+                };
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let mut engine = Self {
+            name: config.command.clone(),
+            child,
+            stdin,
+            lines,
+        };
+        engine.send("usi")?;
+        let deadline = Instant::now() + HANDSHAKE_TIMEOUT;
+        loop {
+            let line = engine
+                .receive(deadline)?
+                .ok_or_else(|| timed_out("usiok"))?;
+            if let Some(name) = line.strip_prefix("id name ") {
+                engine.name = name.trim().to_owned();
+            } else if line.trim() == "usiok" {
+                break;
+            }
+        }
+        for (name, value) in &config.options {
+            engine.send(&format!("setoption name {name} value {value}"))?;
+        }
+        engine.wait_until_ready()?;
+        Ok(engine)
+    }
+
+    fn send(&mut self, line: &str) -> io::Result<()> {
+        writeln!(self.stdin, "{line}")?;
+        self.stdin.flush()
+    }
+
+    // None once the deadline has passed
+    fn receive(&mut self, deadline: Instant) -> io::Result<Option<String>> {
+        match self
+            .lines
+            .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+        {
+            Ok(line) => Ok(Some(line)),
+            Err(RecvTimeoutError::Timeout) => Ok(None),
+            Err(RecvTimeoutError::Disconnected) => Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("{} exited", self.name),
+            )),
+        }
+    }
+
+    fn wait_until_ready(&mut self) -> io::Result<()> {
+        self.send("isready")?;
+        let deadline = Instant::now() + HANDSHAKE_TIMEOUT;
+        loop {
+            let line = self
+                .receive(deadline)?
+                .ok_or_else(|| timed_out("readyok"))?;
+            if line.trim() == "readyok" {
+                return Ok(());
+            }
+        }
+    }
+
+    pub fn new_game(&mut self) -> io::Result<()> {
+        self.wait_until_ready()?;
+        self.send("usinewgame")
+    }
+
+    /// Sends `position` and `go` lines and waits for `bestmove` until the deadline. None if the
+    /// engine didn't answer in time, in which case it is stopped.
+    pub fn go(
+        &mut self,
+        position: &str,
+        go: &str,
+        deadline: Instant,
+    ) -> io::Result<Option<EngineReply>> {
+        self.send(position)?;
+        self.send(go)?;
+        let mut score = None;
+        while let Some(line) = self.receive(deadline)? {
+            let mut tokens = line.split_ascii_whitespace();
+            match tokens.next() {
+                Some("info") => score = parse_score(tokens).or(score),
+                Some("bestmove") => {
+                    return Ok(Some(EngineReply {
+                        bestmove: tokens.next().unwrap_or("resign").to_owned(),
+                        score,
+                    }))
+                }
+                _ => {}
+            }
+        }
+
+        // the late bestmove is thrown away so it doesn't get mistaken for the next one
+        self.send("stop")?;
+        let deadline = Instant::now() + HANDSHAKE_TIMEOUT;
+        while let Some(line) = self.receive(deadline)? {
+            if line.starts_with("bestmove") {
+                break;
+            }
+        }
+        Ok(None)
+    }
+
+    /// Tells the engine the game is over, `result` from its point of view.
+    pub fn game_over(&mut self, result: Option<bool>) -> io::Result<()> {
+        self.send(match result {
+            Some(true) => "gameover win",
+            Some(false) => "gameover lose",
+            None => "gameover draw",
+        })
+    }
+}
+
+impl Drop for UsiEngine {
+    fn drop(&mut self) {
+        let _ = self.send("quit");
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_secs(1) {
+            if !matches!(self.child.try_wait(), Ok(None)) {
+                return;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+// the score in an info line, if it has one
+fn parse_score<'a>(mut tokens: impl Iterator<Item = &'a str>) -> Option<i32> {
+    tokens.find(|token| *token == "score")?;
+    let kind = tokens.next()?;
+    let value = tokens.next()?;
+    match kind {
+        "cp" => value.parse().ok(),
+        // `mate +` and `mate -` are mates of unknown distance
+        "mate" => {
+            let plies = value.parse::<i32>().unwrap_or(0);
+            Some(if value.starts_with('-') {
+                -MATE - plies
+            } else {
+                MATE - plies
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Whether the side to move can declare an entering king win under the 27 point rule: its king
+/// is in the enemy camp and not in check, along with at least 10 of its other pieces, and those
+/// pieces plus its hand are worth 28 points for sente or 27 for gote, with rooks and bishops
+/// worth 5 and everything else 1.
+pub fn can_declare_win(board: &Board) -> bool {
+    let stm = board.stm();
+    let in_camp = |sq: Square| {
+        if stm == 0 {
+            sq.rank() >= 6
+        } else {
+            sq.rank() <= 2
+        }
+    };
+    if !in_camp(board.king_sq()) || board.in_check() {
+        return false;
+    }
+
+    let value = |piece: Piece| {
+        if matches!(piece.piece().unpromote(), Piece::ROOK | Piece::BISHOP) {
+            5
+        } else {
+            1
+        }
+    };
+    let mut pieces = 0;
+    let mut points = 0;
+    for sq in (0..NUM_SQUARES).map(Square).filter(|sq| in_camp(*sq)) {
+        let piece = board.piece_on_square(sq);
+        if piece != Piece::NONE && piece.side() == stm && piece.piece() != Piece::KING {
+            pieces += 1;
+            points += value(piece);
+        }
+    }
+    for (piece, count) in board.hand(stm) {
+        points += value(piece) * u32::from(count);
+    }
+    pieces >= 10 && points >= if stm == 0 { 28 } else { 27 }
+}
+
+/// Checks the last position of `history` for sennichite. Every entry is a position's hash and
+/// whether the side to move was in check in it. Fourfold repetition is a draw, unless one side
+/// gave check with every action since the first repetition, in which case that side loses.
+pub fn sennichite(history: &[(u64, bool)]) -> Option<Termination> {
+    let (hash, _) = *history.last()?;
+    let repeats: Vec<usize> = (0..history.len())
+        .filter(|&i| history[i].0 == hash)
+        .collect();
+    if repeats.len() < 4 {
+        return None;
+    }
+
+    // positions an even number of plies apart have the same side to move
+    let cycle = &history[repeats[0]..];
+    let checked = |parity: usize| {
+        cycle
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| i % 2 == parity)
+            .all(|(_, (_, in_check))| *in_check)
+    };
+    Some(if checked(0) {
+        // the side to move was checked the whole time
+        Termination::OpponentIllegalMove
+    } else if checked(1) {
+        Termination::IllegalMove
+    } else {
+        Termination::Sennichite
+    })
+}
+
+/// Ends the game if it is over before the side to move plays: checkmate (or having no legal
+/// actions at all), sennichite, an entering king win for the side to move or the move limit.
+/// `history` is as in [`sennichite`], with the current position last.
+pub fn adjudicate(
+    board: &mut Board,
+    history: &[(u64, bool)],
+    max_moves: usize,
+) -> Option<Termination> {
+    if !board.has_legal_action() {
+        return Some(Termination::Checkmate);
+    }
+    if let Some(termination) = sennichite(history) {
+        return Some(termination);
+    }
+    if can_declare_win(board) {
+        return Some(Termination::EnteringKing);
+    }
+    (history.len() > max_moves).then_some(Termination::MaxMoves)
+}
+
+// the usi go command for the side to move with the clocks as they are
+fn go_command(remaining: [Duration; 2], control: TimeControl) -> String {
+    let mut command = format!(
+        "go btime {} wtime {}",
+        remaining[0].as_millis(),
+        remaining[1].as_millis()
+    );
+    if control.byoyomi > Duration::ZERO {
+        command += &format!(" byoyomi {}", control.byoyomi.as_millis());
+    } else {
+        command += &format!(
+            " binc {} winc {}",
+            control.increment.as_millis(),
+            control.increment.as_millis()
+        );
+    }
+    command
+}
+
+// an engine that stopped working loses, whether or not it is the side to move
+fn forfeit(board: &Board, side: usize) -> Termination {
+    if side == board.stm() as usize {
+        Termination::IllegalMove
+    } else {
+        Termination::OpponentIllegalMove
+    }
+}
+
+/// Plays one game from an opening, `engines[0]` playing sente. The game comes back with the
+/// engine names as the sente and gote headers, seconds spent on every action and how it ended,
+/// with a comment at the end saying why if it was adjudicated from the engines' scores.
+///
+/// An engine that fails, by exiting or not answering `isready`, loses the game. Its error comes
+/// back by side along with the game so that it can be restarted.
+pub fn play_game(
+    mut engines: [&mut UsiEngine; 2],
+    opening: &Opening,
+    config: &MatchConfig,
+) -> (Game, [Option<io::Error>; 2]) {
+    let mut game = Game::new(&opening.fen);
+    for (key, engine) in ["先手", "後手"].into_iter().zip(&engines) {
+        game.headers.push((key.to_owned(), engine.name.clone()));
+    }
+    let mut errors = [None, None];
+
+    let mut board = Board::from_fen(&opening.fen);
+    let mut history = vec![(board.hash(), board.in_check())];
+    for action in &opening.actions {
+        board.perform_action(*action);
+        history.push((board.hash(), board.in_check()));
+        game.actions.push(*action);
+        game.times.push(None);
+    }
+
+    let control = config.time_control;
+    let mut remaining = [control.time; 2];
+    let mut adjudicator = Adjudicator::new(config.adjudication);
+    // an engine that can't start a game loses it before the first action
+    let mut failed_start = engines
+        .iter_mut()
+        .enumerate()
+        .find_map(|(side, engine)| engine.new_game().err().map(|e| (side, e)));
+    let termination = loop {
+        if let Some((side, e)) = failed_start.take() {
+            errors[side] = Some(e);
+            break forfeit(&board, side);
+        }
+        if let Some(termination) = adjudicate(&mut board, &history, config.max_moves) {
+            break termination;
+        }
+        if let Some((termination, reason)) = adjudicator.adjudicate(board.stm()) {
+            game.comments.push((game.actions.len(), reason));
+            break termination;
+        }
+
+        let stm = board.stm() as usize;
+        let mut position = format!("position sfen {}", opening.fen);
+        if !game.actions.is_empty() {
+            position += " moves";
+            for action in &game.actions {
+                position += &format!(" {action}");
+            }
+        }
+        let start = Instant::now();
+        let deadline = start + remaining[stm] + control.byoyomi + config.timeout_margin;
+        let reply = match engines[stm].go(&position, &go_command(remaining, control), deadline) {
+            Ok(Some(reply)) => reply,
+            Ok(None) => break Termination::Timeout,
+            Err(e) => {
+                errors[stm] = Some(e);
+                break forfeit(&board, stm);
+            }
+        };
+        let elapsed = start.elapsed();
+        // going over the main time is fine as long as it's within the byoyomi
+        remaining[stm] = remaining[stm].saturating_sub(elapsed) + control.increment;
+
+        let action = match reply.bestmove.as_str() {
+            "resign" => break Termination::Resign,
+            "win" if can_declare_win(&board) => break Termination::EnteringKing,
+            "win" => break Termination::IllegalMove,
+            usi => match Action::from_usi(usi, &board) {
+                Ok(action) => action,
+                Err(_) => break Termination::IllegalMove,
+            },
+        };
+        board.perform_action(action);
+        history.push((board.hash(), board.in_check()));
+        game.actions.push(action);
+        game.times.push(Some(elapsed.as_secs() as u32));
+        adjudicator.record(stm as u8, reply.score, game.actions.len());
+    };
+    game.termination = Some(termination);
+    for (engine, error) in engines.iter().zip(&errors) {
+        if let Some(e) = error {
+            let comment = format!("{} failed: {e}", engine.name);
+            game.comments.push((game.actions.len(), comment));
+        }
+    }
+
+    let result = game.result();
+    for (side, engine) in engines.into_iter().enumerate() {
+        if errors[side].is_some() {
+            continue;
+        }
+        let over = engine.game_over(match result {
+            Some(GameResult::SenteWin) => Some(side == 0),
+            Some(GameResult::GoteWin) => Some(side == 1),
+            _ => None,
+        });
+        if let Err(e) = over {
+            errors[side] = Some(e);
+        }
+    }
+    (game, errors)
+}
+
+/// Wins, draws and losses of the first engine.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Score {
+    pub wins: u32,
+    pub draws: u32,
+    pub losses: u32,
+}
+
+// the logistic elo difference for an expected score
+fn elo_from_score(score: f64) -> f64 {
+    400.0 * (score / (1.0 - score)).log10()
+}
+
+fn score_from_elo(elo: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-elo / 400.0))
+}
+
+impl Score {
+    pub fn games(&self) -> u32 {
+        self.wins + self.draws + self.losses
+    }
+
+    // the mean and per game variance of the score
+    fn stats(&self) -> (f64, f64) {
+        let games = f64::from(self.games());
+        let (wins, draws) = (f64::from(self.wins) / games, f64::from(self.draws) / games);
+        let mean = wins + draws / 2.0;
+        (mean, wins + draws / 4.0 - mean * mean)
+    }
+
+    /// The Elo difference and the half width of its 95% confidence interval. Infinite when one
+    /// engine won every game, None before any games.
+    pub fn elo(&self) -> Option<(f64, f64)> {
+        if self.games() == 0 {
+            return None;
+        }
+        let (mean, variance) = self.stats();
+        let deviation = (variance / f64::from(self.games())).sqrt();
+        let clamp = |score: f64| score.clamp(f64::MIN_POSITIVE, 1.0 - f64::EPSILON);
+        let low = elo_from_score(clamp(mean - 1.96 * deviation));
+        let high = elo_from_score(clamp(mean + 1.96 * deviation));
+        Some((elo_from_score(mean), (high - low) / 2.0))
+    }
+}
+
+/// A sequential probability ratio test of whether the first engine is `elo1` stronger (H1)
+/// rather than `elo0` (H0), with logistic Elo, false positive rate `alpha` and false negative
+/// rate `beta`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sprt {
+    pub elo0: f64,
+    pub elo1: f64,
+    pub alpha: f64,
+    pub beta: f64,
+}
+
+impl Default for Sprt {
+    fn default() -> Self {
+        Self {
+            elo0: 0.0,
+            elo1: 5.0,
+            alpha: 0.05,
+            beta: 0.05,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SprtStatus {
+    Continue,
+    AcceptH0,
+    AcceptH1,
+}
+
+impl Sprt {
+    /// The log likelihood ratio, using a normal approximation of the trinomial distribution.
+    pub fn llr(&self, score: &Score) -> f64 {
+        if score.games() == 0 {
+            return 0.0;
+        }
+        let (mean, variance) = score.stats();
+        if variance <= 0.0 {
+            return 0.0;
+        }
+        let (s0, s1) = (score_from_elo(self.elo0), score_from_elo(self.elo1));
+        f64::from(score.games()) * (s1 - s0) * (2.0 * mean - s0 - s1) / (2.0 * variance)
+    }
+
+    /// The LLR at which H0 and H1 are accepted.
+    pub fn bounds(&self) -> (f64, f64) {
+        (
+            (self.beta / (1.0 - self.alpha)).ln(),
+            ((1.0 - self.beta) / self.alpha).ln(),
+        )
+    }
+
+    pub fn status(&self, score: &Score) -> SprtStatus {
+        let llr = self.llr(score);
+        let (lower, upper) = self.bounds();
+        if llr >= upper {
+            SprtStatus::AcceptH1
+        } else if llr <= lower {
+            SprtStatus::AcceptH0
+        } else {
+            SprtStatus::Continue
+        }
+    }
+}
+
+/// Plays the match, calling `on_game` with every game's index and record along with the score
+/// so far. Game `2n` has the first engine as sente and game `2n + 1` swaps colours on the same
+/// opening. Stops early once the SPRT, if there is one, is decided. An engine that fails during a
+/// game is started again for the next one, the match only fails if it can't be started or
+/// `on_game` fails.
+pub fn run_match(
+    config: &MatchConfig,
+    mut on_game: impl FnMut(usize, &Game, &Score) -> io::Result<()>,
+) -> io::Result<Score> {
+    let mut engines = [
+        UsiEngine::start(&config.engines[0])?,
+        UsiEngine::start(&config.engines[1])?,
+    ];
+    let mut score = Score::default();
+    for index in 0..config.games {
+        let opening = &config.openings[index / 2 % config.openings.len()];
+        let [first, second] = &mut engines;
+        let first_is_sente = index % 2 == 0;
+        let players = if first_is_sente {
+            [first, second]
+        } else {
+            [second, first]
+        };
+        let (game, errors) = play_game(players, opening, config);
+
+        match (game.result(), first_is_sente) {
+            (Some(GameResult::Draw), _) => score.draws += 1,
+            (Some(GameResult::SenteWin), true) | (Some(GameResult::GoteWin), false) => {
+                score.wins += 1
+            }
+            (Some(_), _) => score.losses += 1,
+            (None, _) => {}
+        }
+        on_game(index, &game, &score)?;
+        for (side, error) in errors.into_iter().enumerate() {
+            if error.is_some() {
+                let engine = usize::from((side == 0) != first_is_sente);
+                engines[engine] = UsiEngine::start(&config.engines[engine])?;
+            }
+        }
+        if config
+            .sprt
+            .is_some_and(|sprt| sprt.status(&score) != SprtStatus::Continue)
+        {
+            break;
+        }
+    }
+    Ok(score)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        can_declare_win, parse_score, run_match, sennichite, EngineConfig, MatchConfig, Opening,
+        Score, Sprt, SprtStatus,
+    };
+    use crate::{
+        board::{Board, STARTPOS},
+        handicap::Handicap,
+        record::Termination,
+        search::MATE,
+    };
+
+    #[test]
+    fn entering_king() {
+        // 12 points on the board in big pieces, 6 pawns and both spare big pieces in hand
+        let board = Board::from_fen("+R+BGG5/4K4/PPPPPP3/9/9/9/9/9/8k b RB 1");
+        assert!(can_declare_win(&board));
+        // 27 points is a point short for sente, but enough for gote
+        let board = Board::from_fen("+R+BGG5/4K4/PPPPPP3/9/9/9/9/9/8k b R4P 1");
+        assert!(!can_declare_win(&board));
+        let board = Board::from_fen("K8/9/9/9/9/9/3pppppp/4k4/5gg+b+r w r4p 1");
+        assert!(can_declare_win(&board));
+        // only 9 pieces in the camp
+        let board = Board::from_fen("+R+BGG5/4K4/PPPPP4/9/9/9/9/9/8k b RB2P 1");
+        assert!(!can_declare_win(&board));
+        assert!(!can_declare_win(&Board::from_fen(STARTPOS)));
+    }
+
+    #[test]
+    fn repetition() {
+        // a, b, c, d repeated, with the side to move at a and c checked every time
+        let cycle = [(1, true), (2, false), (3, true), (4, false)];
+        let mut history: Vec<(u64, bool)> = Vec::new();
+        for _ in 0..3 {
+            history.extend(cycle);
+        }
+        history.push(cycle[0]);
+        assert_eq!(sennichite(&history), Some(Termination::OpponentIllegalMove));
+        assert_eq!(sennichite(&history[1..]), None);
+        // a check from the other side breaks the chain
+        history[2].1 = false;
+        assert_eq!(sennichite(&history), Some(Termination::Sennichite));
+        // the side to move giving every check
+        let history: Vec<(u64, bool)> = (0..13).map(|i| (i % 2, i % 2 == 1)).collect();
+        assert_eq!(sennichite(&history), Some(Termination::IllegalMove));
+    }
+
+    #[test]
+    fn elo_and_sprt() {
+        let even = Score {
+            wins: 40,
+            draws: 20,
+            losses: 40,
+        };
+        let (elo, margin) = even.elo().unwrap();
+        assert!(elo.abs() < 1e-9);
+        assert!(margin > 50.0 && margin < 80.0, "{margin}");
+
+        // 75% is 190.8 elo
+        let strong = Score {
+            wins: 700,
+            draws: 100,
+            losses: 200,
+        };
+        assert!((strong.elo().unwrap().0 - 190.85).abs() < 0.1);
+        assert_eq!(Score::default().elo(), None);
+
+        let sprt = Sprt::default();
+        let (lower, upper) = sprt.bounds();
+        assert!((lower + 2.944).abs() < 1e-3 && (upper - 2.944).abs() < 1e-3);
+        assert_eq!(sprt.status(&strong), SprtStatus::AcceptH1);
+        let weak = Score {
+            wins: 200,
+            draws: 100,
+            losses: 700,
+        };
+        assert_eq!(sprt.status(&weak), SprtStatus::AcceptH0);
+        let few = Score {
+            wins: 3,
+            draws: 1,
+            losses: 2,
+        };
+        assert_eq!(sprt.status(&few), SprtStatus::Continue);
+    }
+
+    #[test]
+    fn openings_and_scores() {
+        let opening = Opening::parse("startpos moves 7g7f 3c3d").unwrap();
+        assert_eq!(opening.fen, STARTPOS);
+        assert_eq!(opening.actions.len(), 2);
+        let opening = Opening::parse(&format!("sfen {STARTPOS}")).unwrap();
+        assert!(opening.actions.is_empty());
+        let opening = Opening::parse("startpos handicap rook moves 3c3d").unwrap();
+        assert_eq!(opening.fen, Handicap::Rook.fen());
+        assert_eq!(opening.actions.len(), 1);
+        assert!(Opening::parse("startpos moves 7g7e").is_err());
+        assert!(Opening::parse("not a position").is_err());
+
+        let info = |line: &str| parse_score(line.split_ascii_whitespace());
+        assert_eq!(info("info depth 3 score cp -45 pv 7g7f"), Some(-45));
+        assert_eq!(info("info score mate 3 pv 7g7f"), Some(MATE - 3));
+        assert_eq!(info("info score mate -"), Some(-MATE));
+        assert_eq!(info("info depth 1 nodes 30"), None);
+    }
+
+    // a shell script engine that does `on_go` when told to search
+    #[cfg(unix)]
+    fn script_engine(on_go: &str) -> EngineConfig {
+        let script = format!(
+            "while read line; do case \"$line\" in usi) echo usiok;; isready) echo readyok;; \
+             go*) {on_go};; quit) exit;; esac; done"
+        );
+        EngineConfig {
+            command: "sh".to_owned(),
+            args: vec!["-c".to_owned(), script],
+            options: Vec::new(),
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn failing_engines_lose_and_restart() {
+        let config = MatchConfig {
+            engines: [
+                script_engine("exit 1"),
+                script_engine("echo bestmove resign"),
+            ],
+            games: 2,
+            ..Default::default()
+        };
+        let mut games = Vec::new();
+        let score = run_match(&config, |_, game, _| {
+            games.push(game.clone());
+            Ok(())
+        })
+        .unwrap();
+        // the first engine exits as sente, then is started again and wins as gote
+        assert_eq!(
+            score,
+            Score {
+                wins: 1,
+                draws: 0,
+                losses: 1
+            }
+        );
+        assert_eq!(games[0].termination, Some(Termination::IllegalMove));
+        assert_eq!(games[0].comments, [(0, "sh failed: sh exited".to_owned())]);
+        assert_eq!(games[1].termination, Some(Termination::Resign));
+    }
+}
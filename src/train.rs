@@ -0,0 +1,72 @@
+use std::{fs, time::Instant};
+
+use ctenophore::{
+    packed_position::PackedFile,
+    trainer::{TrainConfig, Trainer},
+};
+
+// usage: train <input> <output> [hidden n] [epochs n] [batch n] [lr x] [threads n] [wdl x]
+// [seed n], trains on packed records and writes the quantised network to <output>
+pub fn run(args: &[String]) {
+    let usage = "usage: train <input> <output> [hidden n] [epochs n] [batch n] [lr x] \
+                 [threads n] [wdl x] [seed n]";
+    let [input, output, rest @ ..] = args else {
+        println!("{usage}");
+        return;
+    };
+
+    let mut config = TrainConfig::default();
+    let mut epochs = 10;
+    for pair in rest.chunks(2) {
+        let [name, value] = pair else {
+            println!("missing value for {}", pair[0]);
+            return;
+        };
+        let parsed = match name.as_str() {
+            "hidden" => value.parse().map(|value| config.hidden = value).is_ok(),
+            "epochs" => value.parse().map(|value| epochs = value).is_ok(),
+            "batch" => value.parse().map(|value| config.batch_size = value).is_ok(),
+            "lr" => value
+                .parse()
+                .map(|value| config.learning_rate = value)
+                .is_ok(),
+            "threads" => value.parse().map(|value| config.threads = value).is_ok(),
+            "wdl" => value
+                .parse()
+                .ok()
+                .filter(|value| (0.0..=1.0).contains(value))
+                .map(|value| config.wdl = value)
+                .is_some(),
+            "seed" => value.parse().map(|value| config.seed = value).is_ok(),
+            _ => {
+                println!("unknown option: {name}\n{usage}");
+                return;
+            }
+        };
+        if !parsed {
+            println!("invalid {name}: {value}");
+            return;
+        }
+    }
+    if config.hidden == 0 {
+        println!("invalid hidden: 0");
+        return;
+    }
+
+    let entries = PackedFile::open(input).expect("failed to open the input file");
+    println!("loaded {} positions", entries.len());
+
+    let mut trainer = Trainer::new(config);
+    println!("loss {:.6}", trainer.loss(&entries));
+    let start = Instant::now();
+    for epoch in 1..=epochs {
+        let loss = trainer.train_epoch(&entries);
+        println!(
+            "epoch {epoch}/{epochs}, loss {loss:.6}, {:.1}s",
+            start.elapsed().as_secs_f64()
+        );
+        // the file always holds the latest epoch's network, ready for EvalFile
+        fs::write(output, trainer.quantise().to_bytes()).expect("failed to write the output file");
+    }
+    println!("wrote the network to {output}");
+}
@@ -0,0 +1,477 @@
+//! Trains the [`nnue`](crate::nnue) network on the CPU from [`PackedEntry`] records. Training is
+//! minibatch Adam on the squared error between `sigmoid(output)` and a blend of the game result
+//! and the search score, with every batch split across threads. Only the feature transformer rows
+//! of features that show up in a batch are updated, which keeps a step cheap however many
+//! features there are.
+//!
+//! Weights are floats while training and quantised into a [`Network`] at the end.
+
+use std::{collections::HashMap, thread};
+
+use crate::{
+    datagen::Rng,
+    nnue::{features, Network, NUM_FEATURES, QA, QB, SCALE},
+    packed_position::PackedEntry,
+    types::hand::Hand,
+};
+
+#[derive(Debug, Clone, Copy)]
+pub struct TrainConfig {
+    pub hidden: usize,
+    pub batch_size: usize,
+    pub learning_rate: f32,
+    pub threads: usize,
+    /// How much the game result counts against the score, from 0 (only the score) to 1 (only the
+    /// result).
+    pub wdl: f32,
+    /// For the initial weights and the order positions are seen in.
+    pub seed: u64,
+}
+
+impl Default for TrainConfig {
+    fn default() -> Self {
+        Self {
+            hidden: 32,
+            batch_size: 16384,
+            learning_rate: 0.001,
+            threads: 1,
+            wdl: 0.5,
+            seed: 0,
+        }
+    }
+}
+
+// a position ready for the network, features are from the side to move's point of view first
+struct Input {
+    features: [Vec<u32>; 2],
+    target: f32,
+}
+
+impl Input {
+    fn new(entry: &PackedEntry, wdl: f32) -> Option<Self> {
+        let position = &entry.position;
+        let mailbox = position.mailbox()?;
+        let hands = [Hand(position.hands[0]), Hand(position.hands[1])];
+        let stm = position.stm & 1;
+        let mut inputs = [Vec::with_capacity(80), Vec::with_capacity(80)];
+        features(&mailbox, hands, stm, &mut inputs[0]);
+        features(&mailbox, hands, 1 - stm, &mut inputs[1]);
+
+        let result = f32::from(entry.result.signum() + 1) / 2.0;
+        let score = sigmoid(f32::from(entry.score) / SCALE as f32);
+        Some(Self {
+            features: inputs,
+            target: wdl * result + (1.0 - wdl) * score,
+        })
+    }
+}
+
+fn sigmoid(x: f32) -> f32 {
+    1.0 / (1.0 + (-x).exp())
+}
+
+// summed over a slice of a batch, merged across threads before the step
+struct Gradient {
+    ft_weights: HashMap<u32, Vec<f32>>,
+    ft_bias: Vec<f32>,
+    out_weights: Vec<f32>,
+    out_bias: f32,
+    loss: f32,
+    count: usize,
+}
+
+impl Gradient {
+    fn new(hidden: usize) -> Self {
+        Self {
+            ft_weights: HashMap::new(),
+            ft_bias: vec![0.0; hidden],
+            out_weights: vec![0.0; 2 * hidden],
+            out_bias: 0.0,
+            loss: 0.0,
+            count: 0,
+        }
+    }
+
+    fn merge(&mut self, other: Self) {
+        for (feature, row) in other.ft_weights {
+            match self.ft_weights.get_mut(&feature) {
+                Some(existing) => add(existing, &row),
+                None => {
+                    self.ft_weights.insert(feature, row);
+                }
+            }
+        }
+        add(&mut self.ft_bias, &other.ft_bias);
+        add(&mut self.out_weights, &other.out_weights);
+        self.out_bias += other.out_bias;
+        self.loss += other.loss;
+        self.count += other.count;
+    }
+}
+
+fn add(to: &mut [f32], from: &[f32]) {
+    for (to, from) in to.iter_mut().zip(from) {
+        *to += from;
+    }
+}
+
+// adam's moment estimates for a set of weights
+#[derive(Debug, Clone)]
+struct Moments {
+    momentum: Vec<f32>,
+    velocity: Vec<f32>,
+}
+
+impl Moments {
+    fn new(len: usize) -> Self {
+        Self {
+            momentum: vec![0.0; len],
+            velocity: vec![0.0; len],
+        }
+    }
+}
+
+const BETA1: f32 = 0.9;
+const BETA2: f32 = 0.999;
+
+fn adam(weights: &mut [f32], moments: &mut Moments, offset: usize, gradient: &[f32], lr: f32) {
+    for (i, &gradient) in gradient.iter().enumerate() {
+        let m = &mut moments.momentum[offset + i];
+        let v = &mut moments.velocity[offset + i];
+        *m = BETA1 * *m + (1.0 - BETA1) * gradient;
+        *v = BETA2 * *v + (1.0 - BETA2) * gradient * gradient;
+        weights[offset + i] -= lr * *m / (v.sqrt() + 1e-8);
+    }
+}
+
+/// The network being trained, in floats.
+#[derive(Debug, Clone)]
+pub struct Trainer {
+    pub config: TrainConfig,
+    ft_weights: Vec<f32>,
+    ft_bias: Vec<f32>,
+    out_weights: Vec<f32>,
+    out_bias: f32,
+    ft_weight_moments: Moments,
+    ft_bias_moments: Moments,
+    out_weight_moments: Moments,
+    out_bias_moments: Moments,
+    steps: i32,
+    rng: Rng,
+}
+
+impl Trainer {
+    pub fn new(config: TrainConfig) -> Self {
+        let hidden = config.hidden;
+        let mut rng = Rng(config.seed);
+        let mut uniform = |range: f32| {
+            let unit = (rng.next() >> 40) as f32 / (1u64 << 24) as f32;
+            (unit * 2.0 - 1.0) * range
+        };
+        let ft_weights = (0..NUM_FEATURES * hidden).map(|_| uniform(0.1)).collect();
+        let out_range = 1.0 / (2.0 * hidden as f32).sqrt();
+        let out_weights = (0..2 * hidden).map(|_| uniform(out_range)).collect();
+        Self {
+            config,
+            ft_weights,
+            ft_bias: vec![0.0; hidden],
+            out_weights,
+            out_bias: 0.0,
+            ft_weight_moments: Moments::new(NUM_FEATURES * hidden),
+            ft_bias_moments: Moments::new(hidden),
+            out_weight_moments: Moments::new(2 * hidden),
+            out_bias_moments: Moments::new(1),
+            steps: 0,
+            rng: Rng(config.seed ^ 0x5eed),
+        }
+    }
+
+    fn accumulate(&self, features: &[u32]) -> Vec<f32> {
+        let hidden = self.config.hidden;
+        let mut accumulator = self.ft_bias.clone();
+        for &feature in features {
+            add(
+                &mut accumulator,
+                &self.ft_weights[feature as usize * hidden..][..hidden],
+            );
+        }
+        accumulator
+    }
+
+    // the raw output, sigmoid(output) is the expected result
+    fn forward(&self, input: &Input) -> ([Vec<f32>; 2], f32) {
+        let hidden = self.config.hidden;
+        let accumulators = [
+            self.accumulate(&input.features[0]),
+            self.accumulate(&input.features[1]),
+        ];
+        let mut output = self.out_bias;
+        for (half, accumulator) in accumulators.iter().enumerate() {
+            let weights = &self.out_weights[half * hidden..][..hidden];
+            for (value, weight) in accumulator.iter().zip(weights) {
+                output += value.clamp(0.0, 1.0) * weight;
+            }
+        }
+        (accumulators, output)
+    }
+
+    /// The evaluation of an entry in centipawns before quantisation, None for invalid entries.
+    pub fn evaluate(&self, entry: &PackedEntry) -> Option<f32> {
+        let input = Input::new(entry, self.config.wdl)?;
+        Some(self.forward(&input).1 * SCALE as f32)
+    }
+
+    fn backward(&self, inputs: &[Input]) -> Gradient {
+        let hidden = self.config.hidden;
+        let mut gradient = Gradient::new(hidden);
+        for input in inputs {
+            let (accumulators, output) = self.forward(input);
+            let predicted = sigmoid(output);
+            let error = predicted - input.target;
+            gradient.loss += error * error;
+            gradient.count += 1;
+
+            let delta = 2.0 * error * predicted * (1.0 - predicted);
+            gradient.out_bias += delta;
+            for (half, accumulator) in accumulators.iter().enumerate() {
+                let mut row_gradient = vec![0.0; hidden];
+                for (j, &value) in accumulator.iter().enumerate() {
+                    let weight = half * hidden + j;
+                    gradient.out_weights[weight] += delta * value.clamp(0.0, 1.0);
+                    // the clipped relu has no slope outside of 0..1
+                    if value > 0.0 && value < 1.0 {
+                        row_gradient[j] = delta * self.out_weights[weight];
+                    }
+                }
+                add(&mut gradient.ft_bias, &row_gradient);
+                for &feature in &input.features[half] {
+                    let row = gradient
+                        .ft_weights
+                        .entry(feature)
+                        .or_insert_with(|| vec![0.0; hidden]);
+                    add(row, &row_gradient);
+                }
+            }
+        }
+        gradient
+    }
+
+    fn step(&mut self, inputs: &[Input]) -> (f32, usize) {
+        let threads = self.config.threads.max(1);
+        let chunk = inputs.len().div_ceil(threads).max(1);
+        let mut gradient = Gradient::new(self.config.hidden);
+        let this = &*self;
+        let gradients: Vec<Gradient> = thread::scope(|scope| {
+            let handles: Vec<_> = inputs
+                .chunks(chunk)
+                .map(|inputs| scope.spawn(move || this.backward(inputs)))
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().expect("training thread panicked"))
+                .collect()
+        });
+        for other in gradients {
+            gradient.merge(other);
+        }
+        if gradient.count == 0 {
+            return (0.0, 0);
+        }
+
+        // bias correction folded into the learning rate
+        self.steps += 1;
+        let lr = self.config.learning_rate * (1.0 - BETA2.powi(self.steps)).sqrt()
+            / (1.0 - BETA1.powi(self.steps));
+        let scale = 1.0 / gradient.count as f32;
+        let scaled = |values: &[f32]| values.iter().map(|value| value * scale).collect::<Vec<_>>();
+
+        let hidden = self.config.hidden;
+        // lazy adam, rows that weren't in the batch keep their moments as they were
+        for (feature, row) in &gradient.ft_weights {
+            let offset = *feature as usize * hidden;
+            adam(
+                &mut self.ft_weights,
+                &mut self.ft_weight_moments,
+                offset,
+                &scaled(row),
+                lr,
+            );
+        }
+        adam(
+            &mut self.ft_bias,
+            &mut self.ft_bias_moments,
+            0,
+            &scaled(&gradient.ft_bias),
+            lr,
+        );
+        adam(
+            &mut self.out_weights,
+            &mut self.out_weight_moments,
+            0,
+            &scaled(&gradient.out_weights),
+            lr,
+        );
+        let mut out_bias = [self.out_bias];
+        adam(
+            &mut out_bias,
+            &mut self.out_bias_moments,
+            0,
+            &[gradient.out_bias * scale],
+            lr,
+        );
+        self.out_bias = out_bias[0];
+        (gradient.loss, gradient.count)
+    }
+
+    /// One pass over the entries in a random order, returns the mean loss. Entries that don't
+    /// hold a valid position are skipped.
+    pub fn train_epoch(&mut self, entries: &[PackedEntry]) -> f32 {
+        let mut order: Vec<usize> = (0..entries.len()).collect();
+        for i in (1..order.len()).rev() {
+            order.swap(i, (self.rng.next() % (i as u64 + 1)) as usize);
+        }
+
+        let mut loss = 0.0;
+        let mut count = 0;
+        for batch in order.chunks(self.config.batch_size.max(1)) {
+            let inputs: Vec<Input> = batch
+                .iter()
+                .filter_map(|&i| Input::new(&entries[i], self.config.wdl))
+                .collect();
+            let (batch_loss, batch_count) = self.step(&inputs);
+            loss += batch_loss;
+            count += batch_count;
+        }
+        loss / count.max(1) as f32
+    }
+
+    /// The mean loss over the entries without training on them.
+    pub fn loss(&self, entries: &[PackedEntry]) -> f32 {
+        let mut loss = 0.0;
+        let mut count = 0;
+        for batch in entries.chunks(self.config.batch_size.max(1)) {
+            let inputs: Vec<Input> = batch
+                .iter()
+                .filter_map(|entry| Input::new(entry, self.config.wdl))
+                .collect();
+            let gradient = self.backward(&inputs);
+            loss += gradient.loss;
+            count += gradient.count;
+        }
+        loss / count.max(1) as f32
+    }
+
+    /// The network in the engine's format.
+    pub fn quantise(&self) -> Network {
+        let quantise = |values: &[f32], scale: i32| {
+            values
+                .iter()
+                .map(|value| (value * scale as f32).round().clamp(-32768.0, 32767.0) as i16)
+                .collect()
+        };
+        Network {
+            hidden: self.config.hidden,
+            ft_weights: quantise(&self.ft_weights, QA),
+            ft_bias: quantise(&self.ft_bias, QA),
+            out_weights: quantise(&self.out_weights, QB),
+            out_bias: (self.out_bias * (QA * QB) as f32).round() as i32,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+
+    use super::{TrainConfig, Trainer};
+    use crate::{
+        board::{Board, STARTPOS},
+        datagen::{generate, DatagenConfig},
+        packed_position::{PackedEntry, PackedFile},
+        search::SearchLimits,
+        types::action::Action,
+    };
+
+    #[test]
+    fn learns_and_quantises() {
+        // sente is a rook up and wins, gote is a bishop up and wins, and a level position is drawn
+        let entries: Vec<PackedEntry> = [
+            (
+                "lnsgkgsnl/7b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL b - 1",
+                1,
+            ),
+            (
+                "lnsgkgsnl/7b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL w - 1",
+                -1,
+            ),
+            (
+                "lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/7R1/LNSGKGSNL w - 1",
+                1,
+            ),
+            (STARTPOS, 0),
+        ]
+        .iter()
+        .map(|(fen, result)| {
+            PackedEntry::new(&Board::from_fen(fen), Action::default(), 0, *result).unwrap()
+        })
+        .collect();
+
+        let config = TrainConfig {
+            hidden: 8,
+            batch_size: 2,
+            learning_rate: 0.001,
+            threads: 2,
+            wdl: 1.0,
+            seed: 1,
+        };
+        let mut trainer = Trainer::new(config);
+        let before = trainer.loss(&entries);
+        for _ in 0..500 {
+            trainer.train_epoch(&entries);
+        }
+        assert!(trainer.loss(&entries) < before / 2.0);
+        assert!(trainer.evaluate(&entries[0]).unwrap() > 100.0);
+        assert!(trainer.evaluate(&entries[1]).unwrap() < -100.0);
+
+        // the quantised network agrees with the floats to within a few centipawns
+        let network = trainer.quantise();
+        for entry in &entries {
+            let float = trainer.evaluate(entry).unwrap();
+            let quantised = network.evaluate(&entry.board().unwrap()) as f32;
+            assert!((float - quantised).abs() < 10.0, "{float} {quantised}");
+        }
+    }
+
+    #[test]
+    fn trains_on_datagen_output() {
+        let config = DatagenConfig {
+            games: 4,
+            seed: 3,
+            limits: SearchLimits {
+                depth: Some(1),
+                ..Default::default()
+            },
+            max_plies: 60,
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        let count = generate(&config, &mut output, |_, _| {}).unwrap();
+        let path = env::temp_dir().join(format!("ctenophore-train-{}.bin", process::id()));
+        fs::write(&path, &output).unwrap();
+        let entries = PackedFile::open(&path).unwrap();
+        assert_eq!(entries.len(), count);
+
+        let mut trainer = Trainer::new(TrainConfig {
+            hidden: 8,
+            batch_size: 16,
+            ..Default::default()
+        });
+        let before = trainer.loss(&entries);
+        for _ in 0..20 {
+            trainer.train_epoch(&entries);
+        }
+        assert!(trainer.loss(&entries) < before);
+        drop(entries);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,288 @@
+//! Texel tuning of the evaluation in [`eval`](crate::eval). The evaluation is linear in its
+//! parameters, so every position boils down to a short list of (parameter, coefficient) pairs
+//! once, and training is gradient descent on the mean squared error between game results and
+//! `sigmoid(k * eval)`. The tuned parameters come out as Rust source to paste over the
+//! constants in `eval.rs`.
+
+use crate::{
+    board::Board,
+    eval::{relative_square, HAND_VALUES, PIECE_VALUES, PST},
+    packed_position::PackedEntry,
+    types::{piece::Piece, square::Square},
+};
+
+const PIECES: usize = 0;
+const HAND: usize = PIECES + 14;
+const TABLES: usize = HAND + 7;
+pub const NUM_PARAMS: usize = TABLES + 14 * 81;
+
+/// A labelled position, reduced to the parameters it uses.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TuneEntry {
+    /// From sente's point of view, with counts for pieces in hand.
+    pub features: Vec<(u16, i8)>,
+    /// 1 for a sente win, 0.5 for a draw and 0 for a gote win.
+    pub result: f64,
+}
+
+impl TuneEntry {
+    /// `result` is from the side to move's point of view: 1, 0 or -1.
+    pub fn new(board: &Board, result: i8) -> Self {
+        let mut features = Vec::with_capacity(80);
+        let sign = |side: u8| if side == 0 { 1 } else { -1 };
+        for sq in 0..81 {
+            let piece = board.piece_on_square(Square(sq));
+            if piece == Piece::NONE {
+                continue;
+            }
+            let piece_type = piece.piece().as_usize();
+            let table = TABLES + piece_type * 81 + relative_square(Square(sq), piece.side());
+            // kings always cancel out, their table is all that matters
+            if piece.piece() != Piece::KING {
+                features.push(((PIECES + piece_type) as u16, sign(piece.side())));
+            }
+            features.push((table as u16, sign(piece.side())));
+        }
+        for side in 0..2 {
+            for (piece, count) in board.hand(side) {
+                features.push(((HAND + piece.as_usize()) as u16, sign(side) * count as i8));
+            }
+        }
+
+        let result = if board.stm() == 0 { result } else { -result };
+        Self {
+            features,
+            result: f64::from(result + 1) / 2.0,
+        }
+    }
+
+    fn eval(&self, params: &[f64]) -> f64 {
+        self.features
+            .iter()
+            .map(|&(index, coefficient)| params[index as usize] * f64::from(coefficient))
+            .sum()
+    }
+}
+
+/// Reads [`PackedEntry`] records, like the ones [`datagen`](crate::datagen) writes, skipping any
+/// that don't hold a valid position.
+pub fn load_packed(entries: &[PackedEntry]) -> Vec<TuneEntry> {
+    entries
+        .iter()
+        .filter_map(|entry| Some(TuneEntry::new(&entry.board()?, entry.result)))
+        .collect()
+}
+
+fn sigmoid(x: f64) -> f64 {
+    1.0 / (1.0 + (-x).exp())
+}
+
+/// The parameters in [`eval`](crate::eval), as floats while they are being tuned.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tuner {
+    pub params: Vec<f64>,
+    /// Scales evaluations in centipawns to the sigmoid.
+    pub k: f64,
+    // adam's moment estimates
+    momentum: Vec<f64>,
+    velocity: Vec<f64>,
+    steps: i32,
+}
+
+impl Default for Tuner {
+    fn default() -> Self {
+        let mut params = vec![0.0; NUM_PARAMS];
+        for (i, value) in PIECE_VALUES.iter().enumerate() {
+            params[PIECES + i] = f64::from(*value);
+        }
+        for (i, value) in HAND_VALUES.iter().enumerate() {
+            params[HAND + i] = f64::from(*value);
+        }
+        for (piece, table) in PST.iter().enumerate() {
+            for (sq, value) in table.iter().enumerate() {
+                params[TABLES + piece * 81 + sq] = f64::from(*value);
+            }
+        }
+        Self {
+            params,
+            k: 1.0 / 400.0,
+            momentum: vec![0.0; NUM_PARAMS],
+            velocity: vec![0.0; NUM_PARAMS],
+            steps: 0,
+        }
+    }
+}
+
+impl Tuner {
+    /// The mean squared error over the entries with the current parameters.
+    pub fn loss(&self, entries: &[TuneEntry]) -> f64 {
+        self.loss_with(entries, self.k)
+    }
+
+    fn loss_with(&self, entries: &[TuneEntry], k: f64) -> f64 {
+        let total: f64 = entries
+            .iter()
+            .map(|entry| (entry.result - sigmoid(k * entry.eval(&self.params))).powi(2))
+            .sum();
+        total / entries.len().max(1) as f64
+    }
+
+    /// Picks the `k` that fits the current parameters best. It should be fitted once before
+    /// training and left alone after, otherwise it just trades off against the scale of the
+    /// parameters.
+    pub fn fit_k(&mut self, entries: &[TuneEntry]) -> f64 {
+        // the loss is unimodal in k, so a ternary search on a log scale finds it
+        let (mut low, mut high) = (-12.0f64, 0.0f64);
+        for _ in 0..60 {
+            let a = low + (high - low) / 3.0;
+            let b = high - (high - low) / 3.0;
+            if self.loss_with(entries, a.exp()) < self.loss_with(entries, b.exp()) {
+                high = b;
+            } else {
+                low = a;
+            }
+        }
+        self.k = ((low + high) / 2.0).exp();
+        self.k
+    }
+
+    /// One full batch step of gradient descent with Adam, returns the loss before the step.
+    pub fn step(&mut self, entries: &[TuneEntry], learning_rate: f64) -> f64 {
+        let mut gradient = vec![0.0; NUM_PARAMS];
+        let mut loss = 0.0;
+        for entry in entries {
+            let predicted = sigmoid(self.k * entry.eval(&self.params));
+            let error = predicted - entry.result;
+            loss += error * error;
+            // d/dw (predicted - result)^2, the constant 2 k is left to the learning rate
+            let scale = error * predicted * (1.0 - predicted);
+            for &(index, coefficient) in &entry.features {
+                gradient[index as usize] += scale * f64::from(coefficient);
+            }
+        }
+
+        const BETA1: f64 = 0.9;
+        const BETA2: f64 = 0.999;
+        self.steps += 1;
+        let n = entries.len().max(1) as f64;
+        for (i, gradient) in gradient.into_iter().enumerate() {
+            let gradient = gradient / n;
+            self.momentum[i] = BETA1 * self.momentum[i] + (1.0 - BETA1) * gradient;
+            self.velocity[i] = BETA2 * self.velocity[i] + (1.0 - BETA2) * gradient * gradient;
+            let momentum = self.momentum[i] / (1.0 - BETA1.powi(self.steps));
+            let velocity = self.velocity[i] / (1.0 - BETA2.powi(self.steps));
+            self.params[i] -= learning_rate * momentum / (velocity.sqrt() + 1e-8);
+        }
+        loss / n
+    }
+
+    /// The parameters as the constants in `eval.rs`, rounded to whole centipawns.
+    pub fn to_rust(&self) -> String {
+        let round = |range: std::ops::Range<usize>| {
+            self.params[range]
+                .iter()
+                .map(|value| (value.round() as i32).to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        let mut output = format!(
+            "// indexed by piece type, kings are worth nothing since they never leave the board\n\
+             pub const PIECE_VALUES: [i32; 14] = [\n    {},\n];\n\n",
+            round(PIECES..PIECES + 14)
+        );
+        output += &format!(
+            "// pieces in hand are a little more flexible than the same piece on the board\n\
+             pub const HAND_VALUES: [i32; 7] = [{}];\n\n",
+            round(HAND..HAND + 7)
+        );
+        output +=
+            "// indexed by piece type and then the square from the owner's point of view, see \
+                   relative_square\npub const PST: [[i32; 81]; 14] = [\n";
+        for piece in 0..14 {
+            output += "    [\n";
+            // a rank per line, the same shape as the board
+            for rank in 0..9 {
+                let start = TABLES + piece * 81 + rank * 9;
+                output += &format!("        {},\n", round(start..start + 9));
+            }
+            output += "    ],\n";
+        }
+        output += "];\n";
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{sigmoid, TuneEntry, Tuner, HAND, PIECES};
+    use crate::{board::Board, eval::evaluate, types::piece::Piece};
+
+    #[test]
+    fn features_match_the_evaluation() {
+        let tuner = Tuner::default();
+        for fen in [
+            "lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL b - 1",
+            "l6nl/5+P1gk/2np1S3/p1p4Pp/3P2Sp1/1PPb2P1P/P5GS1/R8/LN4bKL w RGgsn5p 1",
+        ] {
+            let board = Board::from_fen(fen);
+            let entry = TuneEntry::new(&board, 0);
+            let eval = entry.eval(&tuner.params).round() as i32;
+            let sign = if board.stm() == 0 { 1 } else { -1 };
+            assert_eq!(eval, sign * evaluate(&board));
+        }
+    }
+
+    #[test]
+    fn fits_k() {
+        // results that follow a sigmoid with k = 1/200 exactly
+        let mut entries: Vec<TuneEntry> = [
+            "4k4/9/9/9/9/9/9/9/4K4 b P 1",
+            "4k4/9/9/9/9/9/9/9/4K4 b L 1",
+            "4k4/9/9/9/9/9/9/9/4K4 b S 1",
+            "4k4/9/9/9/9/9/9/9/4K4 w P 1",
+        ]
+        .iter()
+        .map(|fen| TuneEntry::new(&Board::from_fen(fen), 0))
+        .collect();
+        let tuner = Tuner::default();
+        for entry in &mut entries {
+            entry.result = sigmoid(entry.eval(&tuner.params) / 200.0);
+        }
+
+        let mut tuner = Tuner::default();
+        let k = tuner.fit_k(&entries);
+        assert!((k * 200.0 - 1.0).abs() < 1e-3, "{k}");
+        assert!(tuner.loss(&entries) < 1e-9);
+    }
+
+    #[test]
+    fn learns_material() {
+        // an extra rook in hand wins, an extra pawn in hand is a coin flip
+        let rook = Board::from_fen("4k4/9/9/9/9/9/9/9/4K4 b R 1");
+        let pawn = Board::from_fen("4k4/9/9/9/9/9/9/9/4K4 w p 1");
+        let mut entries = Vec::new();
+        for i in 0..20 {
+            entries.push(TuneEntry::new(&rook, 1));
+            entries.push(TuneEntry::new(&pawn, [-1, 0, 1][i % 3]));
+        }
+
+        let mut tuner = Tuner::default();
+        tuner.params[HAND + Piece::ROOK.as_usize()] = 100.0;
+        let before = tuner.loss(&entries);
+        for _ in 0..200 {
+            tuner.step(&entries, 5.0);
+        }
+        assert!(tuner.loss(&entries) < before);
+        assert!(tuner.params[HAND + Piece::ROOK.as_usize()] > 500.0);
+        assert!(tuner.params[HAND + Piece::PAWN.as_usize()].abs() < 200.0);
+
+        // nothing else shows up in the positions, so nothing else moves
+        let rust = tuner.to_rust();
+        assert!(rust.contains("pub const PIECE_VALUES: [i32; 14] = [\n    90, 315,"));
+        assert_eq!(
+            rust.matches("        0, 0, 0, 0, 0, 0, 0, 0, 0,\n").count(),
+            14 * 9
+        );
+        assert_eq!(tuner.params[PIECES], 90.0);
+    }
+}
@@ -1,10 +1,14 @@
 use core::fmt;
+use std::error::Error;
 
 use super::{piece::Piece, square::Square};
+use crate::board::Board;
 use arrayvec::ArrayVec;
 
 pub type Actionlist = ArrayVec<Action, 600>;
 
+/// A move or a drop packed into 16 bits: the destination square, then the origin square (or the
+/// dropped piece), a drop flag and a promotion flag.
 #[derive(Default, Debug, Copy, Clone, PartialEq, Eq)]
 pub struct Action(pub u16);
 
@@ -46,33 +50,14 @@ impl Action {
         (self.0 & DROP_MASK) != 0
     }
 
+    /// The action in USI notation, like `7g7f`, `8h2b+` or `P*5e`.
     pub fn to_usi(&self) -> String {
         let to_square = self.to();
         let to_file = 9 - to_square.file();
         let to_rank = (b'i' - to_square.rank()) as char;
 
         if self.is_drop() {
-            format!("{}*{}{}", self.piece(), to_file, to_rank)
-        } else {
-            let from_square = self.from();
-            let from_file = 9 - from_square.file();
-            let from_rank = (b'i' - from_square.rank()) as char;
-            if self.is_promo() {
-                format!("{}{}{}{}+", from_file, from_rank, to_file, to_rank)
-            } else {
-                format!("{}{}{}{}", from_file, from_rank, to_file, to_rank)
-            }
-        }
-    }
-}
-
-impl fmt::Display for Action {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let to_square = self.to();
-        let to_file = 9 - to_square.file();
-        let to_rank = (b'i' - to_square.rank()) as char;
-
-        let output = if self.is_drop() {
+            // drops are always uppercase in usi, no matter who is dropping
             format!(
                 "{}*{}{}",
                 self.piece().to_string().to_ascii_uppercase(),
@@ -88,8 +73,126 @@ impl fmt::Display for Action {
             } else {
                 format!("{}{}{}{}", from_file, from_rank, to_file, to_rank)
             }
+        }
+    }
+
+    /// Parses an action in USI notation, like `7g7f`, `8h2b+` or `P*5e`, and checks that it is
+    /// legal on the board.
+    pub fn from_usi(usi: &str, board: &Board) -> Result<Self, UsiActionError> {
+        let bytes = usi.as_bytes();
+        if !(4..=5).contains(&bytes.len()) {
+            return Err(UsiActionError::Malformed);
+        }
+        let to = parse_usi_square(bytes[2], bytes[3])?;
+
+        let action = if bytes[1] == b'*' {
+            if bytes.len() != 4 {
+                return Err(UsiActionError::Malformed);
+            }
+            // drops are uppercase in usi, lowercase is accepted since some guis send it
+            let piece = match bytes[0].to_ascii_uppercase() {
+                b'P' => Piece::PAWN,
+                b'L' => Piece::LANCE,
+                b'N' => Piece::KNIGHT,
+                b'S' => Piece::SILVER,
+                b'G' => Piece::GOLD,
+                b'B' => Piece::BISHOP,
+                b'R' => Piece::ROOK,
+                _ => return Err(UsiActionError::InvalidPiece),
+            };
+            Self::new_drop(piece.as_stm(board.stm()), to)
+        } else {
+            let from = parse_usi_square(bytes[0], bytes[1])?;
+            let is_promo = match bytes.get(4) {
+                None => false,
+                Some(b'+') => true,
+                Some(_) => return Err(UsiActionError::Malformed),
+            };
+            Self::new_move(from, to, is_promo)
         };
 
-        write!(f, "{output}")
+        // only pawn drops that give check have to be made, and only those are made on a copy
+        let is_legal = || {
+            board
+                .is_legal_unmade(action)
+                .unwrap_or_else(|| board.clone().is_legal(action))
+        };
+        if !board.get_actions().contains(&action) || !is_legal() {
+            return Err(UsiActionError::Illegal);
+        }
+        Ok(action)
+    }
+}
+
+/// Why a USI string couldn't be turned into an action.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UsiActionError {
+    /// Not 4 or 5 characters long, or the fifth isn't `+`.
+    Malformed,
+    InvalidSquare,
+    /// The dropped piece isn't one that can be in hand.
+    InvalidPiece,
+    /// Well formed, but not a legal action in the position.
+    Illegal,
+}
+
+impl fmt::Display for UsiActionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let message = match self {
+            Self::Malformed => "malformed action",
+            Self::InvalidSquare => "invalid square",
+            Self::InvalidPiece => "invalid drop piece",
+            Self::Illegal => "illegal action",
+        };
+        write!(f, "{message}")
+    }
+}
+
+impl Error for UsiActionError {}
+
+fn parse_usi_square(file: u8, rank: u8) -> Result<Square, UsiActionError> {
+    if !(b'1'..=b'9').contains(&file) || !(b'a'..=b'i').contains(&rank) {
+        return Err(UsiActionError::InvalidSquare);
+    }
+    Ok(Square::from_rf(b'i' - rank, 9 - (file - b'0')))
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_usi())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Action, UsiActionError};
+    use crate::board::{Board, STARTPOS};
+
+    #[test]
+    fn from_usi() {
+        let board = Board::from_fen(STARTPOS);
+        for usi in ["7g7f", "2h1h", "5i4h"] {
+            assert_eq!(Action::from_usi(usi, &board).unwrap().to_usi(), usi);
+        }
+
+        let board = Board::from_fen("4k4/9/9/9/9/9/9/1B7/4K4 b Pp 1");
+        assert_eq!(Action::from_usi("8h3c+", &board).unwrap().to_usi(), "8h3c+");
+        assert_eq!(Action::from_usi("P*5e", &board).unwrap().to_usi(), "P*5e");
+        assert_eq!(Action::from_usi("p*5e", &board).unwrap().to_usi(), "P*5e");
+
+        for (usi, error) in [
+            ("", UsiActionError::Malformed),
+            ("7g7f++", UsiActionError::Malformed),
+            ("8h3c=", UsiActionError::Malformed),
+            ("0a1b", UsiActionError::InvalidSquare),
+            ("8h3j", UsiActionError::InvalidSquare),
+            ("K*5e", UsiActionError::InvalidPiece),
+            ("8h7g+", UsiActionError::Illegal),
+            ("8h8g", UsiActionError::Illegal),
+            ("B*5e", UsiActionError::Illegal),
+            ("P*5a", UsiActionError::Illegal),
+        ] {
+            assert_eq!(Action::from_usi(usi, &board), Err(error), "{usi}");
+        }
     }
 }
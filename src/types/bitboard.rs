@@ -1,6 +1,7 @@
 use std::fmt;
 use std::ops::{BitAnd, BitAndAssign, BitOr, BitOrAssign, BitXor, BitXorAssign, Not, Shl, Shr};
 
+/// A set of squares, bit n is set if square n is in the set. Only the low 81 bits are used.
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub struct Bitboard(pub u128);
 
@@ -236,7 +237,6 @@ impl IntoIterator for Bitboard {
     type Item = Square;
     type IntoIter = Biterator;
 
-    #[must_use]
     fn into_iter(self) -> Self::IntoIter {
         Biterator { board: self }
     }
@@ -19,7 +19,7 @@ impl Hand {
     const LANCE_BITS: u32 = 3;
     const KNIGHT_BITS: u32 = 3;
     const SILVER_BITS: u32 = 3;
-    const GOLD_BITS: u32 = 2;
+    const GOLD_BITS: u32 = 3;
     const BISHOP_BITS: u32 = 2;
     const ROOK_BITS: u32 = 2;
 
@@ -32,24 +32,24 @@ impl Hand {
     const BISHOP_OFFSET: u32 = Self::ROOK_OFFSET + Self::ROOK_BITS;
     const ROOK_OFFSET: u32 = Self::PAWN_OFFSET + Self::PAWN_BITS;
 
-    // arrays
+    // arrays, indexed by piece type so gold comes last
     const OFFSETS: [u32; 7] = [
         Self::PAWN_OFFSET,
         Self::LANCE_OFFSET,
         Self::KNIGHT_OFFSET,
         Self::SILVER_OFFSET,
-        Self::GOLD_OFFSET,
         Self::BISHOP_OFFSET,
         Self::ROOK_OFFSET,
+        Self::GOLD_OFFSET,
     ];
     const BITS: [u32; 7] = [
         Self::PAWN_BITS,
         Self::LANCE_BITS,
         Self::KNIGHT_BITS,
         Self::SILVER_BITS,
-        Self::GOLD_BITS,
         Self::BISHOP_BITS,
         Self::ROOK_BITS,
+        Self::GOLD_BITS,
     ];
     const MASKS: [u32; 7] = {
         let mut result = [0; 7];
@@ -134,3 +134,34 @@ impl Iterator for HandIter {
         None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Hand;
+    use crate::{board::Board, types::piece::Piece};
+
+    #[test]
+    fn full_counts() {
+        // golds used to get two bits, so a fourth one wrapped around to none
+        let mut hand = Hand::EMPTY;
+        for (piece, count) in [
+            (Piece::PAWN, 18),
+            (Piece::LANCE, 4),
+            (Piece::KNIGHT, 4),
+            (Piece::SILVER, 4),
+            (Piece::GOLD, 4),
+            (Piece::BISHOP, 2),
+            (Piece::ROOK, 2),
+        ] {
+            for _ in 0..count {
+                hand.inc(piece);
+            }
+            assert_eq!(hand.num(piece), count, "{piece}");
+        }
+        assert_eq!(hand.into_iter().count(), 7);
+
+        let fen = "4k4/9/9/9/9/9/9/9/4K4 b 4G 1";
+        let board = Board::from_fen(fen);
+        assert_eq!(board.to_fen(), fen);
+    }
+}
@@ -81,7 +81,7 @@ impl fmt::Display for Piece {
             Self::PROMO_PAWN => "+p",
             Self::PROMO_LANCE => "+l",
             Self::PROMO_KNIGHT => "+n",
-            Self::PROMO_SILVER => "+p",
+            Self::PROMO_SILVER => "+s",
             Self::PROMO_BISHOP => "+b",
             Self::PROMO_ROOK => "+r",
             Self::NONE => " ",
@@ -1,96 +1,1008 @@
-use std::io;
+use std::{
+    fs,
+    io::{self, ErrorKind},
+    str::{FromStr, SplitAsciiWhitespace},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
-use crate::{
-    board::Board,
-    perft::{perft, split_perft},
-    types::action::Actionlist,
+use ctenophore::{
+    board::{Board, STARTPOS},
+    book::{Book, BookSelection},
+    handicap::Handicap,
+    nnue::{set_network, Network},
+    perft::{fast_perft, parse_divide, perft, perft_diff, split_perft, variant_perft},
+    search::{search, SearchLimits, SearchResult, MATE, MATE_BOUND},
+    types::action::Action,
+    variant::{Variant, VariantBoard},
 };
 
-#[derive(Default)]
+use crate::bench::bench;
+
+/// A command from the gui with its arguments checked, so running it can't fail on bad input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum UsiCommand {
+    Perft {
+        depth: u8,
+        divide: bool,
+    },
+    PerftDiff {
+        depth: u8,
+        path: Option<String>,
+    },
+    FastPerft {
+        depth: u8,
+        threads: usize,
+        hash: usize,
+    },
+    Bench,
+    Position {
+        /// None for `startpos`, which is the position of the `Handicap` option.
+        fen: Option<String>,
+        moves: Vec<String>,
+    },
+    Print,
+    MakeMove(String),
+    Usi,
+    IsReady,
+    UsiNewGame,
+    SetOption {
+        name: String,
+        value: Option<String>,
+    },
+    Go(GoParams),
+    Stop,
+    /// The opponent played the predicted action, the times are the new clock if the gui sent any.
+    PonderHit(GoParams),
+    GameOver,
+    Quit,
+}
+
+/// The limits from a `go` command, times are in milliseconds.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct GoParams {
+    btime: Option<u64>,
+    wtime: Option<u64>,
+    binc: Option<u64>,
+    winc: Option<u64>,
+    byoyomi: Option<u64>,
+    movetime: Option<u64>,
+    depth: Option<u8>,
+    nodes: Option<u64>,
+    infinite: bool,
+    /// Thinking on the opponent's time, the clock only starts at `ponderhit`.
+    ponder: bool,
+}
+
+// kept back from every think time for the gui and the pipe in between
+const MOVE_OVERHEAD: u64 = 50;
+
+impl GoParams {
+    fn limits(&self, stm: u8) -> SearchLimits {
+        SearchLimits {
+            depth: self.depth,
+            nodes: self.nodes,
+            // the time limit for a ponder search is set once it hits
+            time: if self.ponder {
+                None
+            } else {
+                self.think_time(stm)
+            },
+        }
+    }
+
+    // a slice of the remaining time plus whatever comes back after the action, None to search
+    // until told to stop
+    fn think_time(&self, stm: u8) -> Option<Duration> {
+        if self.infinite {
+            return None;
+        }
+        if let Some(movetime) = self.movetime {
+            return Some(Duration::from_millis(
+                movetime.saturating_sub(MOVE_OVERHEAD).max(10),
+            ));
+        }
+        let (time, inc) = if stm == 0 {
+            (self.btime, self.binc)
+        } else {
+            (self.wtime, self.winc)
+        };
+        if time.is_none() && self.byoyomi.is_none() {
+            return None;
+        }
+        let time = time.unwrap_or(0);
+        let byoyomi = self.byoyomi.unwrap_or(0);
+        let budget = (time / 40 + inc.unwrap_or(0) + byoyomi).min(time + byoyomi);
+        Some(Duration::from_millis(
+            budget.saturating_sub(MOVE_OVERHEAD).max(10),
+        ))
+    }
+}
+
+// Ok(None) is an empty line, which is skipped without complaining
+fn parse_command(line: &str) -> Result<Option<UsiCommand>, String> {
+    let mut tokens = line.split_ascii_whitespace();
+    let Some(command) = tokens.next() else {
+        return Ok(None);
+    };
+
+    let command = match command {
+        "perft" => {
+            let mut token = tokens.next();
+            let divide = token == Some("divide");
+            if divide {
+                token = tokens.next();
+            }
+            UsiCommand::Perft {
+                depth: parse_arg(token, "depth")?,
+                divide,
+            }
+        }
+        "splitperft" => UsiCommand::Perft {
+            depth: parse_arg(tokens.next(), "depth")?,
+            divide: true,
+        },
+        "perftdiff" => UsiCommand::PerftDiff {
+            depth: parse_arg(tokens.next(), "depth")?,
+            path: tokens.next().map(str::to_owned),
+        },
+        "fastperft" => {
+            let depth = parse_arg(tokens.next(), "depth")?;
+            let threads = parse_optional(tokens.next(), "thread count", 1)?;
+            if threads == 0 {
+                return Err("thread count must be at least 1".to_owned());
+            }
+            let hash = parse_optional(tokens.next(), "hash size", 0)?;
+            UsiCommand::FastPerft {
+                depth,
+                threads,
+                hash,
+            }
+        }
+        "bench" => UsiCommand::Bench,
+        "position" => parse_position(tokens)?,
+        "print" => UsiCommand::Print,
+        "makemove" => {
+            let action = tokens.next().ok_or("makemove needs an action")?;
+            UsiCommand::MakeMove(action.to_owned())
+        }
+        "usi" => UsiCommand::Usi,
+        "isready" => UsiCommand::IsReady,
+        "usinewgame" => UsiCommand::UsiNewGame,
+        "go" => UsiCommand::Go(parse_go(tokens)?),
+        "stop" => UsiCommand::Stop,
+        "ponderhit" => UsiCommand::PonderHit(parse_go(tokens)?),
+        "setoption" => parse_setoption(tokens)?,
+        // the result doesn't matter to us
+        "gameover" => UsiCommand::GameOver,
+        "quit" => UsiCommand::Quit,
+        _ => return Err(format!("unknown command: {command}")),
+    };
+    Ok(Some(command))
+}
+
+// the sfen itself is checked when the position is set up
+fn parse_position(mut tokens: SplitAsciiWhitespace) -> Result<UsiCommand, String> {
+    let fen = match tokens.next() {
+        Some("startpos") => match tokens.next() {
+            None | Some("moves") => None,
+            Some("handicap") => {
+                let name = tokens.next().ok_or("handicap needs a name")?;
+                let handicap: Handicap = name.parse()?;
+                match tokens.next() {
+                    None | Some("moves") => {}
+                    Some(token) => return Err(format!("expected moves, found {token}")),
+                }
+                Some(handicap.fen().to_owned())
+            }
+            Some(token) => return Err(format!("expected moves or handicap, found {token}")),
+        },
+        Some("sfen") => {
+            let fields: Vec<&str> = tokens
+                .by_ref()
+                .take_while(|token| *token != "moves")
+                .collect();
+            Some(fields.join(" "))
+        }
+        Some(token) => return Err(format!("expected startpos or sfen, found {token}")),
+        None => return Err("position needs startpos or sfen".to_owned()),
+    };
+    Ok(UsiCommand::Position {
+        fen,
+        moves: tokens.map(str::to_owned).collect(),
+    })
+}
+
+fn parse_go(mut tokens: SplitAsciiWhitespace) -> Result<GoParams, String> {
+    let mut params = GoParams::default();
+    while let Some(token) = tokens.next() {
+        match token {
+            "btime" => params.btime = Some(parse_arg(tokens.next(), token)?),
+            "wtime" => params.wtime = Some(parse_arg(tokens.next(), token)?),
+            "binc" => params.binc = Some(parse_arg(tokens.next(), token)?),
+            "winc" => params.winc = Some(parse_arg(tokens.next(), token)?),
+            "byoyomi" => params.byoyomi = Some(parse_arg(tokens.next(), token)?),
+            "movetime" => params.movetime = Some(parse_arg(tokens.next(), token)?),
+            "depth" => params.depth = Some(parse_arg(tokens.next(), token)?),
+            "nodes" => params.nodes = Some(parse_arg(tokens.next(), token)?),
+            "infinite" => params.infinite = true,
+            "ponder" => params.ponder = true,
+            _ => return Err(format!("unknown go parameter: {token}")),
+        }
+    }
+    Ok(params)
+}
+
+// names and values can have spaces in them, like file paths
+fn parse_setoption(mut tokens: SplitAsciiWhitespace) -> Result<UsiCommand, String> {
+    if tokens.next() != Some("name") {
+        return Err("setoption needs a name".to_owned());
+    }
+    let name: Vec<&str> = tokens
+        .by_ref()
+        .take_while(|token| *token != "value")
+        .collect();
+    if name.is_empty() {
+        return Err("setoption needs a name".to_owned());
+    }
+    let value: Vec<&str> = tokens.collect();
+    Ok(UsiCommand::SetOption {
+        name: name.join(" "),
+        value: (!value.is_empty()).then(|| value.join(" ")),
+    })
+}
+
+// the hand-crafted evaluation is used without a network
+fn load_network(path: Option<&str>) -> Result<(), String> {
+    set_network(None);
+    let Some(path) = path.filter(|path| *path != "<empty>") else {
+        return Ok(());
+    };
+    let network = Network::load(path)?;
+    println!(
+        "info string loaded a network with {} hidden neurons",
+        network.hidden
+    );
+    set_network(Some(network));
+    Ok(())
+}
+
+fn parse_arg<T: FromStr>(token: Option<&str>, name: &str) -> Result<T, String> {
+    let token = token.ok_or_else(|| format!("missing {name}"))?;
+    token
+        .parse()
+        .map_err(|_| format!("invalid {name}: {token}"))
+}
+
+fn parse_optional<T: FromStr>(token: Option<&str>, name: &str, default: T) -> Result<T, String> {
+    token.map_or(Ok(default), |token| parse_arg(Some(token), name))
+}
+
+// a search running on its own thread, it prints its own bestmove when it finishes
+struct SearchThread {
+    stop: Arc<AtomicBool>,
+    // set while pondering or searching infinitely, the bestmove has to wait until it is cleared
+    hold: Arc<AtomicBool>,
+    params: GoParams,
+    stm: u8,
+    handle: JoinHandle<()>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Options {
+    ponder: bool,
+    /// The book is used up to this move number.
+    book_moves: i16,
+    /// Book entries searched shallower than this are ignored.
+    book_depth_limit: u32,
+    book_selection: BookSelection,
+    /// What `position startpos` sets up, None for the even starting position.
+    handicap: Option<Handicap>,
+    /// The rules positions are set up with, only standard shogi can be searched.
+    variant: Variant,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            ponder: false,
+            book_moves: 16,
+            book_depth_limit: 0,
+            book_selection: BookSelection::Weighted,
+            handicap: None,
+            variant: Variant::Standard,
+        }
+    }
+}
+
 pub struct UsiManager {
     board: Board,
+    // the position when the variant isn't standard shogi, where only perft is supported
+    variant_board: Option<VariantBoard>,
+    options: Options,
+    book: Option<Book>,
+    // xorshift state for picking book actions
+    seed: u64,
+    input: Receiver<String>,
+    search: Option<SearchThread>,
 }
 
 impl UsiManager {
-    pub fn get_command(&mut self) -> bool {
-        let mut buffer = String::new();
+    pub fn new(input: Receiver<String>) -> Self {
+        Self {
+            board: Board::default(),
+            variant_board: None,
+            options: Options::default(),
+            book: None,
+            seed: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(1, |time| time.as_nanos() as u64)
+                | 1,
+            input,
+            search: None,
+        }
+    }
 
-        io::stdin()
-            .read_line(&mut buffer)
-            .expect("failed to read from stdin");
+    /// A manager reading commands from stdin on a separate thread, so that `stop` and `isready`
+    /// get answered while a search is running.
+    pub fn from_stdin() -> Self {
+        Self::new(spawn_stdin_reader())
+    }
 
-        let (command, _new_line) = buffer.split_at(buffer.len() - 1);
-        self.interpret_command(command)
+    /// Runs commands until `quit` or the end of the input.
+    pub fn run(&mut self) {
+        while let Ok(line) = self.input.recv() {
+            if !self.interpret_command(&line) {
+                break;
+            }
+        }
+        self.stop_search();
     }
-    fn interpret_command(&mut self, command_msg: &str) -> bool {
-        let mut command_split = command_msg.split_ascii_whitespace();
-        let Some(command) = command_split.next() else {
-            return false;
-        };
 
-        match command {
-            "perft" => perft(
-                &mut self.board,
-                command_split
-                    .next()
-                    .expect("No Depth")
-                    .parse()
-                    .expect("Invalid Depth"),
-            ),
-            "splitperft" => split_perft(
-                &mut self.board,
-                command_split
-                    .next()
-                    .expect("No Depth")
-                    .parse()
-                    .expect("Invalid Depth"),
-            ),
-            "position" => self.position(command_msg),
-            "print" => self.board.print_state(),
-            "quit" => return false,
-            "makemove" => self.make_move(command_msg),
-            _ => println!("Invalid Command: {}", command),
+    fn interpret_command(&mut self, line: &str) -> bool {
+        match parse_command(line) {
+            Ok(Some(UsiCommand::Quit)) => {
+                self.stop_search();
+                return false;
+            }
+            Ok(Some(command)) => {
+                if let Err(e) = self.run_command(command) {
+                    println!("info string {e}");
+                }
+            }
+            Ok(None) => {}
+            Err(e) => println!("info string {e}"),
         }
         true
     }
-    fn position(&mut self, command_msg: &str) {
-        let mut command_split = command_msg.split_ascii_whitespace();
-        let _first_token = command_split.next().expect("not enough tokens");
-        let second_token = command_split.next().expect("not enough tokens");
-        let mut fen: String;
-        if second_token == "startpos" {
-            fen = "lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL b - 1".to_string();
-        } else {
-            let third_token = command_split.next().expect("not enough tokens");
-            fen = third_token.to_owned()
-                + " "
-                + command_split.next().expect("not enough tokens")
-                + " "
-                + command_split.next().expect("not enough tokens");
-            let next_token = command_split.next();
-            if let Some(string) = next_token {
-                if string != "moves" {
-                    fen += string;
+
+    fn run_command(&mut self, command: UsiCommand) -> Result<(), String> {
+        match command {
+            // these are answered straight away, even while searching
+            UsiCommand::IsReady => {
+                println!("readyok");
+                return Ok(());
+            }
+            UsiCommand::Stop => {
+                self.stop_search();
+                return Ok(());
+            }
+            UsiCommand::PonderHit(params) => return self.ponderhit(params),
+            _ => {}
+        }
+
+        // everything else needs the board to itself
+        self.stop_search();
+        if let Some(board) = &mut self.variant_board {
+            match &command {
+                UsiCommand::Perft { depth, divide } => {
+                    variant_perft(board, *depth, *divide);
+                    return Ok(());
+                }
+                UsiCommand::Position { fen, moves } => {
+                    return self.variant_position(fen.as_deref(), moves)
+                }
+                UsiCommand::Print => {
+                    println!("{}", board.to_fen());
+                    return Ok(());
+                }
+                UsiCommand::MakeMove(usi) => {
+                    let action = board
+                        .action_from_usi(usi)
+                        .map_err(|e| format!("invalid move {usi}: {e}"))?;
+                    board.perform_action(action);
+                    return Ok(());
+                }
+                UsiCommand::PerftDiff { .. } | UsiCommand::FastPerft { .. } | UsiCommand::Go(_) => {
+                    return Err(format!("only perft is supported for {}", board.variant()))
+                }
+                _ => {}
+            }
+        }
+        match command {
+            UsiCommand::Perft {
+                depth,
+                divide: true,
+            } => split_perft(&mut self.board, depth),
+            UsiCommand::Perft {
+                depth,
+                divide: false,
+            } => perft(&mut self.board, depth),
+            UsiCommand::PerftDiff { depth, path } => self.perft_diff(depth, path)?,
+            UsiCommand::FastPerft {
+                depth,
+                threads,
+                hash,
+            } => fast_perft(&self.board, depth, threads, hash),
+            UsiCommand::Bench => bench(),
+            UsiCommand::Position { fen, moves } => {
+                let start = self.options.handicap.map_or(STARTPOS, Handicap::fen);
+                self.position(fen.as_deref().unwrap_or(start), &moves)?
+            }
+            UsiCommand::Print => self.board.print_state(),
+            UsiCommand::MakeMove(usi) => {
+                let action = Action::from_usi(&usi, &self.board)
+                    .map_err(|e| format!("invalid move {usi}: {e}"))?;
+                self.board.perform_action(action);
+            }
+            UsiCommand::Usi => {
+                println!("id name ctenophore");
+                println!("id author the ctenophore authors");
+                println!("option name USI_Ponder type check default false");
+                println!("option name EvalFile type string default <empty>");
+                println!("option name BookFile type string default <empty>");
+                println!("option name BookMoves type spin default 16 min 0 max 10000");
+                println!("option name BookDepthLimit type spin default 0 min 0 max 1000");
+                println!(
+                    "option name BookSelection type combo default weighted var best var weighted \
+                     var random"
+                );
+                let names: Vec<String> = Handicap::ALL
+                    .iter()
+                    .map(|handicap| format!(" var {handicap}"))
+                    .collect();
+                println!(
+                    "option name Handicap type combo default none var none{}",
+                    names.concat()
+                );
+                let names: Vec<String> = Variant::ALL
+                    .iter()
+                    .map(|variant| format!(" var {variant}"))
+                    .collect();
+                println!(
+                    "option name USI_Variant type combo default {}{}",
+                    Variant::Standard,
+                    names.concat()
+                );
+                println!("usiok");
+            }
+            UsiCommand::SetOption { name, value } => self.set_option(&name, value.as_deref())?,
+            UsiCommand::Go(params) => self.go(params),
+            UsiCommand::UsiNewGame
+            | UsiCommand::GameOver
+            | UsiCommand::IsReady
+            | UsiCommand::Stop
+            | UsiCommand::PonderHit(_)
+            | UsiCommand::Quit => {}
+        }
+        Ok(())
+    }
+
+    fn set_option(&mut self, name: &str, value: Option<&str>) -> Result<(), String> {
+        match name {
+            "USI_Ponder" => self.options.ponder = parse_arg(value, name)?,
+            "EvalFile" => load_network(value)?,
+            "BookFile" => self.load_book(value)?,
+            "BookMoves" => self.options.book_moves = parse_arg(value, name)?,
+            "BookDepthLimit" => self.options.book_depth_limit = parse_arg(value, name)?,
+            "BookSelection" => {
+                self.options.book_selection = match value {
+                    Some("best") => BookSelection::Best,
+                    Some("weighted") => BookSelection::Weighted,
+                    Some("random") => BookSelection::Random,
+                    _ => return Err(format!("invalid {name}: {}", value.unwrap_or(""))),
+                }
+            }
+            "Handicap" => {
+                self.options.handicap = match value {
+                    Some("none") => None,
+                    Some(name) => Some(name.parse()?),
+                    None => return Err(format!("invalid {name}: ")),
+                }
+            }
+            "USI_Variant" => {
+                let variant: Variant = parse_arg(value, name)?;
+                // the old position was set up under the other rules
+                self.options.variant = variant;
+                self.board = Board::from_fen(STARTPOS);
+                self.variant_board =
+                    (variant != Variant::Standard).then(|| VariantBoard::startpos(variant));
+            }
+            // there is no hash table yet, but every gui sends this one
+            "USI_Hash" => {}
+            _ => return Err(format!("unknown option: {name}")),
+        }
+        Ok(())
+    }
+
+    fn load_book(&mut self, path: Option<&str>) -> Result<(), String> {
+        self.book = None;
+        let Some(path) = path.filter(|path| *path != "<empty>") else {
+            return Ok(());
+        };
+        let text = fs::read_to_string(path).map_err(|e| format!("failed to read {path}: {e}"))?;
+        let book = Book::parse_db(&text).map_err(|e| format!("invalid book {path}: {e}"))?;
+        println!("info string loaded {} book positions", book.len());
+        self.book = Some(book);
+        Ok(())
+    }
+
+    // a book action is played straight away, except when pondering or analysing where a search
+    // is expected
+    fn probe_book(&mut self, params: GoParams) -> Option<(Action, Option<Action>)> {
+        if params.ponder || params.infinite || self.board.ply() > self.options.book_moves {
+            return None;
+        }
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 7;
+        self.seed ^= self.seed << 17;
+        self.book.as_ref()?.probe(
+            &self.board,
+            self.options.book_selection,
+            self.options.book_depth_limit,
+            self.seed,
+        )
+    }
+
+    fn go(&mut self, params: GoParams) {
+        if let Some((action, ponder)) = self.probe_book(params) {
+            match ponder {
+                Some(ponder) if self.options.ponder => {
+                    println!("bestmove {action} ponder {ponder}")
                 }
+                _ => println!("bestmove {action}"),
             }
+            return;
         }
-        self.board = Board::default();
-        self.board.load_fen(&fen);
+
+        let mut board = self.board.clone();
+        let stm = board.stm();
+        let limits = params.limits(stm);
+        let stop = Arc::new(AtomicBool::new(false));
+        let hold = Arc::new(AtomicBool::new(params.ponder || params.infinite));
+        let ponder = self.options.ponder;
+        let handle = thread::spawn({
+            let stop = Arc::clone(&stop);
+            let hold = Arc::clone(&hold);
+            move || {
+                let result = search(&mut board, limits, &stop, print_info);
+                // usi doesn't allow a bestmove before ponderhit or stop, even if the search is done
+                while hold.load(Ordering::Relaxed) && !stop.load(Ordering::Relaxed) {
+                    thread::sleep(Duration::from_millis(1));
+                }
+                match (result.best, result.pv.get(1)) {
+                    (Some(best), Some(reply)) if ponder => {
+                        println!("bestmove {best} ponder {reply}")
+                    }
+                    (Some(best), _) => println!("bestmove {best}"),
+                    (None, _) => println!("bestmove resign"),
+                }
+            }
+        });
+        self.search = Some(SearchThread {
+            stop,
+            hold,
+            params,
+            stm,
+            handle,
+        });
     }
-    fn make_move(&mut self, command_msg: &str) {
-        let mut command_split = command_msg.split_ascii_whitespace();
-        let _first_token = command_split.next().expect("not enough tokens");
-        let second_token = command_split.next().expect("not enough tokens");
-        let index: usize = second_token.parse::<usize>().expect("invalid index");
-        let list = self.board.get_actions();
-        // legality check
-        let mut list2 = Actionlist::new();
-        for action in &list {
-            if self.board.perform_action(*action) {
-                self.board.undo_action();
-                list2.push(*action);
+
+    // the clock starts now, so the whole think time is still there even though the search has
+    // been running for a while
+    fn ponderhit(&mut self, params: GoParams) -> Result<(), String> {
+        let search = self
+            .search
+            .as_ref()
+            .filter(|search| search.params.ponder)
+            .ok_or("ponderhit without a ponder search")?;
+        let params = if params == GoParams::default() {
+            search.params
+        } else {
+            params
+        };
+        search.hold.store(false, Ordering::Relaxed);
+        if let Some(time) = params.think_time(search.stm) {
+            let stop = Arc::clone(&search.stop);
+            thread::spawn(move || {
+                thread::sleep(time);
+                stop.store(true, Ordering::Relaxed);
+            });
+        }
+        Ok(())
+    }
+
+    // waits for the bestmove of the running search, if there is one
+    fn stop_search(&mut self) {
+        if let Some(search) = self.search.take() {
+            search.stop.store(true, Ordering::Relaxed);
+            search.handle.join().expect("search thread panicked");
+        }
+    }
+
+    // the board is only replaced once the whole command checks out
+    fn position(&mut self, fen: &str, moves: &[String]) -> Result<(), String> {
+        let mut board = Board::try_from_fen(fen).map_err(|e| format!("invalid sfen: {e}"))?;
+        for usi in moves {
+            let action =
+                Action::from_usi(usi, &board).map_err(|e| format!("invalid move {usi}: {e}"))?;
+            board.perform_action(action);
+        }
+        self.board = board;
+        Ok(())
+    }
+
+    // position for the other variants, the handicaps are all standard shogi positions
+    fn variant_position(&mut self, fen: Option<&str>, moves: &[String]) -> Result<(), String> {
+        let variant = self.options.variant;
+        if fen.is_none() && self.options.handicap.is_some() {
+            return Err(format!("the Handicap option doesn't apply to {variant}"));
+        }
+        let fen = fen.unwrap_or(variant.start_fen());
+        let mut board =
+            VariantBoard::from_fen(variant, fen).map_err(|e| format!("invalid sfen: {e}"))?;
+        for usi in moves {
+            let action = board
+                .action_from_usi(usi)
+                .map_err(|e| format!("invalid move {usi}: {e}"))?;
+            board.perform_action(action);
+        }
+        self.variant_board = Some(board);
+        Ok(())
+    }
+
+    fn perft_diff(&mut self, depth: u8, path: Option<String>) -> Result<(), String> {
+        let reference = match path {
+            Some(path) => parse_divide(
+                fs::read_to_string(&path)
+                    .map_err(|e| format!("failed to read {path}: {e}"))?
+                    .lines(),
+            ),
+            None => {
+                println!("paste the reference divide, end it with an empty line");
+                read_divide(&self.input)
+            }
+        };
+        let input = &self.input;
+        perft_diff(&mut self.board, depth, reference, |board, depth| {
+            println!("paste the reference divide at depth {depth} for");
+            println!("position sfen {}", board.to_fen());
+            read_divide(input)
+        });
+        Ok(())
+    }
+}
+
+fn print_info(result: &SearchResult) {
+    let score = if result.score >= MATE_BOUND {
+        format!("mate {}", MATE - result.score)
+    } else if result.score <= -MATE_BOUND {
+        format!("mate -{}", MATE + result.score)
+    } else {
+        format!("cp {}", result.score)
+    };
+    let millis = result.time.as_millis().max(1);
+    let pv: Vec<String> = result.pv.iter().map(Action::to_usi).collect();
+    println!(
+        "info depth {} score {score} nodes {} nps {} time {} pv {}",
+        result.depth,
+        result.nodes,
+        u128::from(result.nodes) * 1000 / millis,
+        result.time.as_millis(),
+        pv.join(" ")
+    );
+}
+
+// the channel closes at eof, which the manager treats like quit
+fn spawn_stdin_reader() -> Receiver<String> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let stdin = io::stdin();
+        let mut buffer = String::new();
+        loop {
+            buffer.clear();
+            match stdin.read_line(&mut buffer) {
+                Ok(0) => break,
+                Ok(_) => {
+                    let line = buffer.trim_end_matches(['\r', '\n']).to_owned();
+                    if sender.send(line).is_err() {
+                        break;
+                    }
+                }
+                // the bad bytes are consumed, so the next line can still be read
+                Err(e) if e.kind() == ErrorKind::InvalidData => {
+                    println!("info string ignoring a line that isn't utf-8");
+                }
+                Err(e) => {
+                    println!("info string failed to read from stdin: {e}");
+                    break;
+                }
             }
         }
-        self.board.perform_action(list2[index]);
+    });
+    receiver
+}
+
+// reads a pasted divide listing, up to the first empty line or the end of the input
+fn read_divide(input: &Receiver<String>) -> Vec<(String, u64)> {
+    let lines: Vec<String> = input
+        .iter()
+        .take_while(|line| !line.trim().is_empty())
+        .collect();
+    parse_divide(lines.iter().map(String::as_str))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        env, fs,
+        sync::mpsc,
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use super::{parse_command, GoParams, UsiCommand, UsiManager};
+    use ctenophore::{board::STARTPOS, handicap::Handicap};
+
+    #[test]
+    fn parses_commands() {
+        assert_eq!(parse_command(""), Ok(None));
+        assert_eq!(parse_command(" \t "), Ok(None));
+        assert_eq!(
+            parse_command("perft divide 3"),
+            Ok(Some(UsiCommand::Perft {
+                depth: 3,
+                divide: true
+            }))
+        );
+        assert_eq!(
+            parse_command("fastperft 5"),
+            Ok(Some(UsiCommand::FastPerft {
+                depth: 5,
+                threads: 1,
+                hash: 0
+            }))
+        );
+        assert_eq!(
+            parse_command("position startpos moves 7g7f 3c3d"),
+            Ok(Some(UsiCommand::Position {
+                fen: None,
+                moves: vec!["7g7f".to_owned(), "3c3d".to_owned()]
+            }))
+        );
+        assert_eq!(
+            parse_command("position sfen 4k4/9/9/9/9/9/9/9/4K4 b - moves 5i5h"),
+            Ok(Some(UsiCommand::Position {
+                fen: Some("4k4/9/9/9/9/9/9/9/4K4 b -".to_owned()),
+                moves: vec!["5i5h".to_owned()]
+            }))
+        );
+        assert_eq!(
+            parse_command("position startpos handicap rook-lance moves 3a3b"),
+            Ok(Some(UsiCommand::Position {
+                fen: Some(Handicap::RookLance.fen().to_owned()),
+                moves: vec!["3a3b".to_owned()]
+            }))
+        );
+
+        for bad in [
+            "foo",
+            "perft",
+            "perft x",
+            "perft 300",
+            "fastperft 3 0",
+            "position",
+            "position start",
+            "position startpos 7g7f",
+            "position startpos handicap",
+            "position startpos handicap queen",
+            "position startpos handicap rook 3a3b",
+            "makemove",
+            "go depth",
+            "go btime x",
+            "go mate 5",
+            "setoption",
+            "setoption name",
+            "ponderhit byoyomi",
+        ] {
+            assert!(parse_command(bad).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn bad_input_keeps_the_engine_alive() {
+        let (_sender, input) = mpsc::channel();
+        let mut manager = UsiManager::new(input);
+        for line in [
+            "position startpos moves 7g7f",
+            "position sfen",
+            "position sfen 4k4/9/9 b - 1",
+            "position sfen 4k4/9/9/9/9/9/9/9/4K4 x - 1",
+            "position sfen 4k4/9/9/9/9/9/9/9/4K4 b 99P 1",
+            "position sfen 4k4/9/9/9/9/9/9/9/4K4 b - one",
+            "position sfen 9/9/9/9/9/9/9/9/4K4 b - 1",
+            "position sfen 4k4/9/9/9/9/9/9/9/4+K4 b - 1",
+            // a third rook, on the board or in hand, doesn't fit in the hand's rook count
+            "position sfen 4k4/9/9/9/9/9/rr7/RR7/4K4 b 2R 1",
+            "position sfen 4k4/9/9/9/9/9/9/+R+R7/4K4 b r 1",
+            "position sfen 4k4/9/9/9/9/9/9/9/4K4 b 2R2r 1",
+            "position startpos moves 7g7f 7g7f",
+            "makemove 1a1a",
+            "bogus",
+            "",
+        ] {
+            assert!(manager.interpret_command(line), "{line}");
+        }
+        // only the first command got through
+        assert_eq!(
+            manager.board.to_fen(),
+            "lnsgkgsnl/1r5b1/ppppppppp/9/9/2P6/PP1PPPPPP/1B5R1/LNSGKGSNL w - 2"
+        );
+        assert!(!manager.interpret_command("quit"));
+    }
+
+    #[test]
+    fn go() {
+        let Ok(Some(UsiCommand::Go(params))) =
+            parse_command("go btime 60000 wtime 1000 byoyomi 3000")
+        else {
+            panic!("go didn't parse");
+        };
+        assert_eq!(params.think_time(0), Some(Duration::from_millis(4450)));
+        assert_eq!(params.think_time(1), Some(Duration::from_millis(2975)));
+
+        let params = GoParams {
+            btime: Some(100),
+            binc: Some(5000),
+            ..Default::default()
+        };
+        assert_eq!(params.think_time(0), Some(Duration::from_millis(50)));
+        assert_eq!(GoParams::default().think_time(0), None);
+
+        // even a movetime shorter than the overhead leaves time for a search
+        let params = GoParams {
+            movetime: Some(10),
+            ..Default::default()
+        };
+        assert_eq!(params.think_time(0), Some(Duration::from_millis(10)));
+    }
+
+    #[test]
+    fn stop_ends_an_infinite_search() {
+        let (_sender, input) = mpsc::channel();
+        let mut manager = UsiManager::new(input);
+        assert!(manager.interpret_command("position startpos"));
+        assert!(manager.interpret_command("go infinite"));
+        assert!(manager.search.is_some());
+        assert!(manager.interpret_command("isready"));
+        assert!(manager.search.is_some());
+        assert!(manager.interpret_command("stop"));
+        assert!(manager.search.is_none());
+    }
+
+    #[test]
+    fn setoption() {
+        assert_eq!(
+            parse_command("setoption name BookFile value my books/book.db"),
+            Ok(Some(UsiCommand::SetOption {
+                name: "BookFile".to_owned(),
+                value: Some("my books/book.db".to_owned())
+            }))
+        );
+
+        let (_sender, input) = mpsc::channel();
+        let mut manager = UsiManager::new(input);
+        assert!(manager.interpret_command("setoption name USI_Ponder value true"));
+        assert!(manager.options.ponder);
+        assert!(manager.interpret_command("setoption name USI_Ponder value maybe"));
+        assert!(manager.options.ponder);
+    }
+
+    #[test]
+    fn handicap_option_sets_up_startpos() {
+        let (_sender, input) = mpsc::channel();
+        let mut manager = UsiManager::new(input);
+        assert!(manager.interpret_command("setoption name Handicap value 2-piece"));
+        assert!(manager.interpret_command("position startpos moves 5a4b"));
+        assert_eq!(
+            manager.board.to_fen(),
+            "lnsg1gsnl/5k3/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL b - 2"
+        );
+        // an explicit sfen is left alone
+        assert!(manager.interpret_command(&format!("position sfen {STARTPOS}")));
+        assert_eq!(manager.board.to_fen(), STARTPOS);
+        assert!(manager.interpret_command("setoption name Handicap value none"));
+        assert!(manager.interpret_command("position startpos"));
+        assert_eq!(manager.board.to_fen(), STARTPOS);
+    }
+
+    #[test]
+    fn variant_option_switches_the_board() {
+        let (_sender, input) = mpsc::channel();
+        let mut manager = UsiManager::new(input);
+        assert!(manager.interpret_command("setoption name USI_Variant value minishogi"));
+        assert!(manager.interpret_command("position startpos moves 5d5c 1b1c"));
+        let board = manager.variant_board.as_mut().unwrap();
+        assert_eq!(board.to_fen(), "rbsgk/5/P3p/5/KGSBR b - 3");
+        // an illegal move or a 9x9 sfen leaves the position alone
+        assert!(manager.interpret_command("position startpos moves 5d5b"));
+        assert!(manager.interpret_command(&format!("position sfen {STARTPOS}")));
+        assert!(manager.interpret_command("makemove 5e4d"));
+        assert_eq!(
+            manager.variant_board.as_ref().unwrap().to_fen(),
+            "rbsgk/5/P3p/1K3/1GSBR w - 4"
+        );
+        // the search only knows standard shogi
+        assert!(manager.interpret_command("go depth 1"));
+        assert!(manager.search.is_none());
+        assert!(manager.interpret_command("position startpos"));
+        assert_eq!(manager.variant_board.as_mut().unwrap().perft(1), 14);
+
+        assert!(manager.interpret_command("setoption name USI_Variant value shogi"));
+        assert!(manager.variant_board.is_none());
+        assert_eq!(manager.board.to_fen(), STARTPOS);
+    }
+
+    #[test]
+    fn ponder_holds_the_bestmove_until_ponderhit() {
+        let (_sender, input) = mpsc::channel();
+        let mut manager = UsiManager::new(input);
+        assert!(manager.interpret_command("go ponder depth 1 btime 1000 wtime 1000"));
+        thread::sleep(Duration::from_millis(50));
+        assert!(!manager.search.as_ref().unwrap().handle.is_finished());
+
+        assert!(manager.interpret_command("ponderhit"));
+        let search = manager.search.take().unwrap();
+        let start = Instant::now();
+        while !search.handle.is_finished() {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn book_is_probed_before_searching() {
+        let path = env::temp_dir().join(format!("ctenophore-book-{}.db", std::process::id()));
+        fs::write(
+            &path,
+            format!("#YANEURAOU-DB2016 1.00\nsfen {STARTPOS}\n2g2f 8c8d 0 32 1\n"),
+        )
+        .unwrap();
+
+        let (_sender, input) = mpsc::channel();
+        let mut manager = UsiManager::new(input);
+        let command = format!("setoption name BookFile value {}", path.display());
+        assert!(manager.interpret_command(&command));
+        fs::remove_file(&path).unwrap();
+        assert_eq!(manager.book.as_ref().map(|book| book.len()), Some(1));
+
+        assert!(manager.interpret_command("position startpos"));
+        let params = GoParams::default();
+        assert_eq!(
+            manager
+                .probe_book(params)
+                .map(|(action, _)| action.to_usi()),
+            Some("2g2f".to_owned())
+        );
+        assert!(manager.interpret_command("setoption name BookMoves value 0"));
+        assert_eq!(manager.probe_book(params), None);
+
+        assert!(manager.interpret_command("setoption name BookFile value <empty>"));
+        assert!(manager.book.is_none());
     }
 }
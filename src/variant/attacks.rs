@@ -0,0 +1,146 @@
+use crate::types::{bitboard::Bitboard, piece::Piece, square::Square};
+
+use super::Variant;
+
+// rank and file steps from sente's point of view, gote's are mirrored
+const UP: (i8, i8) = (1, 0);
+const DOWN: (i8, i8) = (-1, 0);
+const LEFT: (i8, i8) = (0, -1);
+const RIGHT: (i8, i8) = (0, 1);
+const UP_LEFT: (i8, i8) = (1, -1);
+const UP_RIGHT: (i8, i8) = (1, 1);
+const DOWN_LEFT: (i8, i8) = (-1, -1);
+const DOWN_RIGHT: (i8, i8) = (-1, 1);
+
+const ORTHOGONAL: [(i8, i8); 4] = [UP, DOWN, LEFT, RIGHT];
+const DIAGONAL: [(i8, i8); 4] = [UP_LEFT, UP_RIGHT, DOWN_LEFT, DOWN_RIGHT];
+const SILVER: [(i8, i8); 5] = [UP_LEFT, UP, UP_RIGHT, DOWN_LEFT, DOWN_RIGHT];
+const GOLD: [(i8, i8); 6] = [UP_LEFT, UP, UP_RIGHT, LEFT, RIGHT, DOWN];
+const KNIGHT: [(i8, i8); 2] = [(2, -1), (2, 1)];
+
+type Directions = &'static [(i8, i8)];
+
+// the steps and the slides of a piece type as sente moves it
+fn movement(piece: Piece) -> (Directions, Directions) {
+    match piece {
+        Piece::PAWN => (&[UP], &[]),
+        Piece::LANCE => (&[], &[UP]),
+        Piece::KNIGHT => (&KNIGHT, &[]),
+        Piece::SILVER => (&SILVER, &[]),
+        Piece::BISHOP => (&[], &DIAGONAL),
+        Piece::ROOK => (&[], &ORTHOGONAL),
+        Piece::KING => (
+            &[
+                UP_LEFT, UP, UP_RIGHT, LEFT, RIGHT, DOWN_LEFT, DOWN, DOWN_RIGHT,
+            ],
+            &[],
+        ),
+        Piece::PROMO_BISHOP => (&ORTHOGONAL, &DIAGONAL),
+        Piece::PROMO_ROOK => (&DIAGONAL, &ORTHOGONAL),
+        // gold and the promoted minor pieces
+        _ => (&GOLD, &[]),
+    }
+}
+
+/// Attack lookups for one variant's board: what every piece attacks from every square without
+/// sliding, and the rays the sliders slide along.
+pub(super) struct AttackTables {
+    // indexed by side, then piece type, then square
+    steps: Vec<Vec<Vec<Bitboard>>>,
+    // every direction's ray from every square, the square itself left out
+    rays: Vec<((i8, i8), Vec<Bitboard>)>,
+}
+
+impl AttackTables {
+    pub(super) fn new(variant: Variant) -> Self {
+        let squares = variant.num_squares();
+        let target = |sq: u8, (rank_step, file_step): (i8, i8)| {
+            let sq = Square(sq);
+            let rank = variant.rank_of(sq) as i8 + rank_step;
+            let file = variant.file_of(sq) as i8 + file_step;
+            let on_board = (0..variant.ranks() as i8).contains(&rank)
+                && (0..variant.files() as i8).contains(&file);
+            on_board.then(|| variant.square(rank as u8, file as u8))
+        };
+
+        let steps = (0..2)
+            .map(|side| {
+                (0..Piece::NONE.raw())
+                    .map(|piece| {
+                        let (steps, _) = movement(Piece(piece));
+                        (0..squares)
+                            .map(|sq| {
+                                let mut attacks = Bitboard::EMPTY;
+                                for &(rank_step, file_step) in steps {
+                                    // gote moves down the board
+                                    let rank_step = if side == 0 { rank_step } else { -rank_step };
+                                    if let Some(to) = target(sq, (rank_step, file_step)) {
+                                        attacks |= Bitboard::from_square(to);
+                                    }
+                                }
+                                attacks
+                            })
+                            .collect()
+                    })
+                    .collect()
+            })
+            .collect();
+
+        let rays = ORTHOGONAL
+            .iter()
+            .chain(&DIAGONAL)
+            .map(|&direction| {
+                let ray = (0..squares)
+                    .map(|sq| {
+                        let mut ray = Bitboard::EMPTY;
+                        let mut current = sq;
+                        while let Some(next) = target(current, direction) {
+                            ray |= Bitboard::from_square(next);
+                            current = next.0;
+                        }
+                        ray
+                    })
+                    .collect();
+                (direction, ray)
+            })
+            .collect();
+
+        Self { steps, rays }
+    }
+
+    // the squares a slider reaches along one direction, up to and including the first piece
+    fn slide(&self, sq: Square, direction: (i8, i8), occ: Bitboard) -> Bitboard {
+        let (_, rays) = self
+            .rays
+            .iter()
+            .find(|(dir, _)| *dir == direction)
+            .expect("every direction has rays");
+        let ray = rays[sq.as_usize()];
+        let blockers = ray & occ;
+        if blockers.is_empty() {
+            return ray;
+        }
+        // squares go up the board and to the right, so the nearest blocker is the lowest square
+        // for those directions and the highest one for the others
+        let (rank_step, file_step) = direction;
+        let nearest = if rank_step > 0 || (rank_step == 0 && file_step > 0) {
+            blockers.lsb()
+        } else {
+            127 - blockers.msb()
+        };
+        ray ^ rays[nearest as usize]
+    }
+
+    /// The squares a piece (with its side) attacks from a square.
+    pub(super) fn attacks(&self, piece: Piece, sq: Square, occ: Bitboard) -> Bitboard {
+        let side = piece.side();
+        let piece_type = piece.piece();
+        let mut attacks = self.steps[side as usize][piece_type.as_usize()][sq.as_usize()];
+        let (_, slides) = movement(piece_type);
+        for &(rank_step, file_step) in slides {
+            let rank_step = if side == 0 { rank_step } else { -rank_step };
+            attacks |= self.slide(sq, (rank_step, file_step), occ);
+        }
+        attacks
+    }
+}
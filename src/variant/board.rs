@@ -0,0 +1,452 @@
+use crate::types::{action::Action, bitboard::Bitboard, hand::Hand, piece::Piece, square::Square};
+
+use super::Variant;
+
+/// A position in any [`Variant`]. This is the straightforward version of the main board: a
+/// mailbox and one occupancy bitboard per side, with legality checked by making each action and
+/// looking for attacks on the king.
+#[derive(Debug, Clone)]
+pub struct VariantBoard {
+    variant: Variant,
+    mailbox: Vec<Piece>,
+    sides: [Bitboard; 2],
+    hands: [Hand; 2],
+    stm: u8,
+    ply: u16,
+    // every action performed with the piece it captured, to undo them
+    history: Vec<(Action, Piece)>,
+}
+
+fn piece_from_char(c: char) -> Option<Piece> {
+    let piece = match c.to_ascii_lowercase() {
+        'p' => Piece::PAWN,
+        'l' => Piece::LANCE,
+        'n' => Piece::KNIGHT,
+        's' => Piece::SILVER,
+        'b' => Piece::BISHOP,
+        'r' => Piece::ROOK,
+        'g' => Piece::GOLD,
+        'k' => Piece::KING,
+        _ => return None,
+    };
+    Some(piece.as_stm(c.is_ascii_lowercase() as u8))
+}
+
+// how many of a piece type the variant is played with, which is how many of it the starting
+// position has
+fn piece_limit(variant: Variant, piece: Piece) -> u32 {
+    let (board, _) = variant
+        .start_fen()
+        .split_once(' ')
+        .expect("start positions have a side to move");
+    board
+        .chars()
+        .filter(|&c| piece_from_char(c).is_some_and(|p| p.piece() == piece.piece()))
+        .count() as u32
+}
+
+// the pieces that promote, by type
+fn is_promotable(piece: Piece) -> bool {
+    piece.piece().raw() < Piece::GOLD.raw()
+}
+
+impl VariantBoard {
+    pub fn from_fen(variant: Variant, fen: &str) -> Result<Self, String> {
+        let segments: Vec<&str> = fen.split_ascii_whitespace().collect();
+        if !(3..=4).contains(&segments.len()) {
+            return Err(format!(
+                "expected 3 or 4 fields in sfen, found {}",
+                segments.len()
+            ));
+        }
+        let allowed = |piece: Piece| {
+            piece.piece() == Piece::KING || variant.pieces().contains(&piece.piece())
+        };
+
+        let files = variant.files();
+        let rows: Vec<&str> = segments[0].split('/').collect();
+        if rows.len() != variant.ranks() as usize {
+            return Err(format!(
+                "expected {} ranks in {variant} sfen, found {}",
+                variant.ranks(),
+                rows.len()
+            ));
+        }
+        let mut board = Self {
+            variant,
+            mailbox: vec![Piece::NONE; variant.num_squares() as usize],
+            sides: [Bitboard::EMPTY; 2],
+            hands: [Hand::EMPTY; 2],
+            stm: 0,
+            ply: 1,
+            history: Vec::new(),
+        };
+        let mut kings = [0; 2];
+        // the top rank comes first
+        for (row, rank) in rows.iter().zip((0..variant.ranks()).rev()) {
+            let mut file = 0u8;
+            let mut is_promoted = false;
+            for c in row.chars() {
+                if c == '+' && !is_promoted {
+                    is_promoted = true;
+                    continue;
+                }
+                if let Some(empty) = c.to_digit(10).filter(|_| !is_promoted) {
+                    file = file
+                        .checked_add(empty as u8)
+                        .filter(|&file| file <= files)
+                        .ok_or_else(|| format!("rank {row} isn't {files} squares"))?;
+                    continue;
+                }
+                let piece = piece_from_char(c)
+                    .filter(|&piece| allowed(piece))
+                    .ok_or_else(|| format!("invalid character in {variant} sfen: {c}"))?;
+                if is_promoted && !is_promotable(piece) {
+                    return Err(format!("{c} can't be promoted"));
+                }
+                if file >= files {
+                    return Err(format!("rank {row} isn't {files} squares"));
+                }
+                if piece.piece() == Piece::KING {
+                    kings[piece.side() as usize] += 1;
+                }
+                let piece = if is_promoted { piece.promote() } else { piece };
+                board.put(piece, variant.square(rank, file));
+                is_promoted = false;
+                file += 1;
+            }
+            if is_promoted || file != files {
+                return Err(format!("rank {row} isn't {files} squares"));
+            }
+        }
+        if kings != [1, 1] {
+            return Err("each side needs exactly one king".to_owned());
+        }
+
+        board.stm = match segments[1] {
+            "b" => 0,
+            "w" => 1,
+            stm => return Err(format!("side to move must be b or w, not {stm}")),
+        };
+
+        if segments[2] != "-" {
+            let mut count = 0u32;
+            for c in segments[2].chars() {
+                if let Some(digit) = c.to_digit(10) {
+                    count = count.saturating_mul(10).saturating_add(digit);
+                    continue;
+                }
+                let piece = piece_from_char(c)
+                    .filter(|&piece| piece.piece() != Piece::KING && allowed(piece))
+                    .ok_or_else(|| format!("invalid character in hand: {c}"))?;
+                let max = piece_limit(variant, piece);
+                let count = std::mem::take(&mut count).max(1);
+                if count > max {
+                    return Err(format!("can't have {count} of {c} in hand"));
+                }
+                board.hands[piece.side() as usize].set(piece, count);
+            }
+            if count != 0 {
+                return Err("hand ends with a count".to_owned());
+            }
+        }
+        // promoted pieces count as the piece they promoted from
+        for &piece in variant.pieces() {
+            let on_board = board
+                .mailbox
+                .iter()
+                .filter(|&&on| on != Piece::NONE && on.piece().unpromote() == piece)
+                .count() as u32;
+            let in_hands: u32 = board.hands.iter().map(|hand| hand.num(piece) as u32).sum();
+            let max = piece_limit(variant, piece);
+            if on_board + in_hands > max {
+                return Err(format!(
+                    "{} of {piece} on the board and in hand, {variant} has only {max}",
+                    on_board + in_hands
+                ));
+            }
+        }
+
+        if let Some(ply) = segments.get(3) {
+            board.ply = ply
+                .parse()
+                .map_err(|_| format!("invalid move count: {ply}"))?;
+        }
+        Ok(board)
+    }
+
+    pub fn startpos(variant: Variant) -> Self {
+        Self::from_fen(variant, variant.start_fen()).expect("start positions are valid")
+    }
+
+    pub fn to_fen(&self) -> String {
+        let variant = self.variant;
+        let mut fen = String::new();
+        for rank in (0..variant.ranks()).rev() {
+            let mut empty = 0;
+            for file in 0..variant.files() {
+                let piece = self.piece_on(variant.square(rank, file));
+                if piece == Piece::NONE {
+                    empty += 1;
+                    continue;
+                }
+                if empty > 0 {
+                    fen += &empty.to_string();
+                    empty = 0;
+                }
+                fen += &piece.to_string();
+            }
+            if empty > 0 {
+                fen += &empty.to_string();
+            }
+            if rank != 0 {
+                fen.push('/');
+            }
+        }
+
+        fen += if self.stm == 0 { " b " } else { " w " };
+
+        let mut hand_fen = String::new();
+        for side in 0..2 {
+            for &piece in variant.pieces() {
+                let count = self.hands[side as usize].num(piece);
+                if count > 1 {
+                    hand_fen += &count.to_string();
+                }
+                if count > 0 {
+                    hand_fen += &piece.as_stm(side).to_string();
+                }
+            }
+        }
+        if hand_fen.is_empty() {
+            hand_fen.push('-');
+        }
+        fen += &hand_fen;
+
+        fen += &format!(" {}", self.ply);
+        fen
+    }
+
+    pub fn variant(&self) -> Variant {
+        self.variant
+    }
+
+    pub fn stm(&self) -> u8 {
+        self.stm
+    }
+
+    pub fn piece_on(&self, sq: Square) -> Piece {
+        self.mailbox[sq.as_usize()]
+    }
+
+    pub fn hand(&self, side: u8) -> Hand {
+        self.hands[side as usize]
+    }
+
+    fn put(&mut self, piece: Piece, sq: Square) {
+        self.mailbox[sq.as_usize()] = piece;
+        self.sides[piece.side() as usize] |= Bitboard::from_square(sq);
+    }
+
+    fn remove(&mut self, sq: Square) -> Piece {
+        let piece = std::mem::replace(&mut self.mailbox[sq.as_usize()], Piece::NONE);
+        self.sides[piece.side() as usize] ^= Bitboard::from_square(sq);
+        piece
+    }
+
+    fn occupied(&self) -> Bitboard {
+        self.sides[0] | self.sides[1]
+    }
+
+    fn attacks(&self, piece: Piece, sq: Square) -> Bitboard {
+        self.variant.attacks().attacks(piece, sq, self.occupied())
+    }
+
+    fn is_attacked(&self, sq: Square, by: u8) -> bool {
+        let target = Bitboard::from_square(sq);
+        self.sides[by as usize]
+            .into_iter()
+            .any(|from| (self.attacks(self.piece_on(from), from) & target).is_not_empty())
+    }
+
+    fn king_square(&self, side: u8) -> Square {
+        let king = Piece::KING.as_stm(side);
+        self.sides[side as usize]
+            .into_iter()
+            .find(|&sq| self.piece_on(sq) == king)
+            .expect("both sides have a king")
+    }
+
+    fn is_king_attacked(&self, side: u8) -> bool {
+        self.is_attacked(self.king_square(side), 1 - side)
+    }
+
+    /// Whether the side to move is in check.
+    pub fn in_check(&self) -> bool {
+        self.is_king_attacked(self.stm)
+    }
+
+    /// Every action the side to move's pieces can make, including ones that leave its king in
+    /// check or drop a pawn to mate. Moves that would leave a piece unable to move are left out.
+    pub fn pseudo_legal_actions(&self) -> Vec<Action> {
+        let variant = self.variant;
+        let stm = self.stm;
+        let mut actions = Vec::new();
+
+        for from in self.sides[stm as usize] {
+            let piece = self.piece_on(from);
+            let dead = variant.ranks() - Variant::dead_ranks(piece);
+            for to in self.attacks(piece, from) & !self.sides[stm as usize] {
+                let can_promote = is_promotable(piece)
+                    && (variant.in_promotion_zone(from, stm) || variant.in_promotion_zone(to, stm));
+                if can_promote {
+                    actions.push(Action::new_move(from, to, true));
+                }
+                // a piece that could never move again has to promote
+                if !can_promote || variant.relative_rank(to, stm) < dead {
+                    actions.push(Action::new_move(from, to, false));
+                }
+            }
+        }
+
+        let empty: Vec<Square> = (0..variant.num_squares())
+            .map(Square)
+            .filter(|&sq| self.piece_on(sq) == Piece::NONE)
+            .collect();
+        for &piece in variant.pieces() {
+            if self.hands[stm as usize].num(piece) == 0 {
+                continue;
+            }
+            let piece = piece.as_stm(stm);
+            let dead = variant.ranks() - Variant::dead_ranks(piece);
+            for &to in &empty {
+                if variant.relative_rank(to, stm) >= dead {
+                    continue;
+                }
+                // nifu, no two unpromoted pawns of a side on one file
+                if piece.piece() == Piece::PAWN
+                    && (0..variant.ranks()).any(|rank| {
+                        self.piece_on(variant.square(rank, variant.file_of(to))) == piece
+                    })
+                {
+                    continue;
+                }
+                actions.push(Action::new_drop(piece, to));
+            }
+        }
+        actions
+    }
+
+    /// Every legal action for the side to move.
+    pub fn legal_actions(&mut self) -> Vec<Action> {
+        let mut actions = self.pseudo_legal_actions();
+        actions.retain(|&action| self.is_legal(action));
+        actions
+    }
+
+    fn is_legal(&mut self, action: Action) -> bool {
+        let stm = self.stm;
+        self.perform_action(action);
+        let mut legal = !self.is_king_attacked(stm);
+        // uchifuzume, a pawn drop can't checkmate
+        if legal && action.is_drop() && action.piece().piece() == Piece::PAWN && self.in_check() {
+            legal = !self.legal_actions().is_empty();
+        }
+        self.undo_action();
+        legal
+    }
+
+    /// A legal action in USI notation on this variant's board.
+    pub fn action_from_usi(&mut self, usi: &str) -> Result<Action, String> {
+        let variant = self.variant;
+        self.legal_actions()
+            .into_iter()
+            .find(|&action| variant.action_to_usi(action) == usi)
+            .ok_or_else(|| format!("not a legal {variant} action"))
+    }
+
+    /// Makes an action without checking it, it should come from the action generators.
+    pub fn perform_action(&mut self, action: Action) {
+        let to = action.to();
+        let mut captured = Piece::NONE;
+        if action.is_drop() {
+            let piece = action.piece();
+            self.hands[self.stm as usize].dec(piece);
+            self.put(piece, to);
+        } else {
+            let piece = self.remove(action.from());
+            if self.piece_on(to) != Piece::NONE {
+                captured = self.remove(to);
+                self.hands[self.stm as usize].inc(captured.piece().unpromote());
+            }
+            self.put(
+                if action.is_promo() {
+                    piece.promote()
+                } else {
+                    piece
+                },
+                to,
+            );
+        }
+        self.history.push((action, captured));
+        self.stm ^= 1;
+        self.ply += 1;
+    }
+
+    pub fn undo_action(&mut self) {
+        let (action, captured) = self.history.pop().expect("no action to undo");
+        self.stm ^= 1;
+        self.ply -= 1;
+        let to = action.to();
+        let piece = self.remove(to);
+        if action.is_drop() {
+            self.hands[self.stm as usize].inc(piece);
+        } else {
+            self.put(
+                if action.is_promo() {
+                    piece.unpromote()
+                } else {
+                    piece
+                },
+                action.from(),
+            );
+            if captured != Piece::NONE {
+                self.hands[self.stm as usize].dec(captured.piece().unpromote());
+                self.put(captured, to);
+            }
+        }
+    }
+
+    /// The number of legal action sequences `depth` actions long.
+    pub fn perft(&mut self, depth: u8) -> u64 {
+        if depth == 0 {
+            return 1;
+        }
+        let actions = self.legal_actions();
+        if depth == 1 {
+            return actions.len() as u64;
+        }
+        let mut count = 0;
+        for action in actions {
+            self.perform_action(action);
+            count += self.perft(depth - 1);
+            self.undo_action();
+        }
+        count
+    }
+
+    /// The perft count after each legal action, sorted by the action in USI notation.
+    pub fn divide(&mut self, depth: u8) -> Vec<(String, u64)> {
+        let mut results = Vec::new();
+        for action in self.legal_actions() {
+            self.perform_action(action);
+            results.push((
+                self.variant.action_to_usi(action),
+                self.perft(depth.max(1) - 1),
+            ));
+            self.undo_action();
+        }
+        results.sort();
+        results
+    }
+}
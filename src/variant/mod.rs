@@ -0,0 +1,310 @@
+//! Shogi on other board sizes. The main [`Board`](crate::board::Board) is built around 9x9 with
+//! magic bitboards for speed. The variants here share its [`Piece`], [`Action`], [`Square`] and
+//! [`Bitboard`](crate::types::bitboard::Bitboard) types, but take the board size, promotion zone,
+//! pieces and starting position from a [`Variant`] and build their attack tables for it at
+//! runtime. Standard shogi is a variant too, so the generic code can be checked against the main
+//! board.
+//!
+//! Only move generation is generic. The engine's `USI_Variant` option sets positions up and runs
+//! perft on a [`VariantBoard`], but search, evaluation and the other tools are built on the main
+//! board and play standard shogi only.
+//!
+//! Squares count along the ranks from sente's side, like the main board: square 0 is the
+//! leftmost square of sente's back rank, and a variant with `files` files has square `files` as
+//! the leftmost square of the rank in front of it.
+
+mod attacks;
+mod board;
+
+use std::{fmt, str::FromStr, sync::OnceLock};
+
+pub use board::VariantBoard;
+
+use crate::types::{action::Action, piece::Piece, square::Square};
+use attacks::AttackTables;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Variant {
+    /// 9x9 shogi, the same rules as the main board.
+    Standard,
+    /// 5x5 with a king, gold, silver, bishop, rook and pawn each, promoting on the last rank.
+    Minishogi,
+    /// 6x6 with a knight added to minishogi's pieces, promoting on the last two ranks.
+    Judkins,
+}
+
+// the unpromoted pieces of each variant in the order sfen hands are written, kings left out
+const STANDARD_PIECES: [Piece; 7] = [
+    Piece::ROOK,
+    Piece::BISHOP,
+    Piece::GOLD,
+    Piece::SILVER,
+    Piece::KNIGHT,
+    Piece::LANCE,
+    Piece::PAWN,
+];
+const MINISHOGI_PIECES: [Piece; 5] = [
+    Piece::ROOK,
+    Piece::BISHOP,
+    Piece::GOLD,
+    Piece::SILVER,
+    Piece::PAWN,
+];
+const JUDKINS_PIECES: [Piece; 6] = [
+    Piece::ROOK,
+    Piece::BISHOP,
+    Piece::GOLD,
+    Piece::SILVER,
+    Piece::KNIGHT,
+    Piece::PAWN,
+];
+
+impl Variant {
+    pub const ALL: [Self; 3] = [Self::Standard, Self::Minishogi, Self::Judkins];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Standard => "shogi",
+            Self::Minishogi => "minishogi",
+            Self::Judkins => "judkins",
+        }
+    }
+
+    pub fn files(self) -> u8 {
+        match self {
+            Self::Standard => 9,
+            Self::Minishogi => 5,
+            Self::Judkins => 6,
+        }
+    }
+
+    pub fn ranks(self) -> u8 {
+        self.files()
+    }
+
+    pub fn num_squares(self) -> u8 {
+        self.files() * self.ranks()
+    }
+
+    /// How many ranks at the far end of the board make up the promotion zone.
+    pub fn promotion_ranks(self) -> u8 {
+        match self {
+            Self::Standard => 3,
+            Self::Minishogi => 1,
+            Self::Judkins => 2,
+        }
+    }
+
+    /// The unpromoted piece types other than the king, in the order hands are written.
+    pub fn pieces(self) -> &'static [Piece] {
+        match self {
+            Self::Standard => &STANDARD_PIECES,
+            Self::Minishogi => &MINISHOGI_PIECES,
+            Self::Judkins => &JUDKINS_PIECES,
+        }
+    }
+
+    pub fn start_fen(self) -> &'static str {
+        match self {
+            Self::Standard => crate::board::STARTPOS,
+            Self::Minishogi => "rbsgk/4p/5/P4/KGSBR b - 1",
+            Self::Judkins => "rbnsgk/5p/6/6/P5/KGSNBR b - 1",
+        }
+    }
+
+    pub fn square(self, rank: u8, file: u8) -> Square {
+        Square(rank * self.files() + file)
+    }
+
+    /// The rank counted from sente's side, 0 is sente's back rank.
+    pub fn rank_of(self, sq: Square) -> u8 {
+        sq.0 / self.files()
+    }
+
+    /// The file counted from the left as sente sees the board, so the highest numbered file is 0.
+    pub fn file_of(self, sq: Square) -> u8 {
+        sq.0 % self.files()
+    }
+
+    /// The rank as `side` sees it, 0 is its own back rank.
+    pub fn relative_rank(self, sq: Square, side: u8) -> u8 {
+        let rank = self.rank_of(sq);
+        if side == 0 {
+            rank
+        } else {
+            self.ranks() - 1 - rank
+        }
+    }
+
+    pub fn in_promotion_zone(self, sq: Square, side: u8) -> bool {
+        self.relative_rank(sq, side) >= self.ranks() - self.promotion_ranks()
+    }
+
+    /// How many ranks at the far end a piece can never move from, so it can't be dropped there
+    /// and has to promote when it gets there.
+    pub fn dead_ranks(piece: Piece) -> u8 {
+        match piece.piece() {
+            Piece::PAWN | Piece::LANCE => 1,
+            Piece::KNIGHT => 2,
+            _ => 0,
+        }
+    }
+
+    /// A square in USI notation, like `5e`. Files are numbered from the right, ranks lettered
+    /// from gote's side.
+    pub fn square_to_usi(self, sq: Square) -> String {
+        let file = self.files() - self.file_of(sq);
+        let rank = (b'a' + self.ranks() - 1 - self.rank_of(sq)) as char;
+        format!("{file}{rank}")
+    }
+
+    /// An action in USI notation on this variant's board.
+    pub fn action_to_usi(self, action: Action) -> String {
+        let to = self.square_to_usi(action.to());
+        if action.is_drop() {
+            let piece = action.piece().to_string().to_ascii_uppercase();
+            format!("{piece}*{to}")
+        } else {
+            let from = self.square_to_usi(action.from());
+            let promo = if action.is_promo() { "+" } else { "" };
+            format!("{from}{to}{promo}")
+        }
+    }
+
+    // built the first time a variant is used
+    fn attacks(self) -> &'static AttackTables {
+        static TABLES: [OnceLock<AttackTables>; 3] =
+            [OnceLock::new(), OnceLock::new(), OnceLock::new()];
+        TABLES[self as usize].get_or_init(|| AttackTables::new(self))
+    }
+}
+
+impl FromStr for Variant {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|variant| variant.name().eq_ignore_ascii_case(s))
+            .ok_or_else(|| format!("unknown variant {s}, expected shogi, minishogi or judkins"))
+    }
+}
+
+impl fmt::Display for Variant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Variant, VariantBoard};
+    use crate::board::{Board, STARTPOS};
+
+    #[test]
+    fn start_positions() {
+        for variant in Variant::ALL {
+            let board = VariantBoard::startpos(variant);
+            assert_eq!(board.to_fen(), variant.start_fen());
+            assert_eq!(variant.name().parse(), Ok(variant));
+        }
+        assert!(VariantBoard::from_fen(Variant::Minishogi, STARTPOS).is_err());
+        // no lances in judkins
+        assert!(VariantBoard::from_fen(Variant::Judkins, "rbnsgk/5p/6/6/P5/KGSNBR b L 1").is_err());
+        assert!("chess".parse::<Variant>().is_err());
+    }
+
+    #[test]
+    fn invalid_sfens() {
+        let fen = |board: &str, hand: &str| format!("{board} b {hand} 1");
+        // hands hold at most every piece of a type the variant has
+        assert!(VariantBoard::from_fen(Variant::Minishogi, &fen("4k/5/5/5/K4", "2P")).is_ok());
+        assert!(VariantBoard::from_fen(Variant::Minishogi, &fen("4k/5/5/5/K4", "3P")).is_err());
+        assert!(VariantBoard::from_fen(Variant::Minishogi, &fen("4k/5/5/5/K4", "18P")).is_err());
+        assert!(
+            VariantBoard::from_fen(Variant::Standard, &fen("8k/9/9/9/9/9/9/9/K8", "18P")).is_ok()
+        );
+        // and so do the board and both hands together, promoted pieces included
+        for board in [
+            "4k/rr3/RR3/5/K4 b 2R",
+            "4k/5/+R4/5/K4 b Rr",
+            "4k/5/5/5/K4 b 2Pp",
+        ] {
+            assert!(VariantBoard::from_fen(Variant::Minishogi, &format!("{board} 1")).is_err());
+        }
+        assert!(VariantBoard::from_fen(Variant::Minishogi, "4k/5/+R4/5/K4 b r 1").is_ok());
+        // runs of empty squares can't run past the edge of the board, however long they are
+        let long = "9".repeat(40);
+        for board in [format!("{long}k/5/5/5/K4"), "33k/5/5/5/K4".to_owned()] {
+            assert!(VariantBoard::from_fen(Variant::Minishogi, &fen(&board, "-")).is_err());
+        }
+    }
+
+    #[test]
+    fn usi() {
+        let variant = Variant::Minishogi;
+        assert_eq!(variant.square_to_usi(variant.square(0, 0)), "5e");
+        assert_eq!(variant.square_to_usi(variant.square(4, 4)), "1a");
+
+        let mut board = Board::from_fen(STARTPOS);
+        let mut generic = VariantBoard::startpos(Variant::Standard);
+        let mut ours: Vec<String> = generic
+            .legal_actions()
+            .into_iter()
+            .map(|action| Variant::Standard.action_to_usi(action))
+            .collect();
+        let mut theirs: Vec<String> = board
+            .legal_actions()
+            .iter()
+            .map(|action| action.to_usi())
+            .collect();
+        ours.sort();
+        theirs.sort();
+        assert_eq!(ours, theirs);
+    }
+
+    #[test]
+    fn pawn_drop_mate() {
+        // the gold guards 5b, so the king can't take a pawn dropped there
+        let mut board =
+            VariantBoard::from_fen(Variant::Minishogi, "kr3/1b3/G4/5/3K1 b P 1").unwrap();
+        let drops: Vec<String> = board
+            .legal_actions()
+            .into_iter()
+            .filter(|action| action.is_drop())
+            .map(|action| Variant::Minishogi.action_to_usi(action))
+            .collect();
+        assert!(!drops.contains(&"P*5b".to_owned()));
+        assert!(drops.contains(&"P*4c".to_owned()));
+
+        let mut board =
+            VariantBoard::from_fen(Variant::Minishogi, "kr3/1b3/5/5/G2K1 b P 1").unwrap();
+        assert!(board
+            .legal_actions()
+            .into_iter()
+            .any(|action| Variant::Minishogi.action_to_usi(action) == "P*5b"));
+    }
+
+    #[test]
+    fn perft() {
+        // standard and minishogi are the published counts, judkins was checked against
+        // scripts/perft.py, which generates moves independently
+        let expected: [(Variant, &[u64]); 3] = [
+            (Variant::Standard, &[30, 900, 25470, 719731]),
+            (Variant::Minishogi, &[14, 181, 2512, 35401, 533203]),
+            (Variant::Judkins, &[20, 336, 6183, 118345]),
+        ];
+        for (variant, counts) in expected {
+            let mut board = VariantBoard::startpos(variant);
+            for (depth, &count) in counts.iter().enumerate() {
+                assert_eq!(
+                    board.perft(depth as u8 + 1),
+                    count,
+                    "{variant} depth {}",
+                    depth + 1
+                );
+            }
+            assert_eq!(board.to_fen(), variant.start_fen());
+        }
+    }
+}
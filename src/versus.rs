@@ -0,0 +1,183 @@
+use std::{
+    fs::{self, File},
+    io::BufWriter,
+    time::Duration,
+};
+
+use ctenophore::{
+    convert::{Format, Record, Writer},
+    record::GameResult,
+    tournament::{run_match, MatchConfig, Opening, Score, Sprt},
+};
+
+// usage: match <engine1> <engine2> <openings> [games n] [time ms] [inc ms] [byoyomi ms]
+// [maxmoves n] [resign score,moves] [draw score,moves,ply] [records path] [format csa|kif]
+// [sprt elo0,elo1] [alpha x] [beta x] [option1 name=value] [option2 name=value], scores are from
+// engine1's point of view
+pub fn run(args: &[String]) {
+    let usage = "usage: match <engine1> <engine2> <openings> [games n] [time ms] [inc ms] \
+                 [byoyomi ms] [maxmoves n] [resign score,moves] [draw score,moves,ply] \
+                 [records path] [format csa|kif] [sprt elo0,elo1] [alpha x] [beta x] \
+                 [option1 name=value] [option2 name=value]";
+    let [first, second, openings, rest @ ..] = args else {
+        println!("{usage}");
+        return;
+    };
+
+    let mut config = MatchConfig::default();
+    config.engines[0].command = first.clone();
+    config.engines[1].command = second.clone();
+    let mut records = None;
+    let mut format = None;
+    let mut sprt = None;
+    let mut alpha = None;
+    let mut beta = None;
+    for pair in rest.chunks(2) {
+        let [name, value] = pair else {
+            println!("missing value for {}", pair[0]);
+            return;
+        };
+        let millis = || value.parse().map(Duration::from_millis).ok();
+        let parsed = match name.as_str() {
+            "games" => value.parse().map(|value| config.games = value).is_ok(),
+            "time" => millis()
+                .map(|time| config.time_control.time = time)
+                .is_some(),
+            "inc" => millis()
+                .map(|time| config.time_control.increment = time)
+                .is_some(),
+            "byoyomi" => millis()
+                .map(|time| config.time_control.byoyomi = time)
+                .is_some(),
+            "maxmoves" => value.parse().map(|value| config.max_moves = value).is_ok(),
+            "resign" => value
+                .parse()
+                .map(|rule| config.adjudication.resign = Some(rule))
+                .is_ok(),
+            "draw" => value
+                .parse()
+                .map(|rule| config.adjudication.draw = Some(rule))
+                .is_ok(),
+            "records" => {
+                records = Some(value.clone());
+                true
+            }
+            "format" => value
+                .parse()
+                .ok()
+                .filter(|format: &Format| format.is_game())
+                .map(|value| format = Some(value))
+                .is_some(),
+            "sprt" => value
+                .split_once(',')
+                .and_then(|(elo0, elo1)| Some((elo0.parse().ok()?, elo1.parse().ok()?)))
+                .map(|value| sprt = Some(value))
+                .is_some(),
+            "alpha" => value.parse().map(|value| alpha = Some(value)).is_ok(),
+            "beta" => value.parse().map(|value| beta = Some(value)).is_ok(),
+            "option1" | "option2" => match value.split_once('=') {
+                Some((option, setting)) => {
+                    let engine = usize::from(name == "option2");
+                    config.engines[engine]
+                        .options
+                        .push((option.to_owned(), setting.to_owned()));
+                    true
+                }
+                None => false,
+            },
+            _ => {
+                println!("unknown option: {name}\n{usage}");
+                return;
+            }
+        };
+        if !parsed {
+            println!("invalid {name}: {value}");
+            return;
+        }
+    }
+    if let Some((elo0, elo1)) = sprt {
+        let defaults = Sprt::default();
+        config.sprt = Some(Sprt {
+            elo0,
+            elo1,
+            alpha: alpha.unwrap_or(defaults.alpha),
+            beta: beta.unwrap_or(defaults.beta),
+        });
+    }
+
+    let text = fs::read_to_string(openings).expect("failed to read the openings");
+    config.openings = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match Opening::parse(line) {
+            Ok(opening) => config.openings.push(opening),
+            Err(e) => println!("skipping opening on line {}: {e}", number + 1),
+        }
+    }
+    if config.openings.is_empty() {
+        println!("no openings in {openings}");
+        return;
+    }
+
+    // the format goes by the extension unless it's given
+    let mut writer = records.map(|path| {
+        let format = format.unwrap_or(if path.ends_with(".kif") {
+            Format::Kif
+        } else {
+            Format::Csa
+        });
+        let file = File::create(&path).expect("failed to create the records file");
+        Writer::new(BufWriter::new(file), format, false)
+    });
+
+    let games = config.games;
+    let mut last = Score::default();
+    let result = run_match(&config, |index, game, score| {
+        last = *score;
+        let result = match game.result() {
+            Some(GameResult::SenteWin) => "1-0",
+            Some(GameResult::GoteWin) => "0-1",
+            Some(GameResult::Draw) => "1/2-1/2",
+            None => "*",
+        };
+        println!(
+            "game {}/{games}: {} vs {}, {result} ({:?} after {} actions)",
+            index + 1,
+            game.header("先手").unwrap_or("?"),
+            game.header("後手").unwrap_or("?"),
+            game.termination.expect("finished games have a termination"),
+            game.actions.len(),
+        );
+        print!("score {} - {} - {}", score.wins, score.losses, score.draws);
+        if let Some((elo, margin)) = score.elo() {
+            print!(", elo {elo:.1} +/- {margin:.1}");
+        }
+        match config.sprt {
+            Some(sprt) => {
+                let (lower, upper) = sprt.bounds();
+                println!(", llr {:.2} ({lower:.2}, {upper:.2})", sprt.llr(score));
+            }
+            None => println!(),
+        }
+        if let Some(writer) = &mut writer {
+            writer.write(Record::Game(game.clone()))?;
+            // flushed every game so that the records survive the match being stopped
+            writer.flush()?;
+        }
+        Ok(())
+    });
+    // the score so far has been printed after every game, so it only needs saying why it stopped
+    let score = match result {
+        Ok(score) => score,
+        Err(e) => {
+            println!("match stopped after {} games: {e}", last.games());
+            last
+        }
+    };
+
+    if let Some(sprt) = config.sprt {
+        println!("sprt: {:?}", sprt.status(&score));
+    }
+}
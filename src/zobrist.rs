@@ -0,0 +1,58 @@
+use crate::types::square::NUM_SQUARES;
+
+// piece.raw() has the side in bit 4, so 32 covers both sides
+const NUM_PIECE_KEYS: usize = 32;
+// the most of one piece type a hand can hold is 18 pawns
+const MAX_HAND_COUNT: usize = 19;
+
+pub struct ZobristKeys {
+    pub pieces: [[u64; NUM_SQUARES as usize]; NUM_PIECE_KEYS],
+    pub hands: [[[u64; MAX_HAND_COUNT]; 7]; 2],
+    pub stm: u64,
+}
+
+// xorshift64*, good enough for hashing and usable in a const context
+const fn next_key(state: &mut u64) -> u64 {
+    *state ^= *state >> 12;
+    *state ^= *state << 25;
+    *state ^= *state >> 27;
+    state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+}
+
+// a static so that every use shares the one table instead of copying it
+pub static ZOBRIST: ZobristKeys = {
+    let mut state: u64 = 0x0C7E_4090_4E5E_ED01;
+    let mut keys = ZobristKeys {
+        pieces: [[0; NUM_SQUARES as usize]; NUM_PIECE_KEYS],
+        hands: [[[0; MAX_HAND_COUNT]; 7]; 2],
+        stm: 0,
+    };
+
+    let mut piece = 0;
+    while piece < NUM_PIECE_KEYS {
+        let mut sq = 0;
+        while sq < NUM_SQUARES as usize {
+            keys.pieces[piece][sq] = next_key(&mut state);
+            sq += 1;
+        }
+        piece += 1;
+    }
+
+    // an empty hand leaves the key at 0 so that it doesn't need special casing
+    let mut side = 0;
+    while side < 2 {
+        let mut piece = 0;
+        while piece < 7 {
+            let mut count = 1;
+            while count < MAX_HAND_COUNT {
+                keys.hands[side][piece][count] = next_key(&mut state);
+                count += 1;
+            }
+            piece += 1;
+        }
+        side += 1;
+    }
+
+    keys.stm = next_key(&mut state);
+    keys
+};